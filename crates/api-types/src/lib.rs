@@ -8,3 +8,117 @@ pub struct LidState {
 pub fn now() -> chrono::DateTime<chrono::Utc> {
     chrono::Utc::now()
 }
+
+/// What smartplug-control knows about the plug, as published on its status socket.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PlugStatus {
+    pub plug: String,
+    /// Whether the plug should be on; unknown until the first lid state arrives
+    pub wanted_on: Option<bool>,
+    /// Whether the plug was confirmed to be in the wanted state
+    pub in_sync: bool,
+    /// The state the plug last reported by itself, for switches that tell
+    pub reported_on: Option<bool>,
+    /// A change waiting out its delay, e.g. to see whether the lid stays closed
+    pub pending: Option<PendingChange>,
+    /// Failed attempts in a row
+    pub failures: u32,
+    /// Set once the failures ran past the retry limit; retrying carries on in the background
+    pub persistent_failure: bool,
+    pub last_error: Option<String>,
+    /// What the plug's power meter last read, for plugs with one
+    pub power_watts: Option<f64>,
+    /// Energy used since local midnight, for plugs with a power meter
+    pub energy_today_kwh: Option<f64>,
+    /// Set while the plug is on but next to nothing draws power from it,
+    /// i.e. the TV did not actually turn on
+    pub no_load: bool,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PendingChange {
+    pub on: bool,
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Socket the player takes commands on, one JSON [`PlayerCommand`] per line.
+pub const PLAYER_CONTROL_SOCKET: &str = "/tmp/run/player-control.sock";
+
+/// Something for the player to do, e.g. because a key was pressed on the TV's remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerCommand {
+    Play,
+    /// Pauses until `Play`, or until the lid is opened again
+    Pause,
+    TogglePause,
+    /// Skips to the next file in the playlist
+    Next,
+    Previous,
+}
+
+/// Socket overseer takes requests on: one JSON [`OverseerRequest`] per connection,
+/// answered with JSON [`OverseerReply`] lines until overseer closes the connection.
+pub const OVERSEER_CONTROL_SOCKET: &str = "/tmp/run/overseer.sock";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverseerRequest {
+    Status,
+    /// Starts a service that was stopped or failed
+    Start {
+        service: String,
+    },
+    /// Stops a service until it is started again
+    Stop {
+        service: String,
+    },
+    Restart {
+        service: String,
+    },
+    /// The service's recent output, then with `follow`, its output as it comes
+    Logs {
+        service: String,
+        follow: bool,
+    },
+    /// Reads the config file again
+    Reload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverseerReply {
+    Status(Vec<ServiceStatus>),
+    /// A line the service printed
+    Log(String),
+    Done,
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    /// How long the current process has been running
+    pub uptime_secs: Option<u64>,
+    /// Times it was started again since overseer started it first
+    pub restarts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    /// Waiting for what it depends on to be ready
+    Pending,
+    /// Running, but not ready yet
+    Starting,
+    Running,
+    Stopping,
+    /// Waiting to be restarted after it exited
+    Waiting,
+    Stopped,
+    /// Crashed too often to be restarted
+    Failed,
+}
@@ -0,0 +1,164 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use api_types::{OverseerReply, OverseerRequest, ServiceStatus};
+use clap::Parser;
+
+/// Controls the services overseer runs.
+#[derive(clap::Parser)]
+struct Args {
+    /// overseer's control socket
+    #[clap(long, default_value = api_types::OVERSEER_CONTROL_SOCKET)]
+    socket: PathBuf,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Shows what every service is doing
+    Status,
+    /// Starts a service that was stopped or failed
+    Start { service: String },
+    /// Stops a service until it is started again
+    Stop { service: String },
+    /// Stops a service and starts it again
+    Restart { service: String },
+    /// Prints what a service printed lately
+    Logs {
+        service: String,
+        /// Keep printing what it prints
+        #[clap(short, long)]
+        follow: bool,
+    },
+    /// Makes overseer read its config file again
+    Reload,
+}
+
+fn main() {
+    let args = Args::parse();
+    let request = match args.command {
+        Command::Status => OverseerRequest::Status,
+        Command::Start { service } => OverseerRequest::Start { service },
+        Command::Stop { service } => OverseerRequest::Stop { service },
+        Command::Restart { service } => OverseerRequest::Restart { service },
+        Command::Logs { service, follow } => OverseerRequest::Logs { service, follow },
+        Command::Reload => OverseerRequest::Reload,
+    };
+
+    let mut stream = UnixStream::connect(&args.socket).unwrap_or_else(|why| {
+        eprintln!(
+            "cannot connect to overseer at {}: {why}",
+            args.socket.display()
+        );
+        std::process::exit(1);
+    });
+    let mut line = serde_json::to_string(&request).expect("failed to serialize request");
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .expect("failed to send request");
+
+    let mut failed = false;
+    let mut stdout = std::io::stdout().lock();
+    for line in BufReader::new(stream).lines() {
+        let line = line.expect("failed to read reply");
+        let written = match serde_json::from_str(&line).expect("invalid reply") {
+            OverseerReply::Status(services) => write!(stdout, "{}", format_status(&services)),
+            OverseerReply::Log(line) => writeln!(stdout, "{line}"),
+            OverseerReply::Done => Ok(()),
+            OverseerReply::Error(why) => {
+                eprintln!("{why}");
+                failed = true;
+                Ok(())
+            }
+        };
+        match written {
+            // Whoever reads the output has seen enough, like `head` does
+            Err(why) if why.kind() == ErrorKind::BrokenPipe => return,
+            written => written.expect("failed to write to stdout"),
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// A table with a line for each service.
+fn format_status(services: &[ServiceStatus]) -> String {
+    let name_width = services
+        .iter()
+        .map(|service| service.name.len())
+        .chain([4])
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:name_width$}  {:8}  {:>7}  {:>8}  RESTARTS\n",
+        "NAME", "STATE", "PID", "UPTIME"
+    );
+    for service in services {
+        let state = serde_json::to_value(service.state).expect("failed to serialize state");
+        table += &format!(
+            "{:name_width$}  {:8}  {:>7}  {:>8}  {}\n",
+            service.name,
+            state.as_str().unwrap_or_default(),
+            service.pid.map(|pid| pid.to_string()).unwrap_or("-".into()),
+            service.uptime_secs.map(format_uptime).unwrap_or("-".into()),
+            service.restarts
+        );
+    }
+    table
+}
+
+/// Like `45s`, `12m 5s`, `3h 12m` or `2d 4h`.
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{minutes}m {}s", secs % 60),
+        (0, _, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use api_types::ServiceState;
+
+    use super::*;
+
+    #[test]
+    fn formats_status() {
+        assert_eq!(format_uptime(45), "45s");
+        assert_eq!(format_uptime(725), "12m 5s");
+        assert_eq!(format_uptime(3 * 3600 + 12 * 60 + 9), "3h 12m");
+        assert_eq!(format_uptime(2 * 86400 + 4 * 3600), "2d 4h");
+
+        let services = [
+            ServiceStatus {
+                name: "lid-publisher".to_string(),
+                state: ServiceState::Running,
+                pid: Some(812),
+                uptime_secs: Some(3600),
+                restarts: 0,
+            },
+            ServiceStatus {
+                name: "player".to_string(),
+                state: ServiceState::Failed,
+                pid: None,
+                uptime_secs: None,
+                restarts: 5,
+            },
+        ];
+        assert_eq!(
+            format_status(&services),
+            "NAME           STATE         PID    UPTIME  RESTARTS\n\
+             lid-publisher  running       812     1h 0m  0\n\
+             player         failed          -         -  5\n"
+        );
+    }
+}
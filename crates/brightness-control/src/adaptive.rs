@@ -0,0 +1,300 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{NaiveTime, Timelike};
+
+/// Where the kernel lists IIO devices, including ambient light sensors.
+pub const IIO_DEVICES_ROOT: &str = "/sys/bus/iio/devices";
+
+/// Something that measures how bright the room is.
+pub trait AmbientSensor: Send {
+    /// Illuminance in lux.
+    fn illuminance(&mut self) -> std::io::Result<f64>;
+}
+
+/// Something that tells the local time of day.
+pub trait Clock: Send {
+    fn now(&self) -> NaiveTime;
+}
+
+/// The system's local time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveTime {
+        chrono::Local::now().time()
+    }
+}
+
+/// An ambient light sensor exposed by the IIO subsystem.
+pub struct IioSensor {
+    dir: PathBuf,
+}
+
+impl IioSensor {
+    /// Finds the first IIO device under `root` that reports illuminance.
+    pub fn discover(root: &Path) -> std::io::Result<Option<Self>> {
+        let mut dirs: Vec<_> = std::fs::read_dir(root)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        dirs.sort();
+        for dir in dirs {
+            if std::fs::exists(dir.join("in_illuminance_input"))?
+                || std::fs::exists(dir.join("in_illuminance_raw"))?
+            {
+                return Ok(Some(Self { dir }));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn read(&self, attribute: &str) -> std::io::Result<Option<f64>> {
+        match std::fs::read_to_string(self.dir.join(attribute)) {
+            Ok(text) => text
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidData, why)),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why),
+        }
+    }
+}
+
+impl AmbientSensor for IioSensor {
+    fn illuminance(&mut self) -> std::io::Result<f64> {
+        // Some drivers report lux directly; others need the raw value scaled
+        if let Some(lux) = self.read("in_illuminance_input")? {
+            return Ok(lux);
+        }
+        let raw = self.read("in_illuminance_raw")?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "sensor has no in_illuminance_raw",
+            )
+        })?;
+        let offset = self.read("in_illuminance_offset")?.unwrap_or(0.0);
+        let scale = self.read("in_illuminance_scale")?.unwrap_or(1.0);
+        Ok((raw + offset) * scale)
+    }
+}
+
+/// Maps illuminance to a brightness fraction on a logarithmic scale,
+/// since perceived room brightness is roughly logarithmic in lux.
+#[derive(Debug, Clone, Copy)]
+pub struct LuxRange {
+    /// At or below this, the darkest level is used.
+    pub dark: f64,
+    /// At or above this, full brightness is used.
+    pub bright: f64,
+}
+
+impl LuxRange {
+    pub fn fraction(&self, lux: f64) -> f64 {
+        let lux = lux.max(0.0);
+        let low = (self.dark.max(0.0) + 1.0).log10();
+        let high = (self.bright.max(self.dark) + 1.0).log10();
+        if high <= low {
+            return 1.0;
+        }
+        (((lux + 1.0).log10() - low) / (high - low)).clamp(0.0, 1.0)
+    }
+}
+
+/// Brightness by time of day: a list of `HH:MM=percent` points,
+/// linearly interpolated and wrapping around midnight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DayCurve {
+    points: Vec<(NaiveTime, f64)>,
+}
+
+impl DayCurve {
+    pub fn fraction(&self, time: NaiveTime) -> f64 {
+        let secs = |t: NaiveTime| t.num_seconds_from_midnight() as f64;
+        let day = 24.0 * 60.0 * 60.0;
+        let now = secs(time);
+        // The last point at or before now (wrapping to yesterday), and the next one after it
+        let prev = self
+            .points
+            .iter()
+            .rposition(|(t, _)| secs(*t) <= now)
+            .unwrap_or(self.points.len() - 1);
+        let next = (prev + 1) % self.points.len();
+        let (prev_time, prev_value) = self.points[prev];
+        let (next_time, next_value) = self.points[next];
+
+        let start = secs(prev_time);
+        let mut span = secs(next_time) - start;
+        if span <= 0.0 {
+            span += day;
+        }
+        let mut elapsed = now - start;
+        if elapsed < 0.0 {
+            elapsed += day;
+        }
+        prev_value + (next_value - prev_value) * (elapsed / span).clamp(0.0, 1.0)
+    }
+}
+
+impl FromStr for DayCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let points = utils::time_of_day::parse_percentages(s)?
+            .into_iter()
+            .map(|(time, percent)| (time, percent as f64 / 100.0))
+            .collect();
+        Ok(Self { points })
+    }
+}
+
+/// Works out the "on" brightness from the ambient light sensor if there is one,
+/// or from the time of day otherwise, smoothed so that it does not pump.
+pub struct Adaptive {
+    sensor: Option<(Box<dyn AmbientSensor>, LuxRange)>,
+    clock: Box<dyn Clock>,
+    curve: DayCurve,
+    /// Weight of a new sample in the moving average, `0.0..=1.0`.
+    smoothing: f64,
+    smoothed: Option<f64>,
+}
+
+impl Adaptive {
+    pub fn new(
+        sensor: Option<(Box<dyn AmbientSensor>, LuxRange)>,
+        clock: Box<dyn Clock>,
+        curve: DayCurve,
+        smoothing: f64,
+    ) -> Self {
+        Self {
+            sensor,
+            clock,
+            curve,
+            smoothing: smoothing.clamp(0.0, 1.0),
+            smoothed: None,
+        }
+    }
+
+    /// Forgets the moving average, so that the next sample is used as is.
+    /// Used when the screen is turned on, since old samples are stale by then.
+    pub fn reset(&mut self) {
+        self.smoothed = None;
+    }
+
+    /// Takes a new sample and returns the smoothed brightness fraction.
+    pub fn sample(&mut self) -> f64 {
+        let target = self.measure();
+        let smoothed = match self.smoothed {
+            Some(previous) => previous + (target - previous) * self.smoothing,
+            None => target,
+        };
+        self.smoothed = Some(smoothed);
+        smoothed
+    }
+
+    fn measure(&mut self) -> f64 {
+        if let Some((sensor, range)) = &mut self.sensor {
+            match sensor.illuminance() {
+                Ok(lux) => return range.fraction(lux),
+                Err(why) => {
+                    eprintln!("failed to read ambient light sensor, using time of day: {why}")
+                }
+            }
+        }
+        self.curve.fraction(self.clock.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct FakeSensor(Arc<Mutex<std::io::Result<f64>>>);
+
+    impl AmbientSensor for FakeSensor {
+        fn illuminance(&mut self) -> std::io::Result<f64> {
+            match &*self.0.lock().unwrap() {
+                Ok(lux) => Ok(*lux),
+                Err(why) => Err(std::io::Error::new(why.kind(), why.to_string())),
+            }
+        }
+    }
+
+    struct FixedClock(NaiveTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> NaiveTime {
+            self.0
+        }
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn day_curve_interpolates_across_midnight() {
+        let curve: DayCurve = "08:00=100,22:00=20".parse().unwrap();
+        assert_eq!(curve.fraction(time("08:00")), 1.0);
+        assert!((curve.fraction(time("15:00")) - 0.6).abs() < 1e-9);
+        assert!((curve.fraction(time("03:00")) - 0.6).abs() < 1e-9);
+        assert!("08:00=101".parse::<DayCurve>().is_err());
+    }
+
+    #[test]
+    fn lux_range_is_logarithmic() {
+        let range = LuxRange {
+            dark: 0.0,
+            bright: 999.0,
+        };
+        assert_eq!(range.fraction(0.0), 0.0);
+        assert!((range.fraction(9.0) - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(range.fraction(5000.0), 1.0);
+    }
+
+    #[test]
+    fn smoothing_converges_and_falls_back_to_clock() {
+        let reading = Arc::new(Mutex::new(Ok(999.0)));
+        let range = LuxRange {
+            dark: 0.0,
+            bright: 999.0,
+        };
+        let mut adaptive = Adaptive::new(
+            Some((Box::new(FakeSensor(reading.clone())), range)),
+            Box::new(FixedClock(time("12:00"))),
+            "00:00=25".parse().unwrap(),
+            0.5,
+        );
+        assert_eq!(adaptive.sample(), 1.0);
+
+        *reading.lock().unwrap() = Ok(0.0);
+        assert_eq!(adaptive.sample(), 0.5);
+        assert_eq!(adaptive.sample(), 0.25);
+
+        *reading.lock().unwrap() = Err(std::io::ErrorKind::TimedOut.into());
+        adaptive.reset();
+        assert_eq!(adaptive.sample(), 0.25);
+    }
+
+    #[test]
+    fn iio_sensor_scales_raw_value() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("iio:device0")).unwrap();
+        let dir = root.path().join("iio:device1");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("in_illuminance_raw"), "200\n").unwrap();
+        std::fs::write(dir.join("in_illuminance_scale"), "0.5\n").unwrap();
+
+        let mut sensor = IioSensor::discover(root.path()).unwrap().unwrap();
+        assert_eq!(sensor.dir(), dir);
+        assert_eq!(sensor.illuminance().unwrap(), 100.0);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(test)]
+pub mod fake;
+pub mod logind;
+pub mod sysfs;
+
+/// Where backlight devices are listed by the kernel.
+pub const SYSFS_BACKLIGHT_ROOT: &str = "/sys/class/backlight";
+
+/// A device whose brightness can be read and set.
+pub trait Backlight: Send {
+    /// Name of the device, for logging.
+    fn name(&self) -> &str;
+
+    /// The highest level accepted by [`Backlight::set_brightness`].
+    fn max_brightness(&self) -> std::io::Result<u32>;
+
+    /// The level the device is currently set to.
+    fn brightness(&self) -> std::io::Result<u32>;
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()>;
+
+    /// How long the device needs between writes. Fades write it less often than
+    /// every step, so that they still take as long as they are meant to.
+    fn min_write_interval(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// How the brightness gets written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Write /sys/class/backlight/*/brightness directly (needs root or udev rules)
+    Sysfs,
+    /// Ask systemd-logind to set the brightness for the current session (no root needed)
+    Logind,
+    /// No built-in backlight; only drive the monitors given with --ddc-bus
+    None,
+}
+
+/// The kernel's classification of a backlight device, from its `type` attribute.
+/// Firmware interfaces are usually preferred over platform ones, which are preferred over raw.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceType {
+    Firmware,
+    Platform,
+    Raw,
+}
+
+impl DeviceType {
+    fn from_sysfs(kind: &str) -> Option<Self> {
+        match kind.trim() {
+            "firmware" => Some(Self::Firmware),
+            "platform" => Some(Self::Platform),
+            "raw" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// Which devices to drive.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Device names (e.g. `intel_backlight`). If not empty, exactly these devices are used.
+    pub names: Vec<String>,
+    /// Device types. If not empty, every device of these types is used.
+    pub types: Vec<DeviceType>,
+}
+
+/// A backlight device found in sysfs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: Option<DeviceType>,
+}
+
+/// Lists the devices under `root` that have a `brightness` file.
+pub fn discover(root: &Path) -> std::io::Result<Vec<DeviceInfo>> {
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !std::fs::exists(path.join("brightness"))? {
+            continue;
+        }
+        let kind = std::fs::read_to_string(path.join("type"))
+            .ok()
+            .and_then(|kind| DeviceType::from_sysfs(&kind));
+        devices.push(DeviceInfo {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            path,
+            kind,
+        });
+    }
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// Picks the devices to drive out of the discovered ones.
+/// With an empty selection, the single most preferred device is picked.
+pub fn select(devices: Vec<DeviceInfo>, selection: &Selection) -> Result<Vec<DeviceInfo>, String> {
+    if !selection.names.is_empty() {
+        let mut picked = Vec::new();
+        for name in &selection.names {
+            let device = devices
+                .iter()
+                .find(|d| &d.name == name)
+                .ok_or_else(|| format!("no backlight device named {name:?}"))?;
+            picked.push(device.clone());
+        }
+        return Ok(picked);
+    }
+
+    if !selection.types.is_empty() {
+        let picked: Vec<_> = devices
+            .into_iter()
+            .filter(|d| d.kind.is_some_and(|k| selection.types.contains(&k)))
+            .collect();
+        if picked.is_empty() {
+            return Err(format!("no backlight device of type {:?}", selection.types));
+        }
+        return Ok(picked);
+    }
+
+    // Devices with an unknown type sort last.
+    devices
+        .into_iter()
+        .min_by_key(|d| d.kind.map_or(u8::MAX, |k| k as u8))
+        .map(|d| vec![d])
+        .ok_or_else(|| "no backlight device found; perhaps not running on laptop?".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_device(root: &Path, name: &str, kind: &str) {
+        let dir = root.join(name);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("brightness"), "10\n").unwrap();
+        std::fs::write(dir.join("max_brightness"), "100\n").unwrap();
+        std::fs::write(dir.join("type"), format!("{kind}\n")).unwrap();
+    }
+
+    fn sysfs() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        make_device(root.path(), "acpi_video0", "firmware");
+        make_device(root.path(), "intel_backlight", "raw");
+        make_device(root.path(), "nv_backlight", "raw");
+        root
+    }
+
+    fn names(devices: &[DeviceInfo]) -> Vec<&str> {
+        devices.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn default_selection_prefers_firmware() {
+        let root = sysfs();
+        let devices = discover(root.path()).unwrap();
+        let picked = select(devices, &Selection::default()).unwrap();
+        assert_eq!(names(&picked), ["acpi_video0"]);
+    }
+
+    #[test]
+    fn select_by_type_returns_all_matches() {
+        let root = sysfs();
+        let devices = discover(root.path()).unwrap();
+        let selection = Selection {
+            names: vec![],
+            types: vec![DeviceType::Raw],
+        };
+        let picked = select(devices, &selection).unwrap();
+        assert_eq!(names(&picked), ["intel_backlight", "nv_backlight"]);
+    }
+
+    #[test]
+    fn select_by_unknown_name_fails() {
+        let root = sysfs();
+        let devices = discover(root.path()).unwrap();
+        let selection = Selection {
+            names: vec!["amdgpu_bl0".into()],
+            types: vec![],
+        };
+        assert!(select(devices, &selection).is_err());
+    }
+}
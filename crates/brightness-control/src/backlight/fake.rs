@@ -0,0 +1,91 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::Backlight;
+
+/// An in-memory device. Clones share the same state,
+/// so a test can keep one to inspect what the controller wrote.
+#[derive(Clone)]
+pub struct FakeBacklight {
+    name: String,
+    max: u32,
+    write_interval: Duration,
+    writes: Arc<Mutex<Vec<u32>>>,
+    level: Arc<Mutex<u32>>,
+    refusing: Arc<Mutex<bool>>,
+}
+
+impl FakeBacklight {
+    pub fn new(name: &str, max: u32, level: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            max,
+            write_interval: Duration::ZERO,
+            writes: Arc::default(),
+            level: Arc::new(Mutex::new(level)),
+            refusing: Arc::default(),
+        }
+    }
+
+    /// Makes it a device that needs `interval` between writes, like a DDC/CI monitor.
+    pub fn with_write_interval(mut self, interval: Duration) -> Self {
+        self.write_interval = interval;
+        self
+    }
+
+    /// Every level written so far, in order.
+    pub fn writes(&self) -> Vec<u32> {
+        self.writes.lock().expect("failed to lock writes").clone()
+    }
+
+    /// Makes writes fail until called again with false, like a monitor that just woke up.
+    pub fn refuse_writes(&self, refuse: bool) {
+        *self.refusing.lock().expect("failed to lock refusing") = refuse;
+    }
+
+    /// Changes the level behind the controller's back, like a brightness key would.
+    pub fn set_external(&self, level: u32) {
+        *self.level.lock().expect("failed to lock level") = level;
+    }
+}
+
+impl Backlight for FakeBacklight {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn max_brightness(&self) -> std::io::Result<u32> {
+        Ok(self.max)
+    }
+
+    fn brightness(&self) -> std::io::Result<u32> {
+        Ok(*self.level.lock().expect("failed to lock level"))
+    }
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()> {
+        if *self.refusing.lock().expect("failed to lock refusing") {
+            return Err(std::io::Error::other(format!(
+                "{} refused the write",
+                self.name
+            )));
+        }
+        if level > self.max {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("level {level} is above max {}", self.max),
+            ));
+        }
+        *self.level.lock().expect("failed to lock level") = level;
+        self.writes
+            .lock()
+            .expect("failed to lock writes")
+            .push(level);
+        Ok(())
+    }
+
+    fn min_write_interval(&self) -> Duration {
+        self.write_interval
+    }
+}
@@ -0,0 +1,61 @@
+use zbus::{blocking::Connection, zvariant::OwnedObjectPath};
+
+use super::{Backlight, DeviceInfo, sysfs::SysfsBacklight};
+
+/// Sets the brightness through systemd-logind's `Session.SetBrightness`,
+/// which is allowed for the user owning the active session without root.
+/// The current level is still read from sysfs, which is world-readable.
+pub struct LogindBacklight {
+    sysfs: SysfsBacklight,
+    connection: Connection,
+    session: OwnedObjectPath,
+}
+
+impl LogindBacklight {
+    /// `session` is a logind session ID, or `auto` for the session of this process.
+    pub fn new(device: &DeviceInfo, session: &str) -> std::io::Result<Self> {
+        let connection = Connection::system().map_err(std::io::Error::other)?;
+        let session: OwnedObjectPath = connection
+            .call_method(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1",
+                Some("org.freedesktop.login1.Manager"),
+                "GetSession",
+                &(session,),
+            )
+            .and_then(|reply| reply.body().deserialize())
+            .map_err(std::io::Error::other)?;
+        Ok(Self {
+            sysfs: SysfsBacklight::new(device),
+            connection,
+            session,
+        })
+    }
+}
+
+impl Backlight for LogindBacklight {
+    fn name(&self) -> &str {
+        self.sysfs.name()
+    }
+
+    fn max_brightness(&self) -> std::io::Result<u32> {
+        self.sysfs.max_brightness()
+    }
+
+    fn brightness(&self) -> std::io::Result<u32> {
+        self.sysfs.brightness()
+    }
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()> {
+        self.connection
+            .call_method(
+                Some("org.freedesktop.login1"),
+                &self.session,
+                Some("org.freedesktop.login1.Session"),
+                "SetBrightness",
+                &("backlight", self.sysfs.name(), level),
+            )
+            .map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::{Path, PathBuf};
+
+use super::{Backlight, DeviceInfo};
+
+/// Writes the device's `brightness` attribute directly.
+pub struct SysfsBacklight {
+    name: String,
+    dir: PathBuf,
+}
+
+impl SysfsBacklight {
+    pub fn new(device: &DeviceInfo) -> Self {
+        Self {
+            name: device.name.clone(),
+            dir: device.path.clone(),
+        }
+    }
+}
+
+impl Backlight for SysfsBacklight {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn max_brightness(&self) -> std::io::Result<u32> {
+        read_level(&self.dir.join("max_brightness"))
+    }
+
+    fn brightness(&self) -> std::io::Result<u32> {
+        read_level(&self.dir.join("brightness"))
+    }
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()> {
+        std::fs::write(self.dir.join("brightness"), level.to_string())
+    }
+}
+
+pub fn read_level(path: &Path) -> std::io::Result<u32> {
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidData, why))
+}
@@ -0,0 +1,327 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{backlight::Backlight, power::DisplayPower};
+
+/// I2C address monitors answer DDC/CI on.
+const DDC_ADDRESS: u8 = 0x37;
+/// `I2C_SLAVE` from linux/i2c-dev.h: sets the address for following reads and writes.
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+/// Source address byte of messages from the host.
+const HOST_ADDRESS: u8 = 0x51;
+/// Starting value of the checksum for messages to the display (its address, shifted for writing).
+const DISPLAY_CHECKSUM_SEED: u8 = DDC_ADDRESS << 1;
+/// Starting value of the checksum for replies from the display.
+const REPLY_CHECKSUM_SEED: u8 = 0x50;
+/// Set in the length byte of every message.
+const LENGTH_FLAG: u8 = 0x80;
+
+const GET_VCP_REQUEST: u8 = 0x01;
+const GET_VCP_REPLY: u8 = 0x02;
+const SET_VCP_REQUEST: u8 = 0x03;
+
+/// VCP code for the backlight/luminance level.
+pub const VCP_LUMINANCE: u8 = 0x10;
+/// VCP code for the display's power mode.
+pub const VCP_POWER_MODE: u8 = 0xD6;
+const POWER_MODE_ON: u16 = 0x01;
+const POWER_MODE_OFF: u16 = 0x04;
+
+/// The DDC/CI spec asks hosts to wait this long between commands.
+const COMMAND_DELAY: Duration = Duration::from_millis(50);
+/// How long the display may take to prepare a reply.
+const REPLY_DELAY: Duration = Duration::from_millis(40);
+/// Displays answer with a null message when busy, so reads are retried.
+const READ_ATTEMPTS: usize = 3;
+
+/// A DDC/CI connection over something that behaves like an i2c-dev device file.
+pub struct DdcCi<T> {
+    device: T,
+    delay: Duration,
+    last_command: Option<Instant>,
+}
+
+impl DdcCi<File> {
+    /// Opens an i2c bus (e.g. `/dev/i2c-5`) and addresses the monitor on it.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::options().read(true).write(true).open(path)?;
+        let result =
+            unsafe { libc::ioctl(file.as_raw_fd(), I2C_SLAVE, DDC_ADDRESS as libc::c_ulong) };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self::new(file))
+    }
+}
+
+impl<T: Read + Write> DdcCi<T> {
+    pub fn new(device: T) -> Self {
+        Self {
+            device,
+            delay: COMMAND_DELAY,
+            last_command: None,
+        }
+    }
+
+    /// Reads a VCP feature, returning its current and maximum values.
+    pub fn get_vcp(&mut self, code: u8) -> std::io::Result<(u16, u16)> {
+        let mut last_error = None;
+        for _ in 0..READ_ATTEMPTS {
+            self.send(&[GET_VCP_REQUEST, code])?;
+            std::thread::sleep(REPLY_DELAY);
+            let mut reply = [0u8; 11];
+            self.device.read_exact(&mut reply)?;
+            match parse_get_vcp_reply(&reply, code) {
+                Ok(values) => return Ok(values),
+                Err(why) => last_error = Some(why),
+            }
+        }
+        Err(last_error.expect("at least one attempt was made"))
+    }
+
+    pub fn set_vcp(&mut self, code: u8, value: u16) -> std::io::Result<()> {
+        let [high, low] = value.to_be_bytes();
+        self.send(&[SET_VCP_REQUEST, code, high, low])
+    }
+
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        if let Some(last) = self.last_command {
+            let elapsed = last.elapsed();
+            if elapsed < self.delay {
+                std::thread::sleep(self.delay - elapsed);
+            }
+        }
+        let mut message = Vec::with_capacity(payload.len() + 3);
+        message.push(HOST_ADDRESS);
+        message.push(LENGTH_FLAG | payload.len() as u8);
+        message.extend_from_slice(payload);
+        message.push(checksum(DISPLAY_CHECKSUM_SEED, &message));
+        self.device.write_all(&message)?;
+        self.last_command = Some(Instant::now());
+        Ok(())
+    }
+}
+
+fn checksum(seed: u8, bytes: &[u8]) -> u8 {
+    bytes.iter().fold(seed, |acc, b| acc ^ b)
+}
+
+fn parse_get_vcp_reply(reply: &[u8; 11], code: u8) -> std::io::Result<(u16, u16)> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    if reply[1] == LENGTH_FLAG {
+        return Err(invalid("display is busy (null message)".to_string()));
+    }
+    if checksum(REPLY_CHECKSUM_SEED, &reply[..10]) != reply[10] {
+        return Err(invalid(format!("bad checksum in reply {reply:02x?}")));
+    }
+    if reply[2] != GET_VCP_REPLY || reply[4] != code {
+        return Err(invalid(format!("unexpected reply {reply:02x?}")));
+    }
+    if reply[3] != 0 {
+        return Err(invalid(format!("display does not support VCP {code:#04x}")));
+    }
+    let max = u16::from_be_bytes([reply[6], reply[7]]);
+    let current = u16::from_be_bytes([reply[8], reply[9]]);
+    Ok((current, max))
+}
+
+/// A monitor controlled over DDC/CI. Clones share the connection,
+/// so the same monitor can be used as both a backlight and a power switch.
+pub struct DdcMonitor<T> {
+    name: String,
+    ddc: Arc<Mutex<DdcCi<T>>>,
+}
+
+impl<T> Clone for DdcMonitor<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            ddc: self.ddc.clone(),
+        }
+    }
+}
+
+impl<T: Read + Write> DdcMonitor<T> {
+    pub fn new(name: String, ddc: DdcCi<T>) -> Self {
+        Self {
+            name,
+            ddc: Arc::new(Mutex::new(ddc)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DdcCi<T>> {
+        self.ddc.lock().expect("failed to lock DDC/CI connection")
+    }
+}
+
+impl<T: Read + Write + Send> Backlight for DdcMonitor<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn max_brightness(&self) -> std::io::Result<u32> {
+        Ok(self.lock().get_vcp(VCP_LUMINANCE)?.1 as u32)
+    }
+
+    fn brightness(&self) -> std::io::Result<u32> {
+        Ok(self.lock().get_vcp(VCP_LUMINANCE)?.0 as u32)
+    }
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()> {
+        let level = u16::try_from(level)
+            .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidInput, why))?;
+        self.lock().set_vcp(VCP_LUMINANCE, level)
+    }
+
+    fn min_write_interval(&self) -> Duration {
+        self.lock().delay
+    }
+}
+
+impl<T: Read + Write + Send> DisplayPower for DdcMonitor<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        let mode = if on { POWER_MODE_ON } else { POWER_MODE_OFF };
+        self.lock().set_vcp(VCP_POWER_MODE, mode)
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Stands in for `/dev/i2c-*` with a monitor on the other end:
+    /// requests written to it are answered on the next read.
+    #[derive(Clone, Default)]
+    pub struct FakeI2c {
+        /// VCP code to (current, max)
+        pub features: Arc<Mutex<HashMap<u8, (u16, u16)>>>,
+        /// The next read returns this many null messages before the real reply.
+        pub busy_replies: Arc<Mutex<usize>>,
+        reply: Vec<u8>,
+    }
+
+    impl FakeI2c {
+        pub fn with_feature(self, code: u8, current: u16, max: u16) -> Self {
+            self.features.lock().unwrap().insert(code, (current, max));
+            self
+        }
+
+        pub fn value(&self, code: u8) -> Option<u16> {
+            self.features.lock().unwrap().get(&code).map(|v| v.0)
+        }
+    }
+
+    impl Write for FakeI2c {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            assert_eq!(buf[0], HOST_ADDRESS);
+            assert_eq!(buf[1], LENGTH_FLAG | (buf.len() - 3) as u8);
+            let (message, sum) = buf.split_at(buf.len() - 1);
+            assert_eq!(checksum(DISPLAY_CHECKSUM_SEED, message), sum[0]);
+
+            let mut features = self.features.lock().unwrap();
+            match buf[2] {
+                GET_VCP_REQUEST => {
+                    let code = buf[3];
+                    let (result, (current, max)) = match features.get(&code) {
+                        Some(values) => (0, *values),
+                        None => (1, (0, 0)),
+                    };
+                    let mut reply = vec![DISPLAY_CHECKSUM_SEED, LENGTH_FLAG | 8, GET_VCP_REPLY];
+                    reply.extend([result, code, 0]);
+                    reply.extend(max.to_be_bytes());
+                    reply.extend(current.to_be_bytes());
+                    let mut busy = self.busy_replies.lock().unwrap();
+                    if *busy > 0 {
+                        *busy -= 1;
+                        reply = vec![DISPLAY_CHECKSUM_SEED, LENGTH_FLAG, 0xbe];
+                        reply.resize(10, 0);
+                    }
+                    reply.push(checksum(REPLY_CHECKSUM_SEED, &reply));
+                    self.reply = reply;
+                }
+                SET_VCP_REQUEST => {
+                    let value = u16::from_be_bytes([buf[4], buf[5]]);
+                    features.entry(buf[3]).or_insert((0, 0xffff)).0 = value;
+                }
+                other => panic!("unexpected opcode {other:#04x}"),
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for FakeI2c {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.reply.len());
+            buf[..n].copy_from_slice(&self.reply[..n]);
+            self.reply.drain(..n);
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake::FakeI2c, *};
+
+    fn monitor(i2c: &FakeI2c) -> DdcMonitor<FakeI2c> {
+        let mut ddc = DdcCi::new(i2c.clone());
+        ddc.delay = Duration::ZERO;
+        DdcMonitor::new("ddc:test".to_string(), ddc)
+    }
+
+    #[test]
+    fn reads_and_writes_luminance() {
+        let i2c = FakeI2c::default().with_feature(VCP_LUMINANCE, 30, 100);
+        let mut monitor = monitor(&i2c);
+        assert_eq!(monitor.max_brightness().unwrap(), 100);
+        assert_eq!(monitor.brightness().unwrap(), 30);
+
+        monitor.set_brightness(75).unwrap();
+        assert_eq!(i2c.value(VCP_LUMINANCE), Some(75));
+        assert_eq!(monitor.brightness().unwrap(), 75);
+    }
+
+    #[test]
+    fn retries_when_display_is_busy() {
+        let i2c = FakeI2c::default().with_feature(VCP_LUMINANCE, 30, 100);
+        *i2c.busy_replies.lock().unwrap() = 2;
+        assert_eq!(monitor(&i2c).brightness().unwrap(), 30);
+    }
+
+    #[test]
+    fn unsupported_feature_is_an_error() {
+        let i2c = FakeI2c::default();
+        assert!(monitor(&i2c).max_brightness().is_err());
+    }
+
+    #[test]
+    fn power_mode_switches() {
+        let i2c = FakeI2c::default().with_feature(VCP_POWER_MODE, POWER_MODE_ON, 5);
+        let mut monitor = monitor(&i2c);
+        monitor.set_power(false).unwrap();
+        assert_eq!(i2c.value(VCP_POWER_MODE), Some(POWER_MODE_OFF));
+        monitor.set_power(true).unwrap();
+        assert_eq!(i2c.value(VCP_POWER_MODE), Some(POWER_MODE_ON));
+    }
+}
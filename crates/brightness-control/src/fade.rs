@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+/// Gamma used by the perceptual curve: brightness is interpolated in a space
+/// where equal steps look roughly equally large to a human eye.
+const PERCEPTUAL_GAMMA: f64 = 2.2;
+
+/// The shape of a brightness transition.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    /// Raw brightness values change at a constant rate.
+    Linear,
+    /// Perceived brightness changes at a constant rate (gamma 2.2).
+    Perceptual,
+}
+
+/// A single transition from one brightness level to another.
+#[derive(Clone, Copy, Debug)]
+pub struct Fade {
+    pub from: u32,
+    pub to: u32,
+    pub max: u32,
+    pub duration: Duration,
+    pub curve: Curve,
+}
+
+impl Fade {
+    /// Brightness level at the given progress (`0.0..=1.0`) of the fade.
+    pub fn level_at(&self, progress: f64) -> u32 {
+        let progress = progress.clamp(0.0, 1.0);
+        if self.max == 0 {
+            return 0;
+        }
+        let max = self.max as f64;
+        let from = self.from.min(self.max) as f64 / max;
+        let to = self.to.min(self.max) as f64 / max;
+        let fraction = match self.curve {
+            Curve::Linear => from + (to - from) * progress,
+            Curve::Perceptual => {
+                let from = from.powf(1.0 / PERCEPTUAL_GAMMA);
+                let to = to.powf(1.0 / PERCEPTUAL_GAMMA);
+                (from + (to - from) * progress).powf(PERCEPTUAL_GAMMA)
+            }
+        };
+        (fraction * max).round() as u32
+    }
+
+    /// The levels to write, one per `step`, ending exactly at `to`.
+    pub fn steps(&self, step: Duration) -> impl Iterator<Item = u32> {
+        let count = if step.is_zero() {
+            1
+        } else {
+            (self.duration.as_secs_f64() / step.as_secs_f64())
+                .ceil()
+                .max(1.0) as u32
+        };
+        let fade = *self;
+        (1..=count).map(move |i| fade.level_at(i as f64 / count as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fade(from: u32, to: u32, curve: Curve) -> Fade {
+        Fade {
+            from,
+            to,
+            max: 1000,
+            duration: Duration::from_millis(200),
+            curve,
+        }
+    }
+
+    #[test]
+    fn starts_and_ends_at_the_endpoints() {
+        for curve in [Curve::Linear, Curve::Perceptual] {
+            for (from, to) in [(0, 1000), (1000, 0), (250, 700)] {
+                let fade = fade(from, to, curve);
+                assert_eq!(fade.level_at(0.0), from);
+                assert_eq!(fade.level_at(1.0), to);
+                // Progress outside the fade is clamped
+                assert_eq!(fade.level_at(-1.0), from);
+                assert_eq!(fade.level_at(2.0), to);
+                assert_eq!(fade.steps(Duration::from_millis(20)).last(), Some(to));
+            }
+        }
+        assert_eq!(
+            fade(0, 1000, Curve::Linear).steps(Duration::ZERO).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn moves_monotonically() {
+        for curve in [Curve::Linear, Curve::Perceptual] {
+            let up: Vec<_> = fade(0, 1000, curve)
+                .steps(Duration::from_millis(20))
+                .collect();
+            assert_eq!(up.len(), 10);
+            assert!(up.windows(2).all(|w| w[0] <= w[1]));
+            let down: Vec<_> = fade(1000, 0, curve)
+                .steps(Duration::from_millis(20))
+                .collect();
+            assert!(down.windows(2).all(|w| w[0] >= w[1]));
+        }
+    }
+
+    #[test]
+    fn perceptual_curve_spends_longer_in_the_dark() {
+        let linear = fade(0, 1000, Curve::Linear);
+        let perceptual = fade(0, 1000, Curve::Perceptual);
+        assert_eq!(linear.level_at(0.5), 500);
+        // Halfway is 0.5^2.2 of the raw range
+        assert_eq!(perceptual.level_at(0.5), 218);
+        for progress in [0.1, 0.25, 0.5, 0.75, 0.9] {
+            assert!(perceptual.level_at(progress) < linear.level_at(progress));
+        }
+    }
+}
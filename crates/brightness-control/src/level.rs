@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use utils::state_file;
+
+/// Bounds for the level restored when the backlight is turned back on,
+/// as percentages of the device's maximum.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub floor_percent: u8,
+    pub ceiling_percent: u8,
+}
+
+impl Limits {
+    pub fn clamp(&self, level: u32, max: u32) -> u32 {
+        let floor = percent_of(max, self.floor_percent);
+        let ceiling = percent_of(max, self.ceiling_percent).max(floor);
+        level.clamp(floor, ceiling)
+    }
+}
+
+fn percent_of(max: u32, percent: u8) -> u32 {
+    (max as u64 * percent.min(100) as u64).div_ceil(100) as u32
+}
+
+/// The last non-zero level of each device before it was blanked,
+/// optionally persisted to a JSON file so that it survives restarts.
+pub struct LevelStore {
+    path: Option<PathBuf>,
+    levels: BTreeMap<String, u32>,
+}
+
+impl LevelStore {
+    /// Loads the saved levels from `path`. A missing or unreadable file starts empty.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let levels = match &path {
+            Some(path) => {
+                state_file::load_or_default(path, "saved levels", |message| eprintln!("{message}"))
+            }
+            None => BTreeMap::new(),
+        };
+        Self { path, levels }
+    }
+
+    pub fn get(&self, device: &str) -> Option<u32> {
+        self.levels.get(device).copied()
+    }
+
+    /// Records the level of a device that is about to be blanked.
+    /// Zero levels are ignored, since restoring to zero would leave the screen dark.
+    pub fn remember(&mut self, device: &str, level: u32) -> std::io::Result<()> {
+        if level == 0 || self.get(device) == Some(level) {
+            return Ok(());
+        }
+        self.levels.insert(device.to_string(), level);
+        match &self.path {
+            Some(path) => state_file::save(path, &self.levels),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_applies_floor_and_ceiling() {
+        let limits = Limits {
+            floor_percent: 10,
+            ceiling_percent: 80,
+        };
+        assert_eq!(limits.clamp(0, 1000), 100);
+        assert_eq!(limits.clamp(500, 1000), 500);
+        assert_eq!(limits.clamp(1000, 1000), 800);
+    }
+
+    #[test]
+    fn levels_survive_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("levels.json");
+
+        let mut store = LevelStore::load(Some(path.clone()));
+        store.remember("intel_backlight", 412).unwrap();
+        store.remember("intel_backlight", 0).unwrap();
+
+        let store = LevelStore::load(Some(path));
+        assert_eq!(store.get("intel_backlight"), Some(412));
+        assert_eq!(store.get("acpi_video0"), None);
+    }
+}
@@ -1,59 +1,762 @@
-use std::time::Duration;
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
 
-fn main() {
-    let mut backlight = None;
-    // find the directory at /sys/class/backlight that contains brightness
-
-    let backlight_dir = std::fs::read_dir("/sys/class/backlight").expect("failed to read dir");
-    for i in backlight_dir {
-        let i = i.expect("failed to read dir entry");
-        let path = i.path().join("brightness");
-        if std::fs::exists(&path).expect("failed to check existence of brightness file") {
-            backlight = Some(path);
-            break;
-        } else {
-            continue;
-        }
+use adaptive::{Adaptive, DayCurve, LuxRange};
+use api_types::LidState;
+use backlight::{Backend, Backlight, DeviceType, Selection};
+use clap::Parser;
+use fade::{Curve, Fade};
+use level::{LevelStore, Limits};
+use power::{BlankMode, DisplayPower, DryRun, PowerBackend};
+
+mod adaptive;
+mod backlight;
+mod ddc;
+mod fade;
+mod level;
+mod power;
+
+/// Time between two writes while fading.
+const FADE_STEP: Duration = Duration::from_millis(20);
+
+/// After a fade completes, the final level is written this many more times,
+/// because the firmware sometimes resets the backlight right after a lid event.
+const SETTLE_WRITES: u32 = 2;
+const SETTLE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(clap::Parser)]
+struct Args {
+    /// How long it takes to turn the backlight up, in milliseconds
+    #[clap(long, default_value = "1000")]
+    fade_in_ms: u64,
+
+    /// How long it takes to turn the backlight down, in milliseconds
+    #[clap(long, default_value = "500")]
+    fade_out_ms: u64,
+
+    /// Shape of the transition when turning the backlight up
+    #[clap(long, value_enum, default_value = "perceptual")]
+    fade_in_curve: Curve,
+
+    /// Shape of the transition when turning the backlight down
+    #[clap(long, value_enum, default_value = "perceptual")]
+    fade_out_curve: Curve,
+
+    /// How to write the brightness
+    #[clap(long, value_enum, default_value = "sysfs")]
+    backend: Backend,
+
+    /// logind session to set the brightness through, for the logind backend.
+    /// `auto` means the session this process belongs to.
+    #[clap(long, env = "XDG_SESSION_ID", default_value = "auto")]
+    logind_session: String,
+
+    /// Name of a device in /sys/class/backlight to drive; can be repeated.
+    #[clap(long = "device")]
+    devices: Vec<String>,
+
+    /// Drive every device of this type; can be repeated.
+    /// If neither --device nor --device-type is given,
+    /// the single most preferred device (firmware > platform > raw) is driven.
+    #[clap(long = "device-type", value_enum)]
+    device_types: Vec<DeviceType>,
+
+    /// Directory to look for backlight devices in
+    #[clap(long, default_value = backlight::SYSFS_BACKLIGHT_ROOT)]
+    sysfs_root: PathBuf,
+
+    /// i2c bus of an external monitor to control over DDC/CI (e.g. /dev/i2c-5); can be repeated.
+    /// Monitors are driven alongside the backlight devices.
+    #[clap(long = "ddc-bus")]
+    ddc_buses: Vec<PathBuf>,
+
+    /// Also switch DDC/CI monitors' power mode when blanking by display power
+    #[clap(long)]
+    ddc_power: bool,
+
+    /// How to blank the screen: by backlight, by display power, or both.
+    /// Defaults to both if any display power switch is configured, and backlight otherwise.
+    #[clap(long, value_enum)]
+    blank: Option<BlankMode>,
+
+    /// Switch displays off through this backend when blanking by display power; can be repeated
+    #[clap(long = "display-power", value_enum)]
+    display_power: Vec<PowerBackend>,
+
+    /// DRM card to switch connectors of, for the drm display power backend
+    #[clap(long, default_value = "/dev/dri/card0")]
+    drm_card: PathBuf,
+
+    /// DRM connector (e.g. HDMI-A-1) to switch; can be repeated. Defaults to all connected ones.
+    #[clap(long = "drm-connector")]
+    drm_connectors: Vec<String>,
+
+    /// Read the devices, but only log what would be written to them
+    #[clap(long)]
+    dry_run: bool,
+
+    /// File to save the brightness levels to restore in, so they survive restarts
+    #[clap(long)]
+    state_file: Option<PathBuf>,
+
+    /// Never restore a level below this percentage of the maximum
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u8).range(0..=100))]
+    floor_percent: u8,
+
+    /// Never restore a level above this percentage of the maximum
+    #[clap(long, default_value = "100", value_parser = clap::value_parser!(u8).range(0..=100))]
+    ceiling_percent: u8,
+
+    /// Instead of restoring the last level, follow the ambient light sensor,
+    /// or the time of day if there is no sensor. Floor and ceiling still apply.
+    #[clap(long)]
+    adaptive: bool,
+
+    /// Directory to look for an IIO ambient light sensor in
+    #[clap(long, default_value = adaptive::IIO_DEVICES_ROOT)]
+    iio_root: PathBuf,
+
+    /// Illuminance (lux) at and below which the darkest level is used
+    #[clap(long, default_value = "5")]
+    dark_lux: f64,
+
+    /// Illuminance (lux) at and above which full brightness is used
+    #[clap(long, default_value = "1000")]
+    bright_lux: f64,
+
+    /// Brightness by local time when there is no light sensor,
+    /// as comma-separated HH:MM=percent points
+    #[clap(long, default_value = "07:00=60,12:00=100,19:00=80,22:00=30")]
+    day_curve: DayCurve,
+
+    /// How often to re-check the ambient light while the screen is on, in seconds
+    #[clap(long, default_value = "5")]
+    adaptive_interval_secs: u64,
+
+    /// Weight of each new sample in the moving average, between 0 and 1;
+    /// lower is smoother but slower to react
+    #[clap(long, default_value = "0.3")]
+    adaptive_smoothing: f64,
+
+    /// Only adjust when the level would change by at least this percentage of the maximum
+    #[clap(long, default_value = "3")]
+    adaptive_threshold_percent: u8,
+
+    /// How long an adjustment fade takes, in milliseconds
+    #[clap(long, default_value = "2000")]
+    adaptive_fade_ms: u64,
+}
+
+/// Decides which level each output is turned on to.
+struct Targets {
+    store: LevelStore,
+    limits: Limits,
+    adaptive: Option<Adaptive>,
+}
+
+impl Targets {
+    /// Takes one adaptive sample for a planning pass, so that every output follows
+    /// the same reading and the moving average advances once per pass.
+    fn sample(&mut self) -> Option<f64> {
+        self.adaptive.as_mut().map(Adaptive::sample)
     }
 
-    let Some(backlight) = backlight else {
-        panic!("no backlight file found in /sys/class/backlight; perhaps not running on laptop?");
-    };
+    /// `adaptive` is this pass's [`Targets::sample`].
+    fn on_level(&self, output: &Output, adaptive: Option<f64>) -> u32 {
+        let level = match adaptive {
+            Some(fraction) => (fraction * output.max as f64).round() as u32,
+            None => self.store.get(output.name()).unwrap_or(output.max),
+        };
+        self.limits.clamp(level, output.max)
+    }
+}
+
+/// A device being driven, along with the level it was last set to.
+struct Output {
+    backlight: Box<dyn Backlight>,
+    max: u32,
+    current: u32,
+    /// Whether the last fade ran to completion. While settled,
+    /// the device's level may be changed by hand, so it is re-read before fading.
+    settled: bool,
+}
+
+impl Output {
+    fn new(mut backlight: Box<dyn Backlight>) -> std::io::Result<Self> {
+        let max = backlight.max_brightness()?;
+        let current = backlight.brightness()?;
+        // Try writing the existing level back, so missing permissions show up immediately
+        backlight.set_brightness(current).map_err(|why| {
+            std::io::Error::new(
+                why.kind(),
+                format!(
+                    "failed to write brightness -- consider running this program as root or using --backend logind: {why}"
+                ),
+            )
+        })?;
+        println!(
+            "using backlight {} (current {current}, max {max})",
+            backlight.name()
+        );
+        Ok(Self {
+            backlight,
+            max,
+            current,
+            settled: true,
+        })
+    }
+
+    fn name(&self) -> &str {
+        self.backlight.name()
+    }
+
+    /// Sets the level, or unsettles the output if the device refuses it,
+    /// as DDC/CI monitors do for a moment after waking up.
+    fn write(&mut self, level: u32) {
+        match self.backlight.set_brightness(level) {
+            Ok(()) => self.current = level,
+            Err(why) => {
+                // Once per fade, rather than for every step
+                if self.settled {
+                    eprintln!("failed to write brightness of {}: {why}", self.name());
+                }
+                self.settled = false;
+            }
+        }
+    }
+}
 
-    // Try to read and then write to the file
-    let existing_brightness =
-        std::fs::read_to_string(&backlight).expect("failed to read brightness");
-    std::fs::write(&backlight, existing_brightness)
-        .expect("failed to write brightness -- consider running this program as root");
-    let max_brightness = std::fs::read_to_string(&backlight.with_file_name("max_brightness"))
-        .expect("failed to read brightness");
+fn main() {
+    let args = Args::parse();
+    let mut outputs = open_outputs(&args);
+    let mut displays = open_displays(&args);
+    open_ddc_monitors(&args, &mut outputs, &mut displays);
+    if outputs.is_empty() && displays.is_empty() {
+        panic!("nothing to control: use a backlight backend other than none, or --ddc-bus");
+    }
+    let blank = args.blank.unwrap_or(if displays.is_empty() {
+        BlankMode::Backlight
+    } else {
+        BlankMode::Both
+    });
+    if blank.uses_power() && displays.is_empty() {
+        panic!(
+            "--blank {blank:?} needs a display power switch: use --display-power or --ddc-power"
+        );
+    }
+    println!("blanking by {blank:?}");
 
     let lid_stream =
         lid_subscriber::LidSubscriber::new().expect("failed to connect to lid status socket");
 
-    for event in lid_stream {
-        if event.lid_open {
+    // Events are read on a separate thread, so that a new event can interrupt a running fade.
+    let (tx, events) = mpsc::channel();
+    std::thread::spawn(move || {
+        for event in lid_stream {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut targets = Targets {
+        store: LevelStore::load(args.state_file.clone()),
+        limits: Limits {
+            floor_percent: args.floor_percent,
+            ceiling_percent: args.ceiling_percent,
+        },
+        adaptive: args.adaptive.then(|| open_adaptive(&args)),
+    };
+
+    let mut screen_on = false;
+    let mut next_event = events.recv().ok();
+    loop {
+        let fades = if let Some(event) = next_event.take() {
+            screen_on = !event.lid_open;
+            if let Some(adaptive) = &mut targets.adaptive {
+                adaptive.reset();
+            }
             println!(
-                "lid is open at {}; setting brightness to 0",
-                event.changed_at
+                "lid is {} at {}",
+                if event.lid_open { "open" } else { "closed" },
+                event.changed_at,
             );
-            for i in 0..3 {
-                println!("write {}/3", i + 1);
-                std::fs::write(&backlight, "0")
-                    .expect("failed to write brightness -- consider running this program as root");
-                std::thread::sleep(Duration::from_millis(250));
+            if screen_on && blank.uses_power() {
+                power::set_all(&mut displays, true);
+            }
+            if blank.uses_backlight() {
+                let fades = plan_fades(&args, &mut outputs, event.lid_open, &mut targets);
+                println!(
+                    "fading brightness to {:?}",
+                    fades.iter().map(|f| f.to).collect::<Vec<_>>()
+                );
+                fades
+            } else {
+                Vec::new()
+            }
+        } else if screen_on && targets.adaptive.is_some() {
+            match events.recv_timeout(Duration::from_secs(args.adaptive_interval_secs)) {
+                Ok(event) => {
+                    next_event = Some(event);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            let fades = plan_adjustments(&args, &mut outputs, &mut targets);
+            if fades.is_empty() {
+                continue;
             }
-        } else {
             println!(
-                "lid is closed at {}; setting brightness to {max_brightness}",
-                event.changed_at
+                "ambient light changed; adjusting brightness to {:?}",
+                fades.iter().map(|f| f.to).collect::<Vec<_>>()
+            );
+            fades
+        } else {
+            match events.recv() {
+                Ok(event) => {
+                    next_event = Some(event);
+                    continue;
+                }
+                Err(_) => break,
+            }
+        };
+
+        if !fades.is_empty() {
+            // A write that fails during the fade unsettles its output again
+            for output in outputs.iter_mut() {
+                output.settled = true;
+            }
+            next_event = run_fade(&mut outputs, &fades, &events);
+            if next_event.is_some() {
+                for output in outputs.iter_mut() {
+                    output.settled = false;
+                }
+            }
+        }
+        if !screen_on && next_event.is_none() && blank.uses_power() {
+            power::set_all(&mut displays, false);
+        }
+    }
+}
+
+fn open_adaptive(args: &Args) -> Adaptive {
+    let sensor = match adaptive::IioSensor::discover(&args.iio_root) {
+        Ok(Some(sensor)) => {
+            println!("using ambient light sensor {}", sensor.dir().display());
+            Some(sensor)
+        }
+        Ok(None) => None,
+        Err(why) => {
+            eprintln!(
+                "failed to look for light sensors in {}: {why}",
+                args.iio_root.display()
             );
-            for i in 0..3 {
-                println!("write {}/3", i + 1);
-                std::fs::write(&backlight, &max_brightness)
-                    .expect("failed to write brightness -- consider running this program as root");
-                std::thread::sleep(Duration::from_millis(250));
+            None
+        }
+    };
+    if sensor.is_none() {
+        println!("no ambient light sensor; following the time of day");
+    }
+    let range = LuxRange {
+        dark: args.dark_lux,
+        bright: args.bright_lux,
+    };
+    Adaptive::new(
+        sensor.map(|s| (Box::new(s) as Box<dyn adaptive::AmbientSensor>, range)),
+        Box::new(adaptive::SystemClock),
+        args.day_curve.clone(),
+        args.adaptive_smoothing,
+    )
+}
+
+/// Works out where each output should fade to.
+/// Before blanking, the level each settled output is at gets remembered,
+/// and that level is what it is restored to afterwards.
+fn plan_fades(
+    args: &Args,
+    outputs: &mut [Output],
+    lid_open: bool,
+    targets: &mut Targets,
+) -> Vec<Fade> {
+    let adaptive = if lid_open { None } else { targets.sample() };
+    let mut fades = Vec::with_capacity(outputs.len());
+    for output in outputs.iter_mut() {
+        if output.settled {
+            match output.backlight.brightness() {
+                Ok(level) => output.current = level,
+                Err(why) => eprintln!("failed to read brightness of {}: {why}", output.name()),
+            }
+        }
+
+        let fade = if lid_open {
+            if output.settled
+                && let Err(why) = targets.store.remember(output.name(), output.current)
+            {
+                eprintln!("failed to save brightness of {}: {why}", output.name());
+            }
+            Fade {
+                from: output.current,
+                to: 0,
+                max: output.max,
+                duration: Duration::from_millis(args.fade_out_ms),
+                curve: args.fade_out_curve,
+            }
+        } else {
+            Fade {
+                from: output.current,
+                to: targets.on_level(output, adaptive),
+                max: output.max,
+                duration: Duration::from_millis(args.fade_in_ms),
+                curve: args.fade_in_curve,
+            }
+        };
+        fades.push(fade);
+    }
+    fades
+}
+
+/// Works out gentle fades towards the adaptive level while the screen is on.
+/// Returns nothing if no output would change by more than the threshold,
+/// so that small fluctuations do not cause constant rewrites.
+fn plan_adjustments(args: &Args, outputs: &mut [Output], targets: &mut Targets) -> Vec<Fade> {
+    let adaptive = targets.sample();
+    let fades: Vec<Fade> = outputs
+        .iter()
+        .map(|output| Fade {
+            from: output.current,
+            to: targets.on_level(output, adaptive),
+            max: output.max,
+            duration: Duration::from_millis(args.adaptive_fade_ms),
+            curve: args.fade_in_curve,
+        })
+        .collect();
+    let significant = fades.iter().any(|fade| {
+        let threshold = (fade.max as u64 * args.adaptive_threshold_percent as u64 / 100) as u32;
+        fade.from.abs_diff(fade.to) > threshold
+    });
+    if significant { fades } else { Vec::new() }
+}
+
+fn open_outputs(args: &Args) -> Vec<Output> {
+    match args.backend {
+        Backend::None => return Vec::new(),
+        Backend::Sysfs | Backend::Logind => {}
+    }
+
+    let devices = backlight::discover(&args.sysfs_root).unwrap_or_else(|why| {
+        panic!(
+            "failed to list backlight devices in {}: {why}",
+            args.sysfs_root.display()
+        )
+    });
+    let selection = Selection {
+        names: args.devices.clone(),
+        types: args.device_types.clone(),
+    };
+    let devices = backlight::select(devices, &selection).unwrap_or_else(|why| panic!("{why}"));
+
+    devices
+        .iter()
+        .filter_map(|device| {
+            let backlight: Box<dyn Backlight> = match args.backend {
+                Backend::Sysfs => Box::new(backlight::sysfs::SysfsBacklight::new(device)),
+                Backend::Logind => Box::new(
+                    backlight::logind::LogindBacklight::new(device, &args.logind_session)
+                        .expect("failed to connect to logind"),
+                ),
+                Backend::None => unreachable!(),
+            };
+            open_output(dry_run(args, backlight))
+        })
+        .collect()
+}
+
+/// Opens an output, or logs why it is skipped.
+fn open_output(backlight: Box<dyn Backlight>) -> Option<Output> {
+    let name = backlight.name().to_string();
+    Output::new(backlight)
+        .inspect_err(|why| eprintln!("skipping backlight {name}: {why}"))
+        .ok()
+}
+
+fn open_ddc_monitors(
+    args: &Args,
+    outputs: &mut Vec<Output>,
+    displays: &mut Vec<Box<dyn DisplayPower>>,
+) {
+    for bus in &args.ddc_buses {
+        let connection = match ddc::DdcCi::open(bus) {
+            Ok(connection) => connection,
+            Err(why) => {
+                eprintln!(
+                    "skipping {}: failed to open it for DDC/CI -- is the i2c-dev module loaded? {why}",
+                    bus.display()
+                );
+                continue;
+            }
+        };
+        let monitor = ddc::DdcMonitor::new(format!("ddc:{}", bus.display()), connection);
+        if args.ddc_power {
+            // A monitor that is off now may still be switched on later
+            displays.push(dry_run_display(args, Box::new(monitor.clone())));
+        }
+        outputs.extend(open_output(dry_run(args, Box::new(monitor))));
+    }
+}
+
+fn open_displays(args: &Args) -> Vec<Box<dyn DisplayPower>> {
+    let mut displays: Vec<Box<dyn DisplayPower>> = Vec::new();
+    for backend in &args.display_power {
+        match backend {
+            PowerBackend::Drm => {
+                let connectors =
+                    power::DrmConnector::open_all(&args.drm_card, &args.drm_connectors)
+                        .unwrap_or_else(|why| {
+                            panic!(
+                                "failed to open DRM connectors of {}: {why}",
+                                args.drm_card.display()
+                            )
+                        });
+                for connector in connectors {
+                    println!("using DRM connector {}", connector.name());
+                    displays.push(dry_run_display(args, Box::new(connector)));
+                }
+            }
+            PowerBackend::Mutter => {
+                let mutter =
+                    power::mutter::MutterDisplays::new().expect("failed to connect to session bus");
+                displays.push(dry_run_display(args, Box::new(mutter)));
+            }
+        }
+    }
+    displays
+}
+
+/// Wraps a backlight in [`DryRun`] if --dry-run was given.
+fn dry_run(args: &Args, backlight: Box<dyn Backlight>) -> Box<dyn Backlight> {
+    if args.dry_run {
+        Box::new(DryRun(backlight))
+    } else {
+        backlight
+    }
+}
+
+/// Wraps a display in [`DryRun`] if --dry-run was given.
+fn dry_run_display(args: &Args, display: Box<dyn DisplayPower>) -> Box<dyn DisplayPower> {
+    if args.dry_run {
+        Box::new(DryRun(display))
+    } else {
+        display
+    }
+}
+
+/// Writes every step of the fades (one per output), then re-asserts the final levels.
+/// Returns early with the new event if one arrives while the fade is running.
+fn run_fade(
+    outputs: &mut [Output],
+    fades: &[Fade],
+    events: &Receiver<LidState>,
+) -> Option<LidState> {
+    // Outputs that cannot keep up with every step, like DDC/CI monitors,
+    // take fewer, longer steps over the same duration
+    let strides: Vec<u32> = outputs
+        .iter()
+        .map(|output| {
+            let interval = output.backlight.min_write_interval();
+            (interval.as_micros().div_ceil(FADE_STEP.as_micros()) as u32).max(1)
+        })
+        .collect();
+    let mut steps: Vec<_> = fades
+        .iter()
+        .zip(&strides)
+        .map(|(fade, &stride)| fade.steps(FADE_STEP * stride).peekable())
+        .collect();
+    for tick in 0.. {
+        let mut any = false;
+        for ((output, steps), stride) in outputs.iter_mut().zip(steps.iter_mut()).zip(&strides) {
+            if steps.peek().is_none() {
+                continue;
+            }
+            any = true;
+            if tick % stride == 0
+                && let Some(level) = steps.next()
+                && level != output.current
+            {
+                output.write(level);
             }
         }
+        if !any {
+            break;
+        }
+        match events.recv_timeout(FADE_STEP) {
+            Ok(event) => {
+                println!("new event during fade, stopping");
+                return Some(event);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    for _ in 0..SETTLE_WRITES {
+        for (output, fade) in outputs.iter_mut().zip(fades) {
+            output.write(fade.to);
+        }
+        match events.recv_timeout(SETTLE_INTERVAL) {
+            Ok(event) => return Some(event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backlight::fake::FakeBacklight;
+
+    fn fade_to(output: &Output, to: u32) -> Fade {
+        Fade {
+            from: output.current,
+            to,
+            max: output.max,
+            duration: Duration::from_millis(100),
+            curve: Curve::Linear,
+        }
+    }
+
+    #[test]
+    fn fade_drives_every_output_to_its_target() {
+        let a = FakeBacklight::new("a", 100, 100);
+        let b = FakeBacklight::new("b", 1000, 1000);
+        let mut outputs = vec![
+            Output::new(Box::new(a.clone())).unwrap(),
+            Output::new(Box::new(b.clone())).unwrap(),
+        ];
+        let fades = [fade_to(&outputs[0], 0), fade_to(&outputs[1], 0)];
+        let (_tx, events) = mpsc::channel();
+
+        assert!(run_fade(&mut outputs, &fades, &events).is_none());
+        assert_eq!(a.brightness().unwrap(), 0);
+        assert_eq!(b.brightness().unwrap(), 0);
+        // startup write, at least one intermediate step, then the settle writes
+        assert!(a.writes().len() > 1 + 1 + SETTLE_WRITES as usize);
+        assert!(a.writes().windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn slow_outputs_take_fewer_steps_in_the_same_time() {
+        let fast = FakeBacklight::new("fast", 100, 100);
+        let slow =
+            FakeBacklight::new("slow", 100, 100).with_write_interval(Duration::from_millis(50));
+        let mut outputs = vec![
+            Output::new(Box::new(fast.clone())).unwrap(),
+            Output::new(Box::new(slow.clone())).unwrap(),
+        ];
+        let fades = [fade_to(&outputs[0], 0), fade_to(&outputs[1], 0)];
+        let (_tx, events) = mpsc::channel();
+
+        run_fade(&mut outputs, &fades, &events);
+        // startup write, then 5 steps of 20ms or 2 steps of 60ms, then the settle writes
+        assert_eq!(fast.writes().len(), 1 + 5 + SETTLE_WRITES as usize);
+        assert_eq!(slow.writes().len(), 1 + 2 + SETTLE_WRITES as usize);
+        assert_eq!(slow.writes().last(), Some(&0));
+    }
+
+    #[test]
+    fn refused_writes_unsettle_the_output_without_stopping_the_fade() {
+        let a = FakeBacklight::new("a", 100, 100);
+        let b = FakeBacklight::new("b", 100, 100);
+        let mut outputs = vec![
+            Output::new(Box::new(a.clone())).unwrap(),
+            Output::new(Box::new(b.clone())).unwrap(),
+        ];
+        let fades = [fade_to(&outputs[0], 0), fade_to(&outputs[1], 0)];
+        let (_tx, events) = mpsc::channel();
+
+        a.refuse_writes(true);
+        assert!(run_fade(&mut outputs, &fades, &events).is_none());
+        assert_eq!(a.brightness().unwrap(), 100);
+        assert_eq!(b.brightness().unwrap(), 0);
+        assert!(!outputs[0].settled);
+        assert_eq!(outputs[0].current, 100);
+        assert!(outputs[1].settled);
+    }
+
+    #[test]
+    fn outputs_that_cannot_be_written_are_skipped() {
+        let a = FakeBacklight::new("a", 100, 100);
+        a.refuse_writes(true);
+        assert!(open_output(Box::new(a)).is_none());
+    }
+
+    #[test]
+    fn new_event_interrupts_fade() {
+        let a = FakeBacklight::new("a", 100, 0);
+        let mut outputs = vec![Output::new(Box::new(a.clone())).unwrap()];
+        let fades = [fade_to(&outputs[0], 100)];
+        let (tx, events) = mpsc::channel();
+        let event = LidState {
+            lid_open: true,
+            changed_at: api_types::now(),
+        };
+        tx.send(event.clone()).unwrap();
+
+        assert_eq!(run_fade(&mut outputs, &fades, &events), Some(event));
+        let level = a.brightness().unwrap();
+        assert!(level > 0 && level < 100, "stopped at {level}");
+        assert_eq!(outputs[0].current, level);
+    }
+
+    #[test]
+    fn restores_level_set_by_hand() {
+        let args = Args::parse_from(["brightness-control"]);
+        let limits = Limits {
+            floor_percent: 10,
+            ceiling_percent: 100,
+        };
+        let mut targets = Targets {
+            store: LevelStore::load(None),
+            limits,
+            adaptive: None,
+        };
+        let a = FakeBacklight::new("a", 100, 100);
+        let mut outputs = vec![Output::new(Box::new(a.clone())).unwrap()];
+
+        a.set_external(42);
+        let fades = plan_fades(&args, &mut outputs, true, &mut targets);
+        assert_eq!((fades[0].from, fades[0].to), (42, 0));
+        outputs[0].current = 0;
+
+        let fades = plan_fades(&args, &mut outputs, false, &mut targets);
+        assert_eq!(fades[0].to, 42);
+    }
+
+    #[test]
+    fn interrupted_fade_is_not_remembered() {
+        let args = Args::parse_from(["brightness-control"]);
+        let limits = Limits {
+            floor_percent: 10,
+            ceiling_percent: 100,
+        };
+        let mut targets = Targets {
+            store: LevelStore::load(None),
+            limits,
+            adaptive: None,
+        };
+        let a = FakeBacklight::new("a", 100, 70);
+        let mut outputs = vec![Output::new(Box::new(a.clone())).unwrap()];
+        targets.store.remember("a", 70).unwrap();
+
+        // a fade-in got interrupted at 5; blanking now must keep the old level
+        outputs[0].current = 5;
+        outputs[0].settled = false;
+        plan_fades(&args, &mut outputs, true, &mut targets);
+        assert_eq!(targets.store.get("a"), Some(70));
     }
 }
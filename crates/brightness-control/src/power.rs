@@ -0,0 +1,214 @@
+use std::{
+    fs::File,
+    os::fd::{AsFd, BorrowedFd},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use drm::control::{Device as _, connector, property};
+
+use crate::backlight::Backlight;
+
+pub mod mutter;
+
+/// A display that can be switched off entirely, rather than just dimmed.
+pub trait DisplayPower: Send {
+    /// Name of the display, for logging.
+    fn name(&self) -> &str;
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()>;
+}
+
+/// How the screen is blanked when the lid opens.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlankMode {
+    /// Fade the backlight to zero
+    Backlight,
+    /// Switch the displays off, leaving the backlight level alone
+    Power,
+    /// Fade the backlight to zero, then switch the displays off
+    Both,
+}
+
+impl BlankMode {
+    pub fn uses_backlight(self) -> bool {
+        matches!(self, Self::Backlight | Self::Both)
+    }
+
+    pub fn uses_power(self) -> bool {
+        matches!(self, Self::Power | Self::Both)
+    }
+}
+
+/// Ways to switch a display off other than DDC/CI.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerBackend {
+    /// Set the DPMS property of DRM connectors; needs DRM master, so only works without a compositor
+    Drm,
+    /// Ask GNOME's compositor to power the outputs down, like its screen blanking does
+    Mutter,
+}
+
+/// Switches every display, logging failures instead of stopping:
+/// a monitor that was unplugged should not take the controller down.
+pub fn set_all(displays: &mut [Box<dyn DisplayPower>], on: bool) {
+    for display in displays {
+        println!(
+            "turning display {} {}",
+            display.name(),
+            if on { "on" } else { "off" }
+        );
+        if let Err(why) = display.set_power(on) {
+            eprintln!("failed to switch display {}: {why}", display.name());
+        }
+    }
+}
+
+struct Card(File);
+
+impl AsFd for Card {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl drm::Device for Card {}
+impl drm::control::Device for Card {}
+
+/// DPMS values from the kernel's drm_mode.h.
+const DPMS_ON: u64 = 0;
+const DPMS_OFF: u64 = 3;
+
+/// A DRM connector (e.g. `HDMI-A-1`) switched through its DPMS property.
+pub struct DrmConnector {
+    name: String,
+    card: Arc<Card>,
+    connector: connector::Handle,
+    dpms: property::Handle,
+}
+
+impl DrmConnector {
+    /// Opens the named connectors of a card (e.g. `/dev/dri/card0`),
+    /// or all connected ones if `names` is empty.
+    pub fn open_all(card: &Path, names: &[String]) -> std::io::Result<Vec<Self>> {
+        let card = Arc::new(Card(File::options().read(true).write(true).open(card)?));
+        let mut connectors = Vec::new();
+        for &handle in card.resource_handles()?.connectors() {
+            let info = card.get_connector(handle, false)?;
+            let name = format!("{}-{}", info.interface().as_str(), info.interface_id());
+            let wanted = if names.is_empty() {
+                info.state() == connector::State::Connected
+            } else {
+                names.contains(&name)
+            };
+            if !wanted {
+                continue;
+            }
+
+            let properties = card.get_properties(handle)?;
+            let (ids, _) = properties.as_props_and_values();
+            let mut dpms = None;
+            for &id in ids {
+                if card.get_property(id)?.name().to_bytes() == b"DPMS" {
+                    dpms = Some(id);
+                }
+            }
+            let dpms = dpms.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("connector {name} has no DPMS property"),
+                )
+            })?;
+            connectors.push(Self {
+                name,
+                card: card.clone(),
+                connector: handle,
+                dpms,
+            });
+        }
+
+        if let Some(missing) = names
+            .iter()
+            .find(|name| !connectors.iter().any(|c| &&c.name == name))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no connector named {missing}"),
+            ));
+        }
+        Ok(connectors)
+    }
+}
+
+impl DisplayPower for DrmConnector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        let value = if on { DPMS_ON } else { DPMS_OFF };
+        self.card.set_property(self.connector, self.dpms, value)
+    }
+}
+
+/// Wraps a device so that it is only read from; writes are logged instead.
+pub struct DryRun<T>(pub T);
+
+impl Backlight for DryRun<Box<dyn Backlight>> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn max_brightness(&self) -> std::io::Result<u32> {
+        self.0.max_brightness()
+    }
+
+    fn brightness(&self) -> std::io::Result<u32> {
+        self.0.brightness()
+    }
+
+    fn set_brightness(&mut self, level: u32) -> std::io::Result<()> {
+        println!(
+            "[dry run] would set brightness of {} to {level}",
+            self.name()
+        );
+        Ok(())
+    }
+
+    fn min_write_interval(&self) -> Duration {
+        self.0.min_write_interval()
+    }
+}
+
+impl DisplayPower for DryRun<Box<dyn DisplayPower>> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        println!(
+            "[dry run] would turn display {} {}",
+            self.name(),
+            if on { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlight::fake::FakeBacklight;
+
+    #[test]
+    fn dry_run_reads_but_does_not_write() {
+        let fake = FakeBacklight::new("a", 100, 40);
+        let mut dry: Box<dyn Backlight> =
+            Box::new(DryRun(Box::new(fake.clone()) as Box<dyn Backlight>));
+        assert_eq!(dry.brightness().unwrap(), 40);
+        dry.set_brightness(0).unwrap();
+        assert!(fake.writes().is_empty());
+        assert_eq!(fake.brightness().unwrap(), 40);
+    }
+}
@@ -0,0 +1,43 @@
+use zbus::{blocking::Connection, zvariant::Value};
+
+use super::DisplayPower;
+
+/// `PowerSaveMode` values of org.gnome.Mutter.DisplayConfig (the same as DPMS).
+const POWER_SAVE_ON: i32 = 0;
+const POWER_SAVE_OFF: i32 = 3;
+
+/// Powers all outputs down through GNOME's compositor, over the session bus.
+pub struct MutterDisplays {
+    connection: Connection,
+}
+
+impl MutterDisplays {
+    pub fn new() -> std::io::Result<Self> {
+        let connection = Connection::session().map_err(std::io::Error::other)?;
+        Ok(Self { connection })
+    }
+}
+
+impl DisplayPower for MutterDisplays {
+    fn name(&self) -> &str {
+        "mutter"
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        let mode = if on { POWER_SAVE_ON } else { POWER_SAVE_OFF };
+        self.connection
+            .call_method(
+                Some("org.gnome.Mutter.DisplayConfig"),
+                "/org/gnome/Mutter/DisplayConfig",
+                Some("org.freedesktop.DBus.Properties"),
+                "Set",
+                &(
+                    "org.gnome.Mutter.DisplayConfig",
+                    "PowerSaveMode",
+                    Value::from(mode),
+                ),
+            )
+            .map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
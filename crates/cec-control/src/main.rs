@@ -0,0 +1,357 @@
+use std::{
+    io::Write,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+use api_types::{LidState, PlayerCommand};
+use cec::{
+    CecDevice, Message, PowerStatus, format_physical_address,
+    message::{BROADCAST, MENU_ACTIVATED, TV, key, opcode},
+};
+use clap::Parser;
+
+/// How long to wait for the TV to answer a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often to ask the TV whether it got there while it is turning on or off.
+const POWER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a receive waits, so that errors on the adapter show up.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait after a receive fails, doubled for every failure in a row up to the maximum.
+const RECEIVE_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECEIVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(clap::Parser)]
+struct Args {
+    /// The CEC adapter to use
+    #[clap(long, default_value = "/dev/cec0")]
+    device: PathBuf,
+
+    /// Name the TV shows for this device in its source list, up to 14 characters
+    #[clap(long, default_value = cec::DEFAULT_OSD_NAME)]
+    osd_name: String,
+
+    /// Leave the TV on when the lid closes, only giving up being its source
+    #[clap(long)]
+    keep_tv_on: bool,
+
+    /// How long the TV may take to turn on or off before it is reported as stuck, in milliseconds
+    #[clap(long, default_value = "20000")]
+    power_timeout_ms: u64,
+
+    /// Socket to send the TV remote's playback keys to
+    #[clap(long, default_value = api_types::PLAYER_CONTROL_SOCKET)]
+    player_socket: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    let device = Arc::new(
+        CecDevice::open(&args.device, &args.osd_name, true).expect("failed to open CEC adapter"),
+    );
+    match device.physical_address() {
+        Ok(address) => println!(
+            "using {} as device {} at {}",
+            device.name(),
+            device.logical_address(),
+            format_physical_address(address)
+        ),
+        Err(why) => eprintln!("cannot read physical address of {}: {why}", device.name()),
+    }
+
+    // Whether we should be the TV's source, i.e. the lid is open
+    let active = Arc::new(AtomicBool::new(false));
+    {
+        let device = device.clone();
+        let active = active.clone();
+        let player_socket = args.player_socket.clone();
+        std::thread::spawn(move || follow(&device, &active, &player_socket));
+    }
+
+    let (tx, events) = mpsc::channel();
+    std::thread::spawn(move || {
+        let lid_stream =
+            lid_subscriber::LidSubscriber::new().expect("failed to connect to lid status socket");
+        for state in lid_stream {
+            if tx.send(state).is_err() {
+                break;
+            }
+        }
+    });
+
+    let power_timeout = Duration::from_millis(args.power_timeout_ms);
+    let mut next_event = events.recv().ok();
+    while let Some(state) = next_event.take() {
+        println!(
+            "New state at {}: lid open: {}",
+            state.changed_at, state.lid_open
+        );
+        active.store(state.lid_open, Ordering::SeqCst);
+        let wanted = if state.lid_open {
+            if let Err(why) = turn_on(&device) {
+                eprintln!("failed to turn the TV on: {why}");
+            }
+            Some(PowerStatus::On)
+        } else if args.keep_tv_on {
+            if let Err(why) = give_up_source(&device) {
+                eprintln!("failed to tell the TV to switch away: {why}");
+            }
+            None
+        } else {
+            if let Err(why) = device.send(TV, opcode::STANDBY, &[]) {
+                eprintln!("failed to put the TV in standby: {why}");
+            }
+            Some(PowerStatus::Standby)
+        };
+        next_event = match wanted {
+            Some(wanted) => confirm_power(&device, wanted, power_timeout, &events),
+            None => None,
+        }
+        .or_else(|| events.recv().ok());
+    }
+    eprintln!("lid events stopped, exiting");
+}
+
+/// Wakes the TV up and makes it switch to our input.
+fn turn_on(device: &CecDevice) -> std::io::Result<()> {
+    device.send(TV, opcode::IMAGE_VIEW_ON, &[])?;
+    let address = device.physical_address()?;
+    device.send(BROADCAST, opcode::ACTIVE_SOURCE, &address.to_be_bytes())
+}
+
+fn give_up_source(device: &CecDevice) -> std::io::Result<()> {
+    let address = device.physical_address()?;
+    device.send(TV, opcode::INACTIVE_SOURCE, &address.to_be_bytes())
+}
+
+fn power_status(device: &CecDevice) -> std::io::Result<PowerStatus> {
+    let reply = device.request(
+        TV,
+        opcode::GIVE_DEVICE_POWER_STATUS,
+        &[],
+        opcode::REPORT_POWER_STATUS,
+        REPLY_TIMEOUT,
+    )?;
+    reply
+        .operands
+        .first()
+        .and_then(|&operand| PowerStatus::from_operand(operand))
+        .ok_or_else(|| std::io::Error::other(format!("invalid power status {:?}", reply.operands)))
+}
+
+/// Asks the TV for its power status until it is `wanted`, for up to `timeout`.
+/// Returns early with the new lid state if one arrives in the meantime.
+fn confirm_power(
+    device: &CecDevice,
+    wanted: PowerStatus,
+    timeout: Duration,
+    events: &Receiver<LidState>,
+) -> Option<LidState> {
+    let started = Instant::now();
+    loop {
+        let status = match power_status(device) {
+            Ok(status) if status == wanted => {
+                println!("TV reports {status:?} after {:?}", started.elapsed());
+                return None;
+            }
+            status => status,
+        };
+        if started.elapsed() >= timeout {
+            eprintln!("TV is still not {wanted:?} after {timeout:?}: {status:?}");
+            return None;
+        }
+        match events.recv_timeout(POWER_POLL_INTERVAL) {
+            Ok(state) => return Some(state),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+/// What to do about a message someone sent us.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Reply {
+        destination: u8,
+        opcode: u8,
+        operands: Vec<u8>,
+    },
+    Player(PlayerCommand),
+}
+
+/// Answers what the TV asks of a playback device, and turns remote keys into player commands.
+fn respond(msg: &Message, active: bool, physical_address: u16) -> Option<Action> {
+    let active_source = || Action::Reply {
+        destination: BROADCAST,
+        opcode: opcode::ACTIVE_SOURCE,
+        operands: physical_address.to_be_bytes().to_vec(),
+    };
+    match msg.opcode {
+        opcode::REQUEST_ACTIVE_SOURCE if active => Some(active_source()),
+        opcode::SET_STREAM_PATH if msg.operands == physical_address.to_be_bytes() => {
+            Some(active_source())
+        }
+        // Some TVs only pass remote keys on while the device says its menu is shown
+        opcode::MENU_REQUEST if !msg.is_broadcast() => Some(Action::Reply {
+            destination: msg.initiator,
+            opcode: opcode::MENU_STATUS,
+            operands: vec![MENU_ACTIVATED],
+        }),
+        opcode::GIVE_DEVICE_POWER_STATUS if !msg.is_broadcast() => Some(Action::Reply {
+            destination: msg.initiator,
+            opcode: opcode::REPORT_POWER_STATUS,
+            operands: vec![0],
+        }),
+        opcode::USER_CONTROL_PRESSED => msg
+            .operands
+            .first()
+            .and_then(|&key| player_command(key))
+            .map(Action::Player),
+        _ => None,
+    }
+}
+
+fn player_command(key: u8) -> Option<PlayerCommand> {
+    match key {
+        key::PLAY | key::PLAY_FUNCTION => Some(PlayerCommand::Play),
+        key::PAUSE | key::STOP => Some(PlayerCommand::Pause),
+        key::PAUSE_PLAY_FUNCTION => Some(PlayerCommand::TogglePause),
+        key::CHANNEL_UP | key::FORWARD => Some(PlayerCommand::Next),
+        key::CHANNEL_DOWN | key::BACKWARD => Some(PlayerCommand::Previous),
+        _ => None,
+    }
+}
+
+/// Handles messages sent to us. Receive errors are waited out, with a longer wait
+/// the longer they go on.
+fn follow(device: &CecDevice, active: &AtomicBool, player_socket: &Path) {
+    let mut retry_delay = RECEIVE_RETRY_DELAY;
+    loop {
+        let msg = match device.receive(RECEIVE_TIMEOUT) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => continue,
+            Err(why) => {
+                eprintln!(
+                    "failed to receive from {}: {why}; trying again in {retry_delay:?}",
+                    device.name()
+                );
+                std::thread::sleep(retry_delay);
+                retry_delay = (retry_delay * 2).min(MAX_RECEIVE_RETRY_DELAY);
+                continue;
+            }
+        };
+        retry_delay = RECEIVE_RETRY_DELAY;
+        let physical_address = match device.physical_address() {
+            Ok(address) => address,
+            Err(why) => {
+                eprintln!("cannot read physical address: {why}");
+                continue;
+            }
+        };
+        match respond(&msg, active.load(Ordering::SeqCst), physical_address) {
+            Some(Action::Reply {
+                destination,
+                opcode,
+                operands,
+            }) => {
+                if let Err(why) = device.send(destination, opcode, &operands) {
+                    eprintln!("failed to answer {msg:?}: {why}");
+                }
+            }
+            Some(Action::Player(command)) => {
+                println!("remote key: {command:?}");
+                if let Err(why) = send_to_player(player_socket, command) {
+                    eprintln!("failed to send {command:?} to the player: {why}");
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn send_to_player(path: &Path, command: PlayerCommand) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(path)?;
+    let mut line = serde_json::to_string(&command).expect("failed to serialize command");
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixListener,
+    };
+
+    use super::*;
+
+    fn from_tv(opcode: u8, destination: u8, operands: &[u8]) -> Message {
+        Message {
+            initiator: TV,
+            destination,
+            opcode,
+            operands: operands.to_vec(),
+        }
+    }
+
+    #[test]
+    fn answers_the_tv() {
+        let request = from_tv(opcode::REQUEST_ACTIVE_SOURCE, BROADCAST, &[]);
+        let active_source = Action::Reply {
+            destination: BROADCAST,
+            opcode: opcode::ACTIVE_SOURCE,
+            operands: vec![0x20, 0x00],
+        };
+        assert_eq!(respond(&request, true, 0x2000), Some(active_source));
+        assert_eq!(respond(&request, false, 0x2000), None);
+
+        // Switched to our input, or to another one
+        let ours = from_tv(opcode::SET_STREAM_PATH, BROADCAST, &[0x20, 0x00]);
+        assert!(respond(&ours, false, 0x2000).is_some());
+        let theirs = from_tv(opcode::SET_STREAM_PATH, BROADCAST, &[0x10, 0x00]);
+        assert_eq!(respond(&theirs, true, 0x2000), None);
+
+        let menu = from_tv(opcode::MENU_REQUEST, 4, &[0x02]);
+        assert_eq!(
+            respond(&menu, true, 0x2000),
+            Some(Action::Reply {
+                destination: TV,
+                opcode: opcode::MENU_STATUS,
+                operands: vec![MENU_ACTIVATED],
+            })
+        );
+    }
+
+    #[test]
+    fn forwards_playback_keys() {
+        let pressed = |key| respond(&from_tv(opcode::USER_CONTROL_PRESSED, 4, &[key]), true, 0);
+        assert_eq!(
+            pressed(key::PAUSE),
+            Some(Action::Player(PlayerCommand::Pause))
+        );
+        assert_eq!(
+            pressed(key::CHANNEL_UP),
+            Some(Action::Player(PlayerCommand::Next))
+        );
+        assert_eq!(pressed(key::VOLUME_UP), None);
+    }
+
+    #[test]
+    fn sends_commands_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("player.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        send_to_player(&path, PlayerCommand::TogglePause).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "\"toggle_pause\"\n");
+    }
+}
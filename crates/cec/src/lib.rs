@@ -0,0 +1,247 @@
+//! HDMI-CEC through the Linux CEC API, with the kernel's ioctls on `/dev/cec*` directly
+//! instead of going through libcec or cec-ctl.
+//!
+//! The structs below mirror the kernel's uapi/linux/cec.h; not every field is used.
+#![allow(dead_code)]
+
+use std::{fs::File, os::fd::AsRawFd, path::Path, time::Duration};
+
+pub use message::{AudioStatus, Message, PowerStatus, format_physical_address};
+
+pub mod message;
+
+/// Name the TV shows for us, unless told otherwise.
+pub const DEFAULT_OSD_NAME: &str = "babooshka";
+
+const MAX_MSG_SIZE: usize = 16;
+const MAX_LOG_ADDRS: usize = 4;
+const OSD_NAME_SIZE: usize = 15;
+
+/// `CEC_MODE_INITIATOR`: may transmit, but not exclusively
+const MODE_INITIATOR: u32 = 0x1;
+/// `CEC_MODE_FOLLOWER`: receives the messages the kernel does not handle itself
+const MODE_FOLLOWER: u32 = 0x10;
+
+const LOG_ADDR_TYPE_PLAYBACK: u8 = 3;
+const PRIM_DEVTYPE_PLAYBACK: u8 = 4;
+const ALL_DEVTYPE_PLAYBACK: u8 = 0x10;
+const CEC_VERSION_1_4: u8 = 5;
+const VENDOR_ID_NONE: u32 = 0xffffffff;
+/// `CEC_LOG_ADDRS_FL_ALLOW_UNREG_FALLBACK`: take the unregistered address if every
+/// playback address is taken, rather than none at all
+const LOG_ADDRS_FL_ALLOW_UNREG_FALLBACK: u32 = 1;
+
+const TX_STATUS_OK: u8 = 0x01;
+const RX_STATUS_OK: u8 = 0x01;
+const RX_STATUS_TIMEOUT: u8 = 0x02;
+const RX_STATUS_FEATURE_ABORT: u8 = 0x04;
+
+/// `struct cec_msg`
+#[repr(C)]
+#[derive(Default)]
+struct CecMsg {
+    tx_ts: u64,
+    rx_ts: u64,
+    len: u32,
+    /// How long to wait for a reply, or for a message in `CEC_RECEIVE`, in milliseconds
+    timeout: u32,
+    sequence: u32,
+    flags: u32,
+    msg: [u8; MAX_MSG_SIZE],
+    /// Opcode of the reply to wait for, if any
+    reply: u8,
+    rx_status: u8,
+    tx_status: u8,
+    tx_arb_lost_cnt: u8,
+    tx_nack_cnt: u8,
+    tx_low_drive_cnt: u8,
+    tx_error_cnt: u8,
+}
+
+/// `struct cec_log_addrs`
+#[repr(C)]
+#[derive(Default)]
+struct LogAddrs {
+    log_addr: [u8; MAX_LOG_ADDRS],
+    log_addr_mask: u16,
+    cec_version: u8,
+    num_log_addrs: u8,
+    vendor_id: u32,
+    flags: u32,
+    osd_name: [u8; OSD_NAME_SIZE],
+    primary_device_type: [u8; MAX_LOG_ADDRS],
+    log_addr_type: [u8; MAX_LOG_ADDRS],
+    all_device_types: [u8; MAX_LOG_ADDRS],
+    features: [[u8; 12]; MAX_LOG_ADDRS],
+}
+
+const _: () = {
+    assert!(size_of::<CecMsg>() == 56);
+    assert!(size_of::<LogAddrs>() == 92);
+};
+
+nix::ioctl_read!(adap_g_phys_addr, b'a', 1, u16);
+nix::ioctl_read!(adap_g_log_addrs, b'a', 3, LogAddrs);
+nix::ioctl_readwrite!(adap_s_log_addrs, b'a', 4, LogAddrs);
+nix::ioctl_readwrite!(transmit, b'a', 5, CecMsg);
+nix::ioctl_readwrite!(receive, b'a', 6, CecMsg);
+nix::ioctl_write_ptr!(s_mode, b'a', 9, u32);
+
+/// A CEC adapter, with a logical address to send from.
+pub struct CecDevice {
+    file: File,
+    name: String,
+    logical_address: u8,
+}
+
+impl CecDevice {
+    /// Opens an adapter, e.g. `/dev/cec0`. If nobody configured it yet, it claims a playback
+    /// device address, which the TV shows as `osd_name`; otherwise it uses the address it has.
+    /// As a `follower`, it receives the messages sent to that address.
+    pub fn open(path: &Path, osd_name: &str, follower: bool) -> std::io::Result<Self> {
+        let file = File::options().read(true).write(true).open(path)?;
+        let fd = file.as_raw_fd();
+        let mode = if follower {
+            MODE_INITIATOR | MODE_FOLLOWER
+        } else {
+            MODE_INITIATOR
+        };
+        unsafe { s_mode(fd, &mode) }?;
+
+        let mut addrs = LogAddrs::default();
+        unsafe { adap_g_log_addrs(fd, &mut addrs) }?;
+        if addrs.num_log_addrs == 0 {
+            let mut wanted = LogAddrs {
+                cec_version: CEC_VERSION_1_4,
+                num_log_addrs: 1,
+                vendor_id: VENDOR_ID_NONE,
+                flags: LOG_ADDRS_FL_ALLOW_UNREG_FALLBACK,
+                primary_device_type: [PRIM_DEVTYPE_PLAYBACK, 0, 0, 0],
+                log_addr_type: [LOG_ADDR_TYPE_PLAYBACK, 0, 0, 0],
+                all_device_types: [ALL_DEVTYPE_PLAYBACK, 0, 0, 0],
+                ..Default::default()
+            };
+            // Leave room for the terminating zero
+            let name = &osd_name.as_bytes()[..osd_name.len().min(OSD_NAME_SIZE - 1)];
+            wanted.osd_name[..name.len()].copy_from_slice(name);
+            // Blocks until the address is claimed. Someone else may have configured
+            // the adapter in the meantime, which is just as good.
+            match unsafe { adap_s_log_addrs(fd, &mut wanted) } {
+                Ok(_) | Err(nix::Error::EBUSY) => {}
+                Err(why) => return Err(why.into()),
+            }
+            unsafe { adap_g_log_addrs(fd, &mut addrs) }?;
+        }
+        if addrs.log_addr_mask == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("{} could not claim a logical address", path.display()),
+            ));
+        }
+
+        Ok(Self {
+            file,
+            name: path.display().to_string(),
+            logical_address: addrs.log_addr[0],
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn logical_address(&self) -> u8 {
+        self.logical_address
+    }
+
+    /// Where the adapter is plugged in; `f.f.f.f` while it is not connected to anything.
+    pub fn physical_address(&self) -> std::io::Result<u16> {
+        let mut address = 0;
+        unsafe { adap_g_phys_addr(self.file.as_raw_fd(), &mut address) }?;
+        Ok(address)
+    }
+
+    /// Sends a message from our address and waits for it to be acknowledged,
+    /// or for broadcasts, to go out.
+    pub fn send(&self, destination: u8, opcode: u8, operands: &[u8]) -> std::io::Result<()> {
+        let mut msg = self.msg(destination, opcode, operands)?;
+        self.transmit(&mut msg)
+    }
+
+    /// Sends a message and waits for the reply with the given opcode.
+    pub fn request(
+        &self,
+        destination: u8,
+        opcode: u8,
+        operands: &[u8],
+        reply: u8,
+        timeout: Duration,
+    ) -> std::io::Result<Message> {
+        let mut msg = self.msg(destination, opcode, operands)?;
+        msg.reply = reply;
+        msg.timeout = timeout.as_millis().max(1) as u32;
+        self.transmit(&mut msg)?;
+        if msg.rx_status & RX_STATUS_OK == 0 {
+            let why = if msg.rx_status & RX_STATUS_FEATURE_ABORT != 0 {
+                "refused it (feature abort)"
+            } else if msg.rx_status & RX_STATUS_TIMEOUT != 0 {
+                "did not answer"
+            } else {
+                "sent an invalid reply"
+            };
+            return Err(std::io::Error::other(format!(
+                "device {destination} {why} to {opcode:#04x}"
+            )));
+        }
+        Message::parse(&msg.msg[..msg.len as usize])
+            .ok_or_else(|| std::io::Error::other(format!("empty reply to {opcode:#04x}")))
+    }
+
+    /// Waits up to `timeout` for a message to arrive; only followers get any.
+    pub fn receive(&self, timeout: Duration) -> std::io::Result<Option<Message>> {
+        let mut msg = CecMsg {
+            timeout: timeout.as_millis().max(1) as u32,
+            ..Default::default()
+        };
+        match unsafe { receive(self.file.as_raw_fd(), &mut msg) } {
+            Ok(_) => Ok(Message::parse(&msg.msg[..msg.len as usize])),
+            Err(nix::Error::ETIMEDOUT) => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    fn msg(&self, destination: u8, opcode: u8, operands: &[u8]) -> std::io::Result<CecMsg> {
+        let bytes = Message {
+            initiator: self.logical_address,
+            destination,
+            opcode,
+            operands: operands.to_vec(),
+        }
+        .to_bytes();
+        if bytes.len() > MAX_MSG_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{opcode:#04x} has too many operands"),
+            ));
+        }
+        let mut msg = CecMsg {
+            len: bytes.len() as u32,
+            ..Default::default()
+        };
+        msg.msg[..bytes.len()].copy_from_slice(&bytes);
+        Ok(msg)
+    }
+
+    fn transmit(&self, msg: &mut CecMsg) -> std::io::Result<()> {
+        unsafe { transmit(self.file.as_raw_fd(), msg) }?;
+        if msg.tx_status & TX_STATUS_OK == 0 {
+            return Err(std::io::Error::other(format!(
+                "{:#04x} to device {} was not acknowledged (status {:#04x})",
+                msg.msg[1],
+                msg.msg[0] & 0xf,
+                msg.tx_status
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! CEC messages and the parts of the HDMI-CEC 1.4 spec this project speaks.
+
+/// Logical addresses of the devices we talk to.
+pub const TV: u8 = 0x0;
+pub const AUDIO_SYSTEM: u8 = 0x5;
+/// As a destination, every device on the bus.
+pub const BROADCAST: u8 = 0xf;
+
+pub mod opcode {
+    pub const FEATURE_ABORT: u8 = 0x00;
+    pub const IMAGE_VIEW_ON: u8 = 0x04;
+    pub const STANDBY: u8 = 0x36;
+    pub const USER_CONTROL_PRESSED: u8 = 0x44;
+    pub const USER_CONTROL_RELEASED: u8 = 0x45;
+    pub const GIVE_AUDIO_STATUS: u8 = 0x71;
+    pub const REPORT_AUDIO_STATUS: u8 = 0x7a;
+    pub const ACTIVE_SOURCE: u8 = 0x82;
+    pub const REQUEST_ACTIVE_SOURCE: u8 = 0x85;
+    pub const SET_STREAM_PATH: u8 = 0x86;
+    pub const MENU_REQUEST: u8 = 0x8d;
+    pub const MENU_STATUS: u8 = 0x8e;
+    pub const GIVE_DEVICE_POWER_STATUS: u8 = 0x8f;
+    pub const REPORT_POWER_STATUS: u8 = 0x90;
+    pub const INACTIVE_SOURCE: u8 = 0x9d;
+}
+
+/// Remote control keys, as sent in User Control Pressed.
+pub mod key {
+    pub const CHANNEL_UP: u8 = 0x30;
+    pub const CHANNEL_DOWN: u8 = 0x31;
+    pub const VOLUME_UP: u8 = 0x41;
+    pub const VOLUME_DOWN: u8 = 0x42;
+    pub const PLAY: u8 = 0x44;
+    pub const STOP: u8 = 0x45;
+    pub const PAUSE: u8 = 0x46;
+    pub const FORWARD: u8 = 0x4b;
+    pub const BACKWARD: u8 = 0x4c;
+    pub const PLAY_FUNCTION: u8 = 0x60;
+    pub const PAUSE_PLAY_FUNCTION: u8 = 0x61;
+    pub const MUTE_FUNCTION: u8 = 0x65;
+    pub const RESTORE_VOLUME_FUNCTION: u8 = 0x66;
+}
+
+/// Menu Status operand: the device's menu is shown, so the TV passes remote keys on to it.
+pub const MENU_ACTIVATED: u8 = 0x00;
+
+/// A message as it goes over the bus: a header block with both addresses,
+/// then the opcode and up to 14 operand bytes. Polls, with no opcode, are not represented.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub initiator: u8,
+    pub destination: u8,
+    pub opcode: u8,
+    pub operands: Vec<u8>,
+}
+
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.initiator << 4 | self.destination, self.opcode];
+        bytes.extend_from_slice(&self.operands);
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let [header, opcode, operands @ ..] = bytes else {
+            return None;
+        };
+        Some(Self {
+            initiator: header >> 4,
+            destination: header & 0xf,
+            opcode: *opcode,
+            operands: operands.to_vec(),
+        })
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.destination == BROADCAST
+    }
+}
+
+/// Power status, as in Report Power Status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerStatus {
+    On,
+    Standby,
+    TurningOn,
+    TurningOff,
+}
+
+impl PowerStatus {
+    pub fn from_operand(operand: u8) -> Option<Self> {
+        match operand {
+            0 => Some(Self::On),
+            1 => Some(Self::Standby),
+            2 => Some(Self::TurningOn),
+            3 => Some(Self::TurningOff),
+            _ => None,
+        }
+    }
+}
+
+/// Volume and mute state of an audio system, as in Report Audio Status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioStatus {
+    /// Percentage, or `None` if the audio system does not know it
+    pub volume: Option<u8>,
+    pub muted: bool,
+}
+
+impl AudioStatus {
+    pub fn from_operand(operand: u8) -> Self {
+        let volume = operand & 0x7f;
+        Self {
+            volume: (volume <= 100).then_some(volume),
+            muted: operand & 0x80 != 0,
+        }
+    }
+}
+
+/// Formats a physical address like `1.0.0.0`, which means HDMI input 1 of the TV.
+pub fn format_physical_address(address: u16) -> String {
+    let [high, low] = address.to_be_bytes();
+    format!("{}.{}.{}.{}", high >> 4, high & 0xf, low >> 4, low & 0xf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let active_source = Message {
+            initiator: 4,
+            destination: BROADCAST,
+            opcode: opcode::ACTIVE_SOURCE,
+            operands: vec![0x10, 0x00],
+        };
+        assert_eq!(active_source.to_bytes(), [0x4f, 0x82, 0x10, 0x00]);
+        assert_eq!(
+            Message::parse(&[0x4f, 0x82, 0x10, 0x00]),
+            Some(active_source)
+        );
+        // A poll has no opcode
+        assert_eq!(Message::parse(&[0x40]), None);
+    }
+
+    #[test]
+    fn parses_statuses() {
+        assert_eq!(PowerStatus::from_operand(2), Some(PowerStatus::TurningOn));
+        assert_eq!(PowerStatus::from_operand(9), None);
+        assert_eq!(
+            AudioStatus::from_operand(0x80 | 35),
+            AudioStatus {
+                volume: Some(35),
+                muted: true
+            }
+        );
+        assert_eq!(AudioStatus::from_operand(0x7f).volume, None);
+        assert_eq!(format_physical_address(0x1200), "1.2.0.0");
+    }
+}
@@ -143,7 +143,7 @@ fn connection_handler(
         serde_json::to_string(&*guard).expect("failed to serialize state")
     };
     conn.write_all(state_str.as_bytes())?;
-    conn.write(b"\n")?;
+    conn.write_all(b"\n")?;
     conn.flush()?;
 
     // Loop to wait for changes and send updates
@@ -156,7 +156,7 @@ fn connection_handler(
 
         let state_str = serde_json::to_string(&*guard).expect("failed to serialize state");
         conn.write_all(state_str.as_bytes())?;
-        conn.write(b"\n")?;
+        conn.write_all(b"\n")?;
         conn.flush()?;
     }
 }
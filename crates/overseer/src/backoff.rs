@@ -0,0 +1,111 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::config::ServiceConfig;
+
+/// Recent crashes of a service, to wait longer before each restart
+/// and give up when it keeps crashing.
+pub struct Crashes {
+    /// When it crashed, oldest first, within the window
+    times: VecDeque<Instant>,
+    window: Duration,
+    limit: usize,
+    first_delay: Duration,
+    max_delay: Duration,
+}
+
+/// What to do about a crash.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    RestartIn(Duration),
+    /// It crashed this many times within the window
+    GiveUp(usize),
+}
+
+impl Crashes {
+    pub fn new(config: &ServiceConfig) -> Self {
+        Self {
+            times: VecDeque::new(),
+            window: Duration::from_secs(config.crash_window_secs),
+            limit: config.crash_limit,
+            first_delay: Duration::from_millis(config.restart_delay_ms),
+            max_delay: Duration::from_millis(config.max_restart_delay_ms),
+        }
+    }
+
+    pub fn record(&mut self, now: Instant) -> Verdict {
+        while let Some(&oldest) = self.times.front()
+            && now.duration_since(oldest) >= self.window
+        {
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+
+        let recent = self.times.len();
+        if recent >= self.limit {
+            return Verdict::GiveUp(recent);
+        }
+        let doublings = (recent - 1).min(31) as u32;
+        let delay = self.first_delay.saturating_mul(1 << doublings);
+        Verdict::RestartIn(delay.min(self.max_delay))
+    }
+
+    /// Forgets the crashes, when someone starts the service again by hand.
+    pub fn clear(&mut self) {
+        self.times.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_then_gives_up() {
+        let config: ServiceConfig = toml::from_str(
+            r#"
+            name = "brightness-control"
+            command = "brightness-control"
+            restart_delay_ms = 1000
+            max_restart_delay_ms = 3000
+            crash_limit = 4
+            crash_window_secs = 60
+            "#,
+        )
+        .unwrap();
+        let mut crashes = Crashes::new(&config);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(
+            crashes.record(at(0)),
+            Verdict::RestartIn(Duration::from_secs(1))
+        );
+        assert_eq!(
+            crashes.record(at(10)),
+            Verdict::RestartIn(Duration::from_secs(2))
+        );
+        assert_eq!(
+            crashes.record(at(20)),
+            Verdict::RestartIn(Duration::from_secs(3))
+        );
+        // The first crash is out of the window by now, so it has crashed three times
+        assert_eq!(
+            crashes.record(at(60)),
+            Verdict::RestartIn(Duration::from_secs(3))
+        );
+        assert_eq!(crashes.record(at(65)), Verdict::GiveUp(4));
+        crashes.clear();
+        assert_eq!(
+            crashes.record(at(66)),
+            Verdict::RestartIn(Duration::from_secs(1))
+        );
+        // A crash long after the others starts over
+        assert_eq!(
+            crashes.record(at(200)),
+            Verdict::RestartIn(Duration::from_secs(1))
+        );
+    }
+}
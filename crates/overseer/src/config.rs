@@ -0,0 +1,275 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// The services to supervise, from a TOML file with a `[[service]]` table for each:
+///
+/// ```toml
+/// [[service]]
+/// name = "volume-control"
+/// command = "volume-control"
+/// args = ["--volume", "60", "--backend", "pulse"]
+/// after = ["lid-publisher"]
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Command to tell someone a service failed, with a summary and the details appended as
+    /// two more arguments, e.g. `["notify-send", "--urgency=critical"]`
+    #[serde(default)]
+    pub notify_command: Vec<String>,
+    #[serde(rename = "service")]
+    pub services: Vec<ServiceConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Name for logs, and for other services to depend on
+    pub name: String,
+    /// Program to run, looked up in PATH unless it is a path
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment variables to set on top of overseer's own
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub working_directory: Option<PathBuf>,
+    /// User to run as, instead of overseer's
+    pub user: Option<String>,
+    #[serde(default)]
+    pub restart: Restart,
+    /// Wait before the first restart, doubled for each crash within `crash_window_secs`
+    #[serde(default = "default_restart_delay_ms")]
+    pub restart_delay_ms: u64,
+    /// The longest to wait before a restart
+    #[serde(default = "default_max_restart_delay_ms")]
+    pub max_restart_delay_ms: u64,
+    /// Crashes within `crash_window_secs` after which the service is left failed
+    #[serde(default = "default_crash_limit")]
+    pub crash_limit: usize,
+    #[serde(default = "default_crash_window_secs")]
+    pub crash_window_secs: u64,
+    /// Services to start, and wait to be ready, before this one
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// Services this one cannot do without: it only starts once they are ready,
+    /// and is stopped while they are not running
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// How to tell the service is ready; without this, it is as soon as it runs
+    pub ready: Option<Ready>,
+    /// How long it may take to get ready before it is considered crashed
+    #[serde(default = "default_ready_timeout_secs")]
+    pub ready_timeout_secs: u64,
+    /// How long it gets to quit after a SIGINT, before it gets a SIGTERM
+    #[serde(default = "default_stop_timeout_ms")]
+    pub stop_timeout_ms: u64,
+    /// How long it gets to quit after the SIGTERM, before it is killed
+    #[serde(default = "default_kill_timeout_ms")]
+    pub kill_timeout_ms: u64,
+    /// When it exits, every other service is stopped and overseer exits, e.g. for the lid
+    /// publisher, without which the TV cannot follow the lid
+    #[serde(default)]
+    pub critical: bool,
+}
+
+fn default_restart_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_restart_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_crash_limit() -> usize {
+    5
+}
+
+fn default_crash_window_secs() -> u64 {
+    300
+}
+
+fn default_ready_timeout_secs() -> u64 {
+    10
+}
+
+fn default_stop_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_kill_timeout_ms() -> u64 {
+    3000
+}
+
+/// A readiness probe.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ready {
+    /// A Unix socket accepts connections: `ready = { socket = "/tmp/run/lid-status.sock" }`
+    Socket(PathBuf),
+    /// A file exists: `ready = { file = "/tmp/run/ready" }`
+    File(PathBuf),
+    /// An `http://` URL answers 200 OK: `ready = { http = "http://127.0.0.1:8080/health" }`
+    Http(String),
+    /// The service writes `READY=1` to the pipe in `$NOTIFY_FD`, like sd_notify:
+    /// `ready = "notify"`
+    Notify,
+}
+
+/// What to do when a service exits.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    #[default]
+    Always,
+    /// Only if it exited with an error or was killed
+    OnFailure,
+    Never,
+}
+
+impl ServiceConfig {
+    /// Names of the services this one comes after or requires.
+    pub fn dependencies(&self) -> impl Iterator<Item = &String> {
+        self.after.iter().chain(&self.requires)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|why| format!("cannot read {}: {why}", path.display()))?;
+        Self::parse(&text).map_err(|why| format!("invalid config {}: {why}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|why| why.to_string())?;
+        if config.services.is_empty() {
+            return Err("no services configured".to_string());
+        }
+        let mut names = BTreeSet::new();
+        for service in &config.services {
+            if !names.insert(&service.name) {
+                return Err(format!("two services are called {:?}", service.name));
+            }
+            if service.crash_limit == 0 {
+                return Err(format!("{} has a crash_limit of 0", service.name));
+            }
+        }
+        for service in &config.services {
+            if let Some(missing) = service.dependencies().find(|name| !names.contains(name)) {
+                return Err(format!(
+                    "{} depends on {missing:?}, which does not exist",
+                    service.name
+                ));
+            }
+        }
+        config.start_order()?;
+        Ok(config)
+    }
+
+    /// Indices of the services, each after those it comes after or requires.
+    pub fn start_order(&self) -> Result<Vec<usize>, String> {
+        let mut order: Vec<usize> = Vec::with_capacity(self.services.len());
+        while order.len() < self.services.len() {
+            // The first service not started yet whose dependencies all are, keeping file order
+            let next = (0..self.services.len()).find(|&index| {
+                !order.contains(&index)
+                    && self.services[index].dependencies().all(|dependency| {
+                        order
+                            .iter()
+                            .any(|&started| self.services[started].name == *dependency)
+                    })
+            });
+            match next {
+                Some(index) => order.push(index),
+                None => {
+                    let stuck: Vec<_> = (0..self.services.len())
+                        .filter(|index| !order.contains(index))
+                        .map(|index| self.services[index].name.as_str())
+                        .collect();
+                    return Err(format!("services depend on each other: {stuck:?}"));
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_services_and_orders_them() {
+        let config = Config::parse(
+            r#"
+            notify_command = ["notify-send", "--urgency=critical"]
+
+            [[service]]
+            name = "player"
+            command = "player"
+            args = ["--playlist", "/srv/playlist.json"]
+            env = { RUST_LOG = "info" }
+            after = ["volume-control"]
+            requires = ["lid-publisher"]
+            ready = "notify"
+
+            [[service]]
+            name = "volume-control"
+            command = "volume-control"
+            restart = "on-failure"
+            crash_limit = 3
+            user = "tv"
+            after = ["lid-publisher"]
+
+            [[service]]
+            name = "lid-publisher"
+            command = "/opt/lid-publisher"
+            ready = { socket = "/tmp/run/lid-status.sock" }
+            critical = true
+            "#,
+        )
+        .unwrap();
+
+        let player = &config.services[0];
+        assert_eq!(player.args, ["--playlist", "/srv/playlist.json"]);
+        assert_eq!(player.env["RUST_LOG"], "info");
+        assert_eq!(player.restart, Restart::Always);
+        assert_eq!(player.restart_delay_ms, 500);
+        assert_eq!(player.ready, Some(Ready::Notify));
+        assert_eq!(
+            config.services[2].ready,
+            Some(Ready::Socket("/tmp/run/lid-status.sock".into()))
+        );
+        assert_eq!(config.services[1].restart, Restart::OnFailure);
+        assert_eq!(config.services[1].crash_limit, 3);
+        assert_eq!(config.notify_command, ["notify-send", "--urgency=critical"]);
+        assert!(config.services[2].critical);
+        assert_eq!(config.start_order().unwrap(), [2, 1, 0]);
+    }
+
+    #[test]
+    fn rejects_mistakes() {
+        let service = |name: &str, after: &str| {
+            format!("[[service]]\nname = \"{name}\"\ncommand = \"true\"\nafter = [{after}]\n")
+        };
+        assert!(Config::parse(&service("a", "")).is_ok());
+        // Unknown field, duplicate names, unknown or circular dependencies, nothing at all
+        assert!(Config::parse(&format!("{}restart_delay = 1\n", service("a", ""))).is_err());
+        assert!(Config::parse(&format!("{}{}", service("a", ""), service("a", ""))).is_err());
+        assert!(Config::parse(&service("a", "\"b\"")).is_err());
+        assert!(
+            Config::parse(&format!(
+                "{}{}",
+                service("a", "\"b\""),
+                service("b", "\"a\"")
+            ))
+            .is_err()
+        );
+        assert!(Config::parse("service = []").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use api_types::{OverseerReply, OverseerRequest};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tracing::warn;
+
+/// A request from the control socket, with where to send the replies to.
+/// Dropping `reply` closes the connection.
+pub struct Request {
+    pub request: OverseerRequest,
+    pub reply: mpsc::UnboundedSender<OverseerReply>,
+}
+
+/// Accepts requests on a Unix socket, one JSON [`OverseerRequest`] per connection,
+/// and hands them over to `requests`.
+pub async fn listen(path: &Path, requests: mpsc::UnboundedSender<Request>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("someone else is listening on {}", path.display()),
+        ));
+    }
+    // Stale file or doesn't exist; safe to remove
+    let _ = tokio::fs::remove_file(path).await;
+    let listener = UnixListener::bind(path)?;
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(why) => {
+                    warn!("failed to accept control connection: {why}");
+                    return;
+                }
+            };
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                if let Err(why) = serve(stream, &requests).await {
+                    warn!("control connection failed: {why}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve(
+    stream: UnixStream,
+    requests: &mpsc::UnboundedSender<Request>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let (reply, mut replies) = mpsc::unbounded_channel();
+    match serde_json::from_str(&line) {
+        Ok(request) => {
+            if requests.send(Request { request, reply }).is_err() {
+                return Ok(());
+            }
+        }
+        Err(why) => {
+            let _ = reply.send(OverseerReply::Error(format!("invalid request: {why}")));
+            drop(reply);
+        }
+    }
+    // Until the supervisor is done with the request, or the client hangs up;
+    // for logs that are followed, only the client ends it
+    let mut ignored = [0; 256];
+    loop {
+        tokio::select! {
+            reply = replies.recv() => {
+                let Some(reply) = reply else {
+                    return Ok(());
+                };
+                let mut line = serde_json::to_string(&reply).expect("failed to serialize reply");
+                line.push('\n');
+                writer.write_all(line.as_bytes()).await?;
+            }
+            // Nothing else is expected from the client, so only its end matters
+            read = reader.read(&mut ignored) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ask(path: &Path, request: &str) -> String {
+        let mut conn = UnixStream::connect(path).await.unwrap();
+        conn.write_all(request.as_bytes()).await.unwrap();
+        let mut replies = String::new();
+        conn.read_to_string(&mut replies).await.unwrap();
+        replies
+    }
+
+    #[tokio::test]
+    async fn answers_requests_until_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/overseer.sock");
+        let (tx, mut requests) = mpsc::unbounded_channel();
+        listen(&path, tx.clone()).await.unwrap();
+        assert!(listen(&path, tx).await.is_err());
+
+        tokio::spawn(async move {
+            while let Some(Request { request, reply }) = requests.recv().await {
+                assert_eq!(
+                    request,
+                    OverseerRequest::Logs {
+                        service: "player".to_string(),
+                        follow: false
+                    }
+                );
+                reply.send(OverseerReply::Log("hello".to_string())).unwrap();
+                reply.send(OverseerReply::Done).unwrap();
+            }
+        });
+        assert_eq!(
+            ask(
+                &path,
+                "{\"logs\":{\"service\":\"player\",\"follow\":false}}\n"
+            )
+            .await,
+            "{\"log\":\"hello\"}\n\"done\"\n"
+        );
+        assert!(ask(&path, "nonsense\n").await.starts_with("{\"error\":"));
+    }
+
+    #[tokio::test]
+    async fn drops_replies_once_the_client_hangs_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("overseer.sock");
+        let (tx, mut requests) = mpsc::unbounded_channel();
+        listen(&path, tx).await.unwrap();
+
+        let mut conn = UnixStream::connect(&path).await.unwrap();
+        conn.write_all(b"{\"logs\":{\"service\":\"player\",\"follow\":true}}\n")
+            .await
+            .unwrap();
+        // A follower of a service that prints nothing
+        let Request { reply, .. } = requests.recv().await.unwrap();
+        drop(conn);
+        tokio::time::timeout(std::time::Duration::from_secs(2), reply.closed())
+            .await
+            .unwrap();
+    }
+}
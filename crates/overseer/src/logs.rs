@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+use api_types::OverseerReply;
+use tokio::sync::mpsc;
+
+/// Lines a service printed that are kept, to show to whoever asks for its logs.
+const KEPT_LINES: usize = 1000;
+
+/// A service's recent output, on stdout and stderr, and who follows it.
+#[derive(Default)]
+pub struct Logs {
+    lines: VecDeque<String>,
+    followers: Vec<mpsc::UnboundedSender<OverseerReply>>,
+}
+
+impl Logs {
+    pub fn push(&mut self, line: &str) {
+        if self.lines.len() == KEPT_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+        self.followers
+            .retain(|follower| follower.send(OverseerReply::Log(line.to_string())).is_ok());
+    }
+
+    /// Sends the kept lines, and with `follow`, every line from now on, until `to` is closed.
+    pub fn replay(&mut self, to: mpsc::UnboundedSender<OverseerReply>, follow: bool) {
+        for line in &self.lines {
+            let _ = to.send(OverseerReply::Log(line.clone()));
+        }
+        if follow {
+            // Those that hung up while the service was quiet
+            self.followers.retain(|follower| !follower.is_closed());
+            self.followers.push(to);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_recent_lines_and_follows() {
+        let mut logs = Logs::default();
+        for n in 0..KEPT_LINES + 2 {
+            logs.push(&format!("line {n}"));
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        logs.replay(tx, true);
+        assert_eq!(rx.try_recv(), Ok(OverseerReply::Log("line 2".to_string())));
+        let replayed = std::iter::from_fn(|| rx.try_recv().ok()).count();
+        assert_eq!(replayed, KEPT_LINES - 1);
+
+        logs.push("new");
+        assert_eq!(rx.try_recv(), Ok(OverseerReply::Log("new".to_string())));
+        // Followers that went away are dropped
+        drop(rx);
+        logs.push("unseen");
+        assert!(logs.followers.is_empty());
+
+        // Or when someone else starts following
+        let (tx, rx) = mpsc::unbounded_channel();
+        logs.replay(tx, true);
+        drop(rx);
+        let (tx, _rx) = mpsc::unbounded_channel();
+        logs.replay(tx, true);
+        assert_eq!(logs.followers.len(), 1);
+    }
+}
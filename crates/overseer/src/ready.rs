@@ -0,0 +1,176 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, BorrowedFd, OwnedFd},
+    time::Duration,
+};
+
+use nix::{
+    fcntl::{FcntlArg, FdFlag, OFlag, fcntl},
+    unistd::pipe2,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    net::{TcpStream, UnixStream, unix::pipe},
+};
+
+use crate::config::Ready;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits until the probe says the service is ready. `notify` is the reading end of the
+/// service's notify pipe, for [`Ready::Notify`].
+pub async fn wait(ready: &Ready, notify: Option<OwnedFd>) -> io::Result<()> {
+    match ready {
+        Ready::Socket(path) => {
+            while UnixStream::connect(path).await.is_err() {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        Ready::File(path) => {
+            while !tokio::fs::try_exists(path).await? {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        Ready::Http(url) => {
+            while !http_ok(url).await {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        Ready::Notify => {
+            let notify = notify.expect("notify pipe for a notify probe");
+            let mut lines =
+                tokio::io::BufReader::new(pipe::Receiver::from_owned_fd(notify)?).lines();
+            while let Some(line) = lines.next_line().await? {
+                if line.trim() == "READY=1" {
+                    // Keep reading whatever else it says, so it does not get a SIGPIPE for it
+                    tokio::spawn(async move { while let Ok(Some(_)) = lines.next_line().await {} });
+                    return Ok(());
+                }
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "closed its notify pipe without saying READY=1",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A pipe for a service to say it is ready on; the service gets the writing end.
+/// Both ends are closed on exec, until [`pass_notify_fd`].
+pub fn notify_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    Ok(pipe2(OFlag::O_CLOEXEC)?)
+}
+
+/// Lets the process the command spawns inherit `writer`, and tells it which fd it is in
+/// `$NOTIFY_FD`. Only that process gets it, and not others spawned meanwhile.
+pub fn pass_notify_fd(command: &mut tokio::process::Command, writer: &OwnedFd) {
+    let fd = writer.as_raw_fd();
+    command.env("NOTIFY_FD", fd.to_string());
+    unsafe {
+        command.pre_exec(move || {
+            fcntl(
+                BorrowedFd::borrow_raw(fd),
+                FcntlArg::F_SETFD(FdFlag::empty()),
+            )?;
+            Ok(())
+        });
+    }
+}
+
+/// Whether a plain `http://` URL answers 200 OK.
+async fn http_ok(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("http://") else {
+        tracing::warn!("only http:// URLs can be probed, not {url}");
+        return false;
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    let Ok(mut stream) = TcpStream::connect(address).await else {
+        return false;
+    };
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\nConnection: close\r\n\r\n");
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+    let mut status = String::new();
+    if tokio::io::BufReader::new(stream)
+        .read_line(&mut status)
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    status.split_whitespace().nth(1) == Some("200")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, UnixListener},
+    };
+
+    use super::*;
+
+    async fn ready_within(ready: &Ready, notify: Option<OwnedFd>) -> bool {
+        tokio::time::timeout(Duration::from_millis(500), wait(ready, notify))
+            .await
+            .is_ok_and(|result| result.is_ok())
+    }
+
+    #[tokio::test]
+    async fn probes_sockets_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = Ready::Socket(dir.path().join("lid-status.sock"));
+        let file = Ready::File(dir.path().join("ready"));
+        assert!(!ready_within(&socket, None).await);
+        assert!(!ready_within(&file, None).await);
+
+        let _listener = UnixListener::bind(dir.path().join("lid-status.sock")).unwrap();
+        std::fs::write(dir.path().join("ready"), "").unwrap();
+        assert!(ready_within(&socket, None).await);
+        assert!(ready_within(&file, None).await);
+    }
+
+    #[tokio::test]
+    async fn probes_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap();
+                assert!(request[..read].starts_with(b"GET /health HTTP/1.0\r\n"));
+                let response = format!("HTTP/1.0 {status}\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        // Unavailable at first, then ready
+        assert!(ready_within(&Ready::Http(url), None).await);
+    }
+
+    #[tokio::test]
+    async fn waits_for_ready_on_the_notify_pipe() {
+        let (reader, writer) = notify_pipe().unwrap();
+        let mut writer = std::fs::File::from(writer);
+        writer.write_all(b"STATUS=loading\nREADY=1\n").unwrap();
+        assert!(ready_within(&Ready::Notify, Some(reader)).await);
+
+        // Exiting without saying so is not being ready
+        let (reader, writer) = notify_pipe().unwrap();
+        drop(writer);
+        let result = wait(&Ready::Notify, Some(reader)).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,1217 @@
+use std::{
+    collections::BTreeSet,
+    io,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use api_types::{OverseerReply, OverseerRequest, ServiceState, ServiceStatus};
+
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::{Pid, User},
+};
+use tokio::{io::AsyncBufReadExt, sync::mpsc};
+
+use crate::{
+    backoff::{Crashes, Verdict},
+    config::{Config, Ready, Restart, ServiceConfig},
+    control::Request,
+    logs::Logs,
+    ready,
+};
+
+/// A service and the process running it, if any.
+struct Service {
+    config: ServiceConfig,
+    state: State,
+    crashes: Crashes,
+    /// Counts the processes started for it, to tell which one an event is about
+    generation: u64,
+    /// Stopped by hand, so not to be started until it is started by hand
+    held: bool,
+    started_at: Option<Instant>,
+    restarts: u32,
+    logs: Arc<Mutex<Logs>>,
+    /// Gone from the config since a reload; kept until its process is,
+    /// since events about it refer to it by index
+    removed: bool,
+}
+
+impl Service {
+    fn new(config: ServiceConfig) -> Self {
+        Self {
+            crashes: Crashes::new(&config),
+            config,
+            state: State::Pending,
+            generation: 0,
+            held: false,
+            started_at: None,
+            restarts: 0,
+            logs: Default::default(),
+            removed: false,
+        }
+    }
+
+    fn pid(&self) -> Option<u32> {
+        match self.state {
+            State::Starting { pid } | State::Running { pid } | State::Stopping { pid } => Some(pid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// To be started once what it depends on is ready
+    Pending,
+    /// Running, but not ready yet
+    Starting {
+        pid: u32,
+    },
+    Running {
+        pid: u32,
+    },
+    /// Asked to quit, by hand or because something it requires stopped
+    Stopping {
+        pid: u32,
+    },
+    /// Exited, to be started again after a delay
+    Waiting,
+    /// Exited, and not to be restarted
+    Stopped,
+    /// Crashed too often to keep restarting it
+    Failed,
+}
+
+enum Event {
+    /// A service's process exited
+    Exited {
+        index: usize,
+        generation: u64,
+        status: ExitStatus,
+    },
+    /// A service's readiness probe finished
+    Ready {
+        index: usize,
+        generation: u64,
+        result: io::Result<()>,
+    },
+    /// A service waited long enough after exiting
+    Restart { index: usize, generation: u64 },
+    /// A service asked to quit had long enough to do so
+    Escalate {
+        index: usize,
+        generation: u64,
+        signal: Signal,
+    },
+}
+
+/// Starts the configured services once what they depend on is ready, and restarts them as
+/// their policy says, until a critical one exits or we are told to stop.
+pub struct Supervisor {
+    services: Vec<Service>,
+    order: Vec<usize>,
+    notify_command: Vec<String>,
+    /// Where the config came from, to read it again on reload
+    config_path: PathBuf,
+    events_tx: mpsc::UnboundedSender<Event>,
+    events: mpsc::UnboundedReceiver<Event>,
+    requests_tx: mpsc::UnboundedSender<Request>,
+    requests: mpsc::UnboundedReceiver<Request>,
+}
+
+impl Supervisor {
+    pub fn new(config: Config, config_path: PathBuf) -> Self {
+        let order = config.start_order().expect("config was validated");
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (requests_tx, requests) = mpsc::unbounded_channel();
+        Self {
+            services: config.services.into_iter().map(Service::new).collect(),
+            order,
+            notify_command: config.notify_command,
+            config_path,
+            events_tx,
+            events,
+            requests_tx,
+            requests,
+        }
+    }
+
+    /// Where to send requests, like those from the control socket.
+    pub fn requests(&self) -> mpsc::UnboundedSender<Request> {
+        self.requests_tx.clone()
+    }
+
+    pub async fn run(self) {
+        self.run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("received ctrl-c, exiting");
+        })
+        .await;
+    }
+
+    /// Runs the services until `stop` completes, or a critical one exits.
+    /// A SIGHUP reloads the config.
+    pub async fn run_until(mut self, stop: impl Future<Output = ()>) {
+        tokio::pin!(stop);
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("failed to listen for SIGHUP");
+        let mut quit = self.start_pending();
+        while !quit {
+            tokio::select! {
+                Some(()) = hangups.recv() => {
+                    tracing::info!("received SIGHUP, reloading {}", self.config_path.display());
+                    if let Err(why) = self.reload() {
+                        tracing::error!("not reloading: {why}");
+                    }
+                    quit = self.start_pending();
+                }
+                Some(event) = self.events.recv() => {
+                    quit = self.handle(event) || self.start_pending();
+                }
+                Some(request) = self.requests.recv() => {
+                    self.answer(request);
+                    quit = self.start_pending();
+                }
+                _ = &mut stop => break,
+            }
+        }
+        self.shutdown().await;
+    }
+
+    /// Starts the pending services whose dependencies are ready; true if everything must stop.
+    fn start_pending(&mut self) -> bool {
+        for index in self.order.clone() {
+            if self.services[index].state != State::Pending || !self.dependencies_ready(index) {
+                continue;
+            }
+            if let Err(why) = self.spawn(index)
+                && self.exited(index, &format!("failed to start: {why}"), false)
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn dependencies_ready(&self, index: usize) -> bool {
+        let config = &self.services[index].config;
+        let state = |name: &String| self.service(name).state;
+        config
+            .requires
+            .iter()
+            .all(|name| matches!(state(name), State::Running { .. }))
+            && config.after.iter().all(|name| {
+                matches!(
+                    state(name),
+                    State::Running { .. } | State::Stopped | State::Failed
+                )
+            })
+    }
+
+    fn service(&self, name: &str) -> &Service {
+        self.services
+            .iter()
+            .find(|service| !service.removed && service.config.name == name)
+            .expect("config was validated")
+    }
+
+    /// Handles an event; true if everything must stop.
+    fn handle(&mut self, event: Event) -> bool {
+        match event {
+            Event::Exited {
+                index,
+                generation,
+                status,
+            } => {
+                if generation != self.services[index].generation {
+                    return false;
+                }
+                let service = &mut self.services[index];
+                let quit = if let State::Stopping { .. } = service.state {
+                    tracing::info!("{} stopped ({status})", service.config.name);
+                    service.state = if service.held {
+                        State::Stopped
+                    } else {
+                        State::Pending
+                    };
+                    false
+                } else {
+                    self.exited(index, &status.to_string(), status.success())
+                };
+                self.stop_dependents(index);
+                quit
+            }
+            Event::Ready {
+                index,
+                generation,
+                result,
+            } => {
+                let service = &mut self.services[index];
+                let State::Starting { pid } = service.state else {
+                    return false;
+                };
+                if generation != service.generation {
+                    return false;
+                }
+                match result {
+                    Ok(()) => {
+                        tracing::info!("{} is ready", service.config.name);
+                        service.state = State::Running { pid };
+                    }
+                    Err(why) => {
+                        // It exits, and that is handled like any crash
+                        tracing::error!("{} did not get ready: {why}", service.config.name);
+                        let _ = signal(service, Signal::SIGTERM);
+                        let timeout = Duration::from_millis(service.config.kill_timeout_ms);
+                        self.escalate(index, timeout, Signal::SIGKILL);
+                    }
+                }
+                false
+            }
+            Event::Restart { index, generation } => {
+                // A timer from before a newer process must not cut that one's wait short
+                if generation == self.services[index].generation
+                    && self.services[index].state == State::Waiting
+                {
+                    self.services[index].state = State::Pending;
+                }
+                false
+            }
+            Event::Escalate {
+                index,
+                generation,
+                signal,
+            } => {
+                let service = &self.services[index];
+                // A process that never got ready is still Starting while it is killed
+                if generation != service.generation
+                    || !matches!(
+                        service.state,
+                        State::Stopping { .. } | State::Starting { .. }
+                    )
+                {
+                    return false;
+                }
+                tracing::warn!("{} is still running", service.config.name);
+                let _ = self::signal(service, signal);
+                if signal == Signal::SIGTERM {
+                    let timeout = Duration::from_millis(service.config.kill_timeout_ms);
+                    self.escalate(index, timeout, Signal::SIGKILL);
+                }
+                false
+            }
+        }
+    }
+
+    fn answer(&mut self, Request { request, reply }: Request) {
+        let answer = match request {
+            OverseerRequest::Status => Ok(OverseerReply::Status(self.status())),
+            OverseerRequest::Start { service } => self.find(&service).map(|index| {
+                self.start(index);
+                OverseerReply::Done
+            }),
+            OverseerRequest::Stop { service } => self.find(&service).map(|index| {
+                self.stop(index);
+                OverseerReply::Done
+            }),
+            OverseerRequest::Restart { service } => self.find(&service).map(|index| {
+                self.restart(index);
+                OverseerReply::Done
+            }),
+            OverseerRequest::Logs { service, follow } => match self.find(&service) {
+                Ok(index) => {
+                    let logs = &self.services[index].logs;
+                    logs.lock()
+                        .expect("failed to lock logs")
+                        .replay(reply, follow);
+                    return;
+                }
+                Err(why) => Err(why),
+            },
+            OverseerRequest::Reload => match self.reload() {
+                Ok(changes) => {
+                    for change in changes {
+                        let _ = reply.send(OverseerReply::Log(change));
+                    }
+                    Ok(OverseerReply::Done)
+                }
+                Err(why) => Err(why),
+            },
+        };
+        let _ = reply.send(answer.unwrap_or_else(OverseerReply::Error));
+    }
+
+    fn find(&self, name: &str) -> Result<usize, String> {
+        self.services
+            .iter()
+            .position(|service| !service.removed && service.config.name == name)
+            .ok_or_else(|| format!("there is no service called {name:?}"))
+    }
+
+    fn status(&self) -> Vec<ServiceStatus> {
+        self.services
+            .iter()
+            .filter(|service| !service.removed || service.pid().is_some())
+            .map(|service| ServiceStatus {
+                name: service.config.name.clone(),
+                state: match service.state {
+                    State::Pending => ServiceState::Pending,
+                    State::Starting { .. } => ServiceState::Starting,
+                    State::Running { .. } => ServiceState::Running,
+                    State::Stopping { .. } => ServiceState::Stopping,
+                    State::Waiting => ServiceState::Waiting,
+                    State::Stopped => ServiceState::Stopped,
+                    State::Failed => ServiceState::Failed,
+                },
+                pid: service.pid(),
+                uptime_secs: service
+                    .pid()
+                    .and(service.started_at)
+                    .map(|started| started.elapsed().as_secs()),
+                restarts: service.restarts,
+            })
+            .collect()
+    }
+
+    /// Reads the config file again, and starts, stops and restarts the services whose
+    /// definitions were added, removed or changed. Returns what it did.
+    fn reload(&mut self) -> Result<Vec<String>, String> {
+        let config = Config::load(&self.config_path)?;
+        Ok(self.apply(config))
+    }
+
+    fn apply(&mut self, config: Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        let names: BTreeSet<_> = config
+            .services
+            .iter()
+            .map(|service| &service.name)
+            .collect();
+        for index in 0..self.services.len() {
+            let service = &mut self.services[index];
+            if service.removed || names.contains(&service.config.name) {
+                continue;
+            }
+            changes.push(format!("stopping {}, which is gone", service.config.name));
+            service.removed = true;
+            service.held = true;
+            match service.state {
+                State::Starting { .. } | State::Running { .. } => self.stop_process(index),
+                State::Pending | State::Waiting => service.state = State::Stopped,
+                State::Stopping { .. } | State::Stopped | State::Failed => {}
+            }
+        }
+
+        let order = config.start_order().expect("config was validated");
+        let mut indices = Vec::with_capacity(config.services.len());
+        for new in config.services {
+            let Ok(index) = self.find(&new.name) else {
+                changes.push(format!("starting {}", new.name));
+                indices.push(self.services.len());
+                self.services.push(Service::new(new));
+                continue;
+            };
+            indices.push(index);
+            let service = &mut self.services[index];
+            if service.config == new {
+                continue;
+            }
+            changes.push(format!("restarting {} as it changed", new.name));
+            service.crashes = Crashes::new(&new);
+            service.config = new;
+            match service.state {
+                State::Starting { .. } | State::Running { .. } => self.stop_process(index),
+                State::Waiting | State::Failed => service.state = State::Pending,
+                State::Stopped if !service.held => service.state = State::Pending,
+                State::Pending | State::Stopping { .. } | State::Stopped => {}
+            }
+        }
+        self.order = order.into_iter().map(|index| indices[index]).collect();
+        self.notify_command = config.notify_command;
+
+        if changes.is_empty() {
+            changes.push("no service changed".to_string());
+        }
+        for change in &changes {
+            tracing::info!("reload: {change}");
+        }
+        changes
+    }
+
+    /// Starts a service by hand, giving it a fresh start if it failed.
+    fn start(&mut self, index: usize) {
+        let service = &mut self.services[index];
+        tracing::info!("starting {} by hand", service.config.name);
+        service.held = false;
+        service.crashes.clear();
+        if matches!(service.state, State::Stopped | State::Failed) {
+            service.state = State::Pending;
+        }
+    }
+
+    /// Stops a service by hand, until it is started by hand again.
+    fn stop(&mut self, index: usize) {
+        let service = &mut self.services[index];
+        tracing::info!("stopping {} by hand", service.config.name);
+        service.held = true;
+        match service.state {
+            State::Starting { .. } | State::Running { .. } => self.stop_process(index),
+            State::Pending | State::Waiting => service.state = State::Stopped,
+            State::Stopping { .. } | State::Stopped | State::Failed => {}
+        }
+    }
+
+    fn restart(&mut self, index: usize) {
+        let service = &mut self.services[index];
+        tracing::info!("restarting {} by hand", service.config.name);
+        service.held = false;
+        service.crashes.clear();
+        match service.state {
+            State::Starting { .. } | State::Running { .. } => self.stop_process(index),
+            State::Waiting | State::Stopped | State::Failed => service.state = State::Pending,
+            State::Pending | State::Stopping { .. } => {}
+        }
+    }
+
+    /// Asks a service's process to quit with a SIGINT, then with a SIGTERM,
+    /// and kills it if it still runs after its timeouts.
+    fn stop_process(&mut self, index: usize) {
+        let service = &mut self.services[index];
+        let Some(pid) = service.pid() else {
+            return;
+        };
+        let _ = signal(service, Signal::SIGINT);
+        service.state = State::Stopping { pid };
+        let timeout = Duration::from_millis(service.config.stop_timeout_ms);
+        self.escalate(index, timeout, Signal::SIGTERM);
+    }
+
+    /// Sends the signal to the service's current process after the timeout,
+    /// unless it is gone by then.
+    fn escalate(&self, index: usize, timeout: Duration, signal: Signal) {
+        let generation = self.services[index].generation;
+        let events = self.events_tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let _ = events.send(Event::Escalate {
+                index,
+                generation,
+                signal,
+            });
+        });
+    }
+
+    /// Stops the services that require one that just exited; they start again once it is
+    /// ready again.
+    fn stop_dependents(&mut self, index: usize) {
+        let name = self.services[index].config.name.clone();
+        for dependent in 0..self.services.len() {
+            let service = &mut self.services[dependent];
+            if !service.config.requires.contains(&name) {
+                continue;
+            }
+            match service.state {
+                State::Starting { .. } | State::Running { .. } => {
+                    tracing::warn!("stopping {} since {name} exited", service.config.name);
+                    self.stop_process(dependent);
+                }
+                State::Waiting => service.state = State::Pending,
+                _ => {}
+            }
+        }
+    }
+
+    /// Decides what to do about a service that exited, or could not be started;
+    /// true if everything must stop.
+    fn exited(&mut self, index: usize, how: &str, success: bool) -> bool {
+        let service = &mut self.services[index];
+        let name = service.config.name.clone();
+        service.state = State::Stopped;
+        if service.config.critical {
+            tracing::error!("{name} exited ({how}) !! For safety, exiting !!");
+            return true;
+        }
+        let restart = match service.config.restart {
+            Restart::Always => true,
+            Restart::OnFailure => !success,
+            Restart::Never => false,
+        };
+        if !restart {
+            tracing::warn!("{name} exited ({how}), leaving it stopped");
+            return false;
+        }
+
+        match service.crashes.record(Instant::now()) {
+            Verdict::RestartIn(delay) => {
+                tracing::error!("{name} exited ({how}), spawning new one in {delay:?}");
+                service.state = State::Waiting;
+                let generation = service.generation;
+                let events = self.events_tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = events.send(Event::Restart { index, generation });
+                });
+            }
+            Verdict::GiveUp(crashes) => {
+                let details = format!(
+                    "{name} exited ({how}), {crashes} times in {}s; not restarting it",
+                    service.config.crash_window_secs
+                );
+                tracing::error!("{details}");
+                service.state = State::Failed;
+                self.notify(&format!("{name} failed"), &details);
+            }
+        }
+        false
+    }
+
+    fn spawn(&mut self, index: usize) -> io::Result<()> {
+        let service = &mut self.services[index];
+        let mut command = command(&service.config)?;
+        let notify_pipe = match service.config.ready {
+            Some(Ready::Notify) => {
+                let (reader, writer) = ready::notify_pipe()?;
+                ready::pass_notify_fd(&mut command, &writer);
+                Some((reader, writer))
+            }
+            _ => None,
+        };
+        let mut child = command.spawn_child(&service.config.name, service.logs.clone())?;
+        // Only the child writes to it, so the probe sees when it closes
+        let (notify, _writer) = notify_pipe.unzip();
+        let pid = child.child.id().expect("child was just spawned");
+        if service.generation > 0 {
+            service.restarts += 1;
+        }
+        service.generation += 1;
+        service.started_at = Some(Instant::now());
+        let generation = service.generation;
+
+        service.state = match service.config.ready.clone() {
+            None => State::Running { pid },
+            Some(probe) => {
+                let timeout = Duration::from_secs(service.config.ready_timeout_secs);
+                let events = self.events_tx.clone();
+                tokio::spawn(async move {
+                    let result = tokio::time::timeout(timeout, ready::wait(&probe, notify))
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                format!("not ready after {timeout:?}"),
+                            ))
+                        });
+                    let _ = events.send(Event::Ready {
+                        index,
+                        generation,
+                        result,
+                    });
+                });
+                State::Starting { pid }
+            }
+        };
+
+        let events = self.events_tx.clone();
+        tokio::spawn(async move {
+            let status = child.wait().await;
+            let _ = events.send(Event::Exited {
+                index,
+                generation,
+                status,
+            });
+        });
+        Ok(())
+    }
+
+    /// Tells someone about a failure with the notify command, if there is one.
+    fn notify(&self, summary: &str, details: &str) {
+        let Some((program, args)) = self.notify_command.split_first() else {
+            return;
+        };
+        let spawned = tokio::process::Command::new(program)
+            .args(args)
+            .arg(summary)
+            .arg(details)
+            .stdin(Stdio::null())
+            .spawn();
+        if let Err(why) = spawned {
+            tracing::warn!("failed to run {program} to notify about {summary}: {why}");
+        }
+    }
+
+    /// Stops every running service, each after those that depend on it, and as many at once
+    /// as that allows. Each is asked to quit with a SIGINT, then with a SIGTERM, and killed if
+    /// it still runs after its timeouts.
+    async fn shutdown(&mut self) {
+        for service in &mut self.services {
+            if matches!(service.state, State::Pending | State::Waiting) {
+                service.state = State::Stopped;
+            }
+        }
+        // When to send which signal next to the services asked to quit; none after SIGKILL
+        let mut stopping: Vec<Option<(Instant, Option<Signal>)>> = vec![None; self.services.len()];
+        loop {
+            for (index, stop) in stopping.iter_mut().enumerate() {
+                if stop.is_none()
+                    && self.services[index].pid().is_some()
+                    && !self.has_running_dependents(index)
+                {
+                    let timeout = self.services[index].config.stop_timeout_ms;
+                    self.signal_or_forget(index, Signal::SIGINT);
+                    *stop = Some((
+                        Instant::now() + Duration::from_millis(timeout),
+                        Some(Signal::SIGTERM),
+                    ));
+                }
+            }
+            let running: Vec<_> = (0..self.services.len())
+                .filter(|&index| self.services[index].pid().is_some())
+                .collect();
+            if running.is_empty() {
+                return;
+            }
+
+            let deadline = running
+                .iter()
+                .filter_map(|&index| stopping[index].map(|(at, _)| at))
+                .min()
+                .expect("a running service with nothing running that depends on it");
+            match tokio::time::timeout_at(deadline.into(), self.events.recv()).await {
+                Ok(Some(Event::Exited {
+                    index,
+                    generation,
+                    status,
+                })) => {
+                    let service = &mut self.services[index];
+                    if generation == service.generation {
+                        tracing::info!("{} exited ({status})", service.config.name);
+                        service.state = State::Stopped;
+                    }
+                }
+                // Too late to start anything, and quitting is taken care of here
+                Ok(Some(Event::Ready { .. } | Event::Restart { .. } | Event::Escalate { .. })) => {}
+                Ok(None) => unreachable!("we hold a sender"),
+                Err(_) => {
+                    let now = Instant::now();
+                    for index in running {
+                        let Some((at, next)) = stopping[index] else {
+                            continue;
+                        };
+                        if at > now {
+                            continue;
+                        }
+                        let config = &self.services[index].config;
+                        let timeout = Duration::from_millis(config.kill_timeout_ms);
+                        match next {
+                            Some(signal) => {
+                                tracing::warn!("{} is still running", config.name);
+                                let after = (signal == Signal::SIGTERM).then_some(Signal::SIGKILL);
+                                self.signal_or_forget(index, signal);
+                                stopping[index] = Some((now + timeout, after));
+                            }
+                            None => {
+                                tracing::error!(
+                                    "{} did not exit even when killed, leaving it",
+                                    config.name
+                                );
+                                self.services[index].state = State::Stopped;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether something that comes after or requires the service is still running.
+    fn has_running_dependents(&self, index: usize) -> bool {
+        let name = &self.services[index].config.name;
+        self.services.iter().any(|service| {
+            service.pid().is_some() && service.config.dependencies().any(|other| other == name)
+        })
+    }
+
+    /// Signals a service, and takes it as stopped if its process is gone already.
+    fn signal_or_forget(&mut self, index: usize, signal: Signal) {
+        if !self::signal(&self.services[index], signal) {
+            self.services[index].state = State::Stopped;
+        }
+    }
+}
+
+/// Signals the service's process; false if there is none any more.
+fn signal(service: &Service, signal: Signal) -> bool {
+    let Some(pid) = service.pid() else {
+        return false;
+    };
+    tracing::info!(
+        "killing child {pid} from {} with a {signal}",
+        service.config.name
+    );
+    match kill(Pid::from_raw(pid as i32), signal) {
+        Ok(()) => true,
+        // It exited, and we hear about that soon
+        Err(nix::Error::ESRCH) => false,
+        Err(why) => {
+            tracing::warn!("failed to signal {}: {why}", service.config.name);
+            true
+        }
+    }
+}
+/// The command that runs a service as configured.
+fn command(config: &ServiceConfig) -> io::Result<tokio::process::Command> {
+    let mut command = tokio::process::Command::new(&config.command);
+    command.args(&config.args).envs(&config.env);
+    if let Some(directory) = &config.working_directory {
+        command.current_dir(directory);
+    }
+    if let Some(name) = &config.user {
+        let user = User::from_name(name)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such user {name}"))
+        })?;
+        command
+            .uid(user.uid.as_raw())
+            .gid(user.gid.as_raw())
+            .env("HOME", &user.dir)
+            .env("USER", &user.name)
+            .env("LOGNAME", &user.name);
+    }
+    Ok(command)
+}
+
+struct ChildProcess {
+    name: String,
+    child: tokio::process::Child,
+}
+
+impl ChildProcess {
+    async fn wait(&mut self) -> ExitStatus {
+        self.child.wait().await.expect("failed to wait for child")
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.child.try_wait().expect("failed to wait process");
+        self.child.id().is_some()
+    }
+}
+
+impl Drop for ChildProcess {
+    fn drop(&mut self) {
+        let running = self.is_running();
+        tracing::info!("dropping child {} (still running? {running})", self.name,);
+    }
+}
+
+trait SpawnChild {
+    fn spawn_child(&mut self, name: &str, logs: Arc<Mutex<Logs>>) -> io::Result<ChildProcess>;
+}
+
+impl SpawnChild for tokio::process::Command {
+    fn spawn_child(&mut self, name: &str, logs: Arc<Mutex<Logs>>) -> io::Result<ChildProcess> {
+        self.kill_on_drop(true);
+
+        let mut child = self
+            // .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        tracing::info!("spawned child {}", name);
+        let stdout = tokio::io::BufReader::new(child.stdout.take().expect("no stdout"));
+        let stderr = tokio::io::BufReader::new(child.stderr.take().expect("no stderr"));
+
+        {
+            let name = name.to_owned();
+            let logs = logs.clone();
+            tokio::spawn(async move {
+                let mut stdout = stdout.lines();
+                let span = tracing::info_span!("stdout", name = name);
+                while let Some(line) = stdout.next_line().await.expect("failed to read stdout") {
+                    let _enter = span.enter();
+                    tracing::info!("{line}");
+                    logs.lock().expect("failed to lock logs").push(&line);
+                }
+
+                tracing::info!("{name} stdout closed");
+            });
+        }
+
+        {
+            let name = name.to_owned();
+            tokio::spawn(async move {
+                let mut stderr = stderr.lines();
+                let span = tracing::error_span!("stderr", name = name);
+                while let Some(line) = stderr.next_line().await.expect("failed to read stderr") {
+                    let _enter = span.enter();
+                    tracing::error!("{line}");
+                    logs.lock().expect("failed to lock logs").push(&line);
+                }
+
+                tracing::error!("{name} stderr closed");
+            });
+        }
+
+        Ok(ChildProcess {
+            name: name.to_string(),
+            child,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// A service running a bash script that notes in `log` when it gets a SIGINT, and then
+    /// runs `on_sigint`.
+    fn service(name: &str, log: &Path, on_sigint: &str, extra: &str) -> String {
+        format!(
+            r#"
+            [[service]]
+            name = "{name}"
+            command = "bash"
+            args = ["-c", "trap 'echo {name} >> {log}; {on_sigint}' INT; touch {log}.{name}; while :; do sleep 0.05; done"]
+            ready = {{ file = "{log}.{name}" }}
+            kill_timeout_ms = 300
+            {extra}
+            "#,
+            log = log.display()
+        )
+    }
+
+    async fn run_briefly(config: &str) -> Duration {
+        let supervisor = Supervisor::new(toml::from_str(config).unwrap(), PathBuf::new());
+        let started = Instant::now();
+        supervisor
+            .run_until(tokio::time::sleep(Duration::from_secs(1)))
+            .await;
+        started.elapsed() - Duration::from_secs(1)
+    }
+
+    #[tokio::test]
+    async fn stops_dependents_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let config = [
+            service("player", &log, "exit", r#"after = ["volume"]"#),
+            service("lid", &log, "exit", ""),
+            service("volume", &log, "exit", r#"requires = ["lid"]"#),
+        ]
+        .concat();
+        run_briefly(&config).await;
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "player\nvolume\nlid\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_independent_services_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        // Each takes a while to quit; one after the other, they would take twice as long
+        let config = [
+            service(
+                "brightness",
+                &log,
+                "sleep 0.5; exit",
+                "stop_timeout_ms = 2000",
+            ),
+            service("volume", &log, "sleep 0.5; exit", ""),
+        ]
+        .concat();
+        let took = run_briefly(&config).await;
+        assert!(took < Duration::from_millis(900), "took {took:?}");
+        assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn kills_services_that_do_not_quit() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        // Ignores both SIGINT and SIGTERM, so only SIGKILL gets rid of it
+        let stubborn = service("stubborn", &log, "trap : TERM", "stop_timeout_ms = 300");
+        let took = run_briefly(&stubborn).await;
+        assert!(took >= Duration::from_millis(600), "took {took:?}");
+        assert!(took < Duration::from_millis(1200), "took {took:?}");
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "stubborn\n");
+    }
+
+    #[tokio::test]
+    async fn kills_services_that_never_get_ready() {
+        let config = r#"
+            [[service]]
+            name = "stubborn"
+            command = "bash"
+            args = ["-c", "trap : TERM; while :; do sleep 0.05; done"]
+            ready = { file = "/nonexistent/ready" }
+            ready_timeout_secs = 1
+            kill_timeout_ms = 300
+            restart = "never"
+        "#;
+        let supervisor = Supervisor::new(toml::from_str(config).unwrap(), PathBuf::new());
+        let requests = supervisor.requests();
+        let (done_tx, done) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(supervisor.run_until(async {
+            let _ = done.await;
+        }));
+
+        // The probe gives up after 1s, and the SIGTERM it ignores is followed by a SIGKILL
+        tokio::time::sleep(Duration::from_millis(1600)).await;
+        let status = status(&requests).await;
+        assert_eq!(status[0].state, ServiceState::Stopped);
+        assert_eq!(status[0].pid, None);
+
+        done_tx.send(()).unwrap();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_skips_processes_that_are_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let mut supervisor = Supervisor::new(
+            toml::from_str(
+                &[
+                    service("lid", &log, "exit", ""),
+                    service("gone", &log, "exit", ""),
+                ]
+                .concat(),
+            )
+            .unwrap(),
+            PathBuf::new(),
+        );
+        supervisor.start_pending();
+        // A process we already reaped, as if it exited just before being signalled
+        let mut child = tokio::process::Command::new("true").spawn().unwrap();
+        let pid = child.id().unwrap();
+        child.wait().await.unwrap();
+        supervisor.services[1].state = State::Running { pid };
+
+        tokio::time::timeout(Duration::from_secs(1), supervisor.shutdown())
+            .await
+            .expect("shutdown got stuck");
+        assert!(
+            supervisor
+                .services
+                .iter()
+                .all(|service| service.state == State::Stopped)
+        );
+    }
+
+    async fn ask(
+        requests: &mpsc::UnboundedSender<Request>,
+        request: OverseerRequest,
+    ) -> Vec<OverseerReply> {
+        let (reply, mut replies) = mpsc::unbounded_channel();
+        requests.send(Request { request, reply }).unwrap();
+        let mut all = Vec::new();
+        while let Some(reply) = replies.recv().await {
+            all.push(reply);
+        }
+        all
+    }
+
+    async fn status(requests: &mpsc::UnboundedSender<Request>) -> Vec<ServiceStatus> {
+        match &ask(requests, OverseerRequest::Status).await[..] {
+            [OverseerReply::Status(status)] => status.clone(),
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_timers_from_before_a_restart_by_hand_are_ignored() {
+        let config = r#"
+            [[service]]
+            name = "tv"
+            command = "bash"
+            args = ["-c", "sleep 0.2; exit 1"]
+            restart_delay_ms = 1000
+        "#;
+        let supervisor = Supervisor::new(toml::from_str(config).unwrap(), PathBuf::new());
+        let requests = supervisor.requests();
+        let (done_tx, done) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(supervisor.run_until(async {
+            let _ = done.await;
+        }));
+
+        // It crashes at 0.2s, is restarted by hand at 0.4s and crashes again at 0.6s
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let restart = OverseerRequest::Restart {
+            service: "tv".to_string(),
+        };
+        assert_eq!(ask(&requests, restart).await, [OverseerReply::Done]);
+        // The first crash's timer goes off at 1.2s, the second's only at 1.6s
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let status = status(&requests).await;
+        assert_eq!(status[0].state, ServiceState::Waiting);
+        assert_eq!(status[0].restarts, 1);
+
+        done_tx.send(()).unwrap();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_starts_and_restarts_services_by_hand() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let config = [
+            service("lid", &log, "exit", ""),
+            service("volume", &log, "exit", r#"requires = ["lid"]"#),
+        ]
+        .concat();
+        let supervisor = Supervisor::new(toml::from_str(&config).unwrap(), PathBuf::new());
+        let requests = supervisor.requests();
+        let (done_tx, done) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(supervisor.run_until(async {
+            let _ = done.await;
+        }));
+        let settle = || tokio::time::sleep(Duration::from_millis(400));
+        let service = |service: &str| service.to_string();
+
+        settle().await;
+        let states = |status: Vec<ServiceStatus>| {
+            status
+                .into_iter()
+                .map(|service| (service.state, service.restarts))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            states(status(&requests).await),
+            [(ServiceState::Running, 0), (ServiceState::Running, 0)]
+        );
+
+        // Stopping the lid stops the volume too, until the lid is back
+        let stop = OverseerRequest::Stop {
+            service: service("lid"),
+        };
+        assert_eq!(ask(&requests, stop).await, [OverseerReply::Done]);
+        settle().await;
+        assert_eq!(
+            states(status(&requests).await),
+            [(ServiceState::Stopped, 0), (ServiceState::Pending, 0)]
+        );
+        let start = OverseerRequest::Start {
+            service: service("lid"),
+        };
+        assert_eq!(ask(&requests, start).await, [OverseerReply::Done]);
+        settle().await;
+        let restart = OverseerRequest::Restart {
+            service: service("volume"),
+        };
+        assert_eq!(ask(&requests, restart).await, [OverseerReply::Done]);
+        settle().await;
+        let status = status(&requests).await;
+        assert_eq!(
+            states(status.clone()),
+            [(ServiceState::Running, 1), (ServiceState::Running, 2)]
+        );
+        assert!(status[1].pid.is_some());
+
+        let logs = OverseerRequest::Logs {
+            service: service("lid"),
+            follow: false,
+        };
+        assert_eq!(ask(&requests, logs).await, []);
+        let unknown = OverseerRequest::Stop {
+            service: service("tv"),
+        };
+        assert!(matches!(
+            &ask(&requests, unknown).await[..],
+            [OverseerReply::Error(_)]
+        ));
+
+        done_tx.send(()).unwrap();
+        running.await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "lid\nvolume\nvolume\nvolume\nlid\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_only_touches_changed_services() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let path = dir.path().join("overseer.toml");
+        let lid = service("lid", &log, "exit", "");
+        let config = [
+            lid.clone(),
+            service("volume", &log, "exit", r#"requires = ["lid"]"#),
+            service("player", &log, "exit", r#"after = ["lid"]"#),
+        ]
+        .concat();
+        std::fs::write(&path, &config).unwrap();
+        let supervisor = Supervisor::new(Config::load(&path).unwrap(), path.clone());
+        let requests = supervisor.requests();
+        let (done_tx, done) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(supervisor.run_until(async {
+            let _ = done.await;
+        }));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let before = status(&requests).await;
+
+        let changed = [
+            lid,
+            service(
+                "volume",
+                &log,
+                "exit",
+                r#"requires = ["lid"]
+            env = { PULSE_SERVER = "/run/pulse" }"#,
+            ),
+            service("cec", &log, "exit", ""),
+        ]
+        .concat();
+        std::fs::write(&path, &changed).unwrap();
+        let log_line = |line: &str| OverseerReply::Log(line.to_string());
+        assert_eq!(
+            ask(&requests, OverseerRequest::Reload).await,
+            [
+                log_line("stopping player, which is gone"),
+                log_line("restarting volume as it changed"),
+                log_line("starting cec"),
+                OverseerReply::Done
+            ]
+        );
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let after = status(&requests).await;
+        let names: Vec<_> = after.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["lid", "volume", "cec"]);
+        assert_eq!(after[0].pid, before[0].pid);
+        assert_eq!(after[1].restarts, 1);
+        assert_eq!(after[2].state, ServiceState::Running);
+
+        // A broken config changes nothing
+        std::fs::write(&path, "[[service]]\nname = \"lid\"\n").unwrap();
+        assert!(matches!(
+            &ask(&requests, OverseerRequest::Reload).await[..],
+            [OverseerReply::Error(_)]
+        ));
+        let pids = |status: Vec<ServiceStatus>| {
+            status
+                .into_iter()
+                .map(|service| service.pid)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pids(status(&requests).await), pids(after));
+
+        done_tx.send(()).unwrap();
+        running.await.unwrap();
+        // The reload stops player and volume at about the same time, in no particular order,
+        // and cec only at shutdown
+        let stopped = std::fs::read_to_string(&log).unwrap();
+        let mut reloaded: Vec<_> = stopped.lines().take(2).collect();
+        reloaded.sort();
+        assert_eq!(reloaded, ["player", "volume"], "{stopped}");
+        assert!(
+            stopped.lines().skip(2).any(|line| line == "cec"),
+            "{stopped}"
+        );
+    }
+}
@@ -22,18 +22,30 @@ pub struct MpvPlayer {
     socket: OwnedWriteHalf,
     recv_loop: tokio::task::JoinHandle<()>,
     last_cmd_id: usize,
-    waiting_command_statuses:
-        Arc<Mutex<HashMap<usize, tokio::sync::oneshot::Sender<CommandResponse>>>>,
-    waiting_events: Arc<
-        Mutex<
-            Vec<(
-                Box<dyn Fn(&EventData) -> bool + 'static + Send>,
-                tokio::sync::oneshot::Sender<EventData>,
-            )>,
-        >,
-    >,
+    waiting_command_statuses: CommandWaiters,
+    waiting_events: EventWaiters,
+}
+
+impl Drop for MpvPlayer {
+    fn drop(&mut self) {
+        self.recv_loop.abort();
+    }
 }
 
+/// Pending command responses, keyed by request ID.
+type CommandWaiters = Arc<Mutex<HashMap<usize, tokio::sync::oneshot::Sender<CommandResponse>>>>;
+
+/// Pending event subscriptions: each predicate is checked against incoming events,
+/// and the first matching event is sent to its channel.
+type EventWaiters = Arc<
+    Mutex<
+        Vec<(
+            Box<dyn Fn(&EventData) -> bool + 'static + Send>,
+            tokio::sync::oneshot::Sender<EventData>,
+        )>,
+    >,
+>;
+
 #[derive(serde::Serialize, Debug)]
 struct CommandMsg {
     command: Vec<serde_json::Value>,
@@ -50,6 +62,7 @@ pub struct CommandResponse {
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
+#[allow(dead_code)]
 pub struct EventData {
     pub id: Option<usize>,
     pub event: String,
@@ -137,17 +150,8 @@ impl MpvPlayer {
 
     async fn recv_loop(
         socket: OwnedReadHalf,
-        waiting_command_statuses: Arc<
-            Mutex<HashMap<usize, tokio::sync::oneshot::Sender<CommandResponse>>>,
-        >,
-        waiting_events: Arc<
-            Mutex<
-                Vec<(
-                    Box<dyn Fn(&EventData) -> bool + 'static + Send>,
-                    tokio::sync::oneshot::Sender<EventData>,
-                )>,
-            >,
-        >,
+        waiting_command_statuses: CommandWaiters,
+        waiting_events: EventWaiters,
     ) {
         let mut reader = tokio::io::BufReader::new(socket);
         let mut buf = String::new();
@@ -177,7 +181,7 @@ impl MpvPlayer {
                     }
                     if let Some(tx) = waiting_command_statuses.lock().remove(&cmd.request_id) {
                         let id = cmd.request_id;
-                        if let Err(_) = tx.send(cmd) {
+                        if tx.send(cmd).is_err() {
                             warn!("Listener for command id {id} dropped before receiving response");
                         };
                     } else {
@@ -226,7 +230,7 @@ impl MpvPlayer {
     ) -> Result<(), std::io::Error> {
         let msg = serde_json::to_string(&msg).expect("failed to serialize message");
         self.socket.write_all(msg.as_bytes()).await?;
-        self.socket.write(b"\n").await?;
+        self.socket.write_all(b"\n").await?;
         self.socket.flush().await?;
         debug!("sent command");
         Ok(())
@@ -260,6 +264,7 @@ impl MpvPlayer {
         Ok(rx.await.expect("recv_loop closed"))
     }
 
+    #[allow(dead_code)]
     #[instrument(skip(self))]
     pub async fn osd_text(&mut self, text: &str) -> Result<(), std::io::Error> {
         self.send(CommandMsg {
@@ -309,6 +314,27 @@ impl MpvPlayer {
         Ok(())
     }
 
+    /// Sets the gain applied to the audio, in dB, through a volume filter that replaces any other
+    /// audio filters. It stays in place for the following files until it is set again.
+    #[instrument(skip(self))]
+    pub async fn set_audio_gain(&mut self, gain_db: f64) -> Result<(), std::io::Error> {
+        let filter = format!("@loudness:lavfi=[volume={gain_db:.2}dB]");
+        let response = self
+            .send_cmd(vec![
+                "set_property".json(),
+                "af".json(),
+                filter.as_str().json(),
+            ])
+            .await?;
+        if response.error != "success" {
+            return Err(std::io::Error::other(format!(
+                "mpv rejected audio filter {filter}: {}",
+                response.error
+            )));
+        }
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     pub async fn loadfile(&mut self, path: &str) -> Result<(), std::io::Error> {
         debug!("loading media");
@@ -321,19 +347,33 @@ impl MpvPlayer {
         &self,
         event_match: impl Fn(&EventData) -> bool + 'static + Send,
     ) -> Result<EventData, std::io::Error> {
+        let event = self.subscribe_event(event_match).await?;
+        debug!("event received: {event:?}");
+        Ok(event)
+    }
+
+    /// Like [`Self::wait_for_event`], but the subscription is made right away and the returned
+    /// future does not borrow the player, so it can be awaited without holding the player's lock.
+    pub fn subscribe_event<F>(
+        &self,
+        event_match: F,
+    ) -> impl Future<Output = Result<EventData, std::io::Error>> + use<F>
+    where
+        F: Fn(&EventData) -> bool + 'static + Send,
+    {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.waiting_events.lock().push((Box::new(event_match), tx));
         debug!("event subscription created");
-        let event = rx.await.map_err(|_| {
-            std::io::Error::new(
-                ErrorKind::Other,
-                "event receiver dropped -- probably event loop crashed or player exited",
-            )
-        })?;
-        debug!("event received: {event:?}");
-        Ok(event)
+        async move {
+            rx.await.map_err(|_| {
+                std::io::Error::other(
+                    "event receiver dropped -- probably event loop crashed or player exited",
+                )
+            })
+        }
     }
 
+    #[allow(dead_code)]
     #[instrument(skip(self))]
     pub async fn wait_for_event_by_name(&self, name: &str) -> Result<EventData, std::io::Error> {
         let name = name.to_string();
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use api_types::PlayerCommand;
+use tokio::{
+    io::AsyncBufReadExt,
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tracing::warn;
+
+/// Accepts commands on a Unix socket, one JSON [`PlayerCommand`] per line, from any number
+/// of connections, and hands them over in the order they arrive.
+pub async fn listen(path: &Path) -> std::io::Result<mpsc::UnboundedReceiver<PlayerCommand>> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("someone else is listening on {}", path.display()),
+        ));
+    }
+    // Stale file or doesn't exist; safe to remove
+    let _ = tokio::fs::remove_file(path).await;
+    let listener = UnixListener::bind(path)?;
+
+    let (tx, commands) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(why) => {
+                    warn!("failed to accept control connection: {why}");
+                    return;
+                }
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut lines = tokio::io::BufReader::new(stream).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    match serde_json::from_str(&line) {
+                        Ok(command) => {
+                            if tx.send(command).is_err() {
+                                return;
+                            }
+                        }
+                        Err(why) => warn!("ignoring invalid command {line:?}: {why}"),
+                    }
+                }
+            });
+        }
+    });
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_arrive_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/player-control.sock");
+        let mut commands = listen(&path).await.unwrap();
+        assert!(listen(&path).await.is_err());
+
+        let mut conn = UnixStream::connect(&path).await.unwrap();
+        conn.write_all(b"\"pause\"\nnonsense\n\"next\"\n")
+            .await
+            .unwrap();
+        assert_eq!(commands.recv().await, Some(PlayerCommand::Pause));
+        assert_eq!(commands.recv().await, Some(PlayerCommand::Next));
+    }
+}
@@ -0,0 +1,314 @@
+//! Integrated loudness as defined by ITU-R BS.1770-4 and EBU R128: K-weighted mean square
+//! over 400ms blocks (overlapping by 75%), gated at -70 LUFS and then 10 LU below the ungated mean.
+
+use std::{f64::consts::PI, path::Path, process::Stdio};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+/// Files are decoded to this rate and channel count. mpv plays them as stereo on the TV as well,
+/// so the downmix is what is actually heard.
+const DECODE_RATE: u32 = 48000;
+const DECODE_CHANNELS: usize = 2;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Blocks are 400ms long and start every 100ms.
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+const SUB_BLOCKS_PER_SECOND: u32 = 10;
+
+/// A second-order IIR filter, in direct form I.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two stages of the K-weighting filter (a high shelf modelling the head, then a high pass),
+/// with coefficients derived for any sample rate the same way libebur128 does.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Channel weights: 1 for front channels, 1.41 for surrounds and none for LFE,
+/// assuming the usual L R C LFE Ls Rs order for 5.1.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Measures integrated loudness of interleaved samples fed to it in any number of chunks.
+pub struct Meter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    sub_block_len: usize,
+    /// Frames and weighted sum of squares of the sub-block being filled
+    frames: usize,
+    sum: f64,
+    /// Weighted sums of squares of the last few complete sub-blocks
+    recent: Vec<f64>,
+    /// Mean power of every 400ms block
+    blocks: Vec<f64>,
+}
+
+impl Meter {
+    pub fn new(channels: usize, rate: u32) -> Self {
+        Self {
+            channels,
+            filters: vec![k_weighting(rate); channels],
+            weights: (0..channels)
+                .map(|channel| channel_weight(channel, channels))
+                .collect(),
+            sub_block_len: (rate / SUB_BLOCKS_PER_SECOND) as usize,
+            frames: 0,
+            sum: 0.0,
+            recent: Vec::with_capacity(SUB_BLOCKS_PER_BLOCK),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Adds interleaved samples; a trailing partial frame is ignored.
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let filtered = high_pass.process(shelf.process(sample as f64));
+                self.sum += self.weights[channel] * filtered * filtered;
+            }
+            self.frames += 1;
+            if self.frames == self.sub_block_len {
+                self.finish_sub_block();
+            }
+        }
+    }
+
+    fn finish_sub_block(&mut self) {
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            self.recent.remove(0);
+        }
+        self.recent.push(self.sum);
+        self.frames = 0;
+        self.sum = 0.0;
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            let block_len = (self.sub_block_len * SUB_BLOCKS_PER_BLOCK) as f64;
+            self.blocks
+                .push(self.recent.iter().sum::<f64>() / block_len);
+        }
+    }
+
+    /// Gated loudness of everything added so far, in LUFS,
+    /// or `None` if nothing was loud enough to measure (silence, or less than 400ms).
+    pub fn integrated_loudness(&self) -> Option<f64> {
+        let mean = |powers: &mut dyn Iterator<Item = f64>| {
+            let (sum, count) = powers.fold((0.0, 0), |(sum, count), p| (sum + p, count + 1));
+            (count > 0).then(|| sum / count as f64)
+        };
+        let above = |threshold: f64| {
+            self.blocks
+                .iter()
+                .copied()
+                .filter(move |&p| power_to_lufs(p) > threshold)
+        };
+        let ungated = mean(&mut above(ABSOLUTE_GATE_LUFS))?;
+        let relative_gate = power_to_lufs(ungated) + RELATIVE_GATE_LU;
+        let gated = mean(&mut above(relative_gate.max(ABSOLUTE_GATE_LUFS)))?;
+        Some(power_to_lufs(gated))
+    }
+}
+
+/// Measures a stream of interleaved little-endian f32 samples.
+pub async fn measure_stream(
+    mut reader: impl AsyncRead + Unpin,
+    channels: usize,
+    rate: u32,
+) -> std::io::Result<Option<f64>> {
+    let mut meter = Meter::new(channels, rate);
+    let mut buf = vec![0u8; 64 * 1024];
+    // Bytes of a sample split across two reads
+    let mut filled = 0;
+    let mut samples = Vec::with_capacity(buf.len() / 4);
+    loop {
+        let count = reader.read(&mut buf[filled..]).await?;
+        if count == 0 {
+            break;
+        }
+        filled += count;
+        let whole = filled - filled % 4;
+        samples.clear();
+        samples.extend(
+            buf[..whole]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().expect("4 bytes"))),
+        );
+        // Keep a partial frame for the next round as well
+        let frames = samples.len() - samples.len() % channels;
+        meter.add(&samples[..frames]);
+        let used = frames * 4;
+        buf.copy_within(used..filled, 0);
+        filled -= used;
+    }
+    Ok(meter.integrated_loudness())
+}
+
+/// Decodes the audio of a media file with ffmpeg and measures it.
+pub async fn measure_file(path: &Path) -> std::io::Result<Option<f64>> {
+    debug!("measuring loudness of {}", path.display());
+    let mut child = tokio::process::Command::new("ffmpeg")
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-vn", "-ac", &DECODE_CHANNELS.to_string()])
+        .args(["-ar", &DECODE_RATE.to_string(), "-f", "f32le", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let loudness = measure_stream(stdout, DECODE_CHANNELS, DECODE_RATE).await?;
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "ffmpeg could not decode {}: {status}",
+            path.display()
+        )));
+    }
+    Ok(loudness)
+}
+
+/// The gain that brings a file to the target loudness, in dB.
+/// Boosts are limited, so that quiet files do not bring up their noise floor too much;
+/// files without measurable audio are left alone.
+pub fn gain_db(loudness: Option<f64>, target: f64, max_boost: f64) -> f64 {
+    match loudness {
+        Some(loudness) => (target - loudness).min(max_boost),
+        None => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    /// A stereo 1 kHz sine, with the given peak level in dBFS, for `seconds`.
+    fn sine(rate: u32, dbfs: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let frames = (rate as f64 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s = (amplitude * (2.0 * PI * 1000.0 * i as f64 / rate as f64).sin()) as f32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn measure(rate: u32, samples: &[f32]) -> Option<f64> {
+        let mut meter = Meter::new(2, rate);
+        meter.add(samples);
+        meter.integrated_loudness()
+    }
+
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("no loudness measured");
+        assert!(
+            (actual - expected).abs() < 0.1,
+            "measured {actual} LUFS, expected {expected}"
+        );
+    }
+
+    /// EBU Tech 3341 case 1: a stereo 1 kHz sine at -23 dBFS reads -23 LUFS.
+    #[test]
+    fn sine_reads_its_level() {
+        assert_near(measure(48000, &sine(48000, -23.0, 5.0)), -23.0);
+        assert_near(measure(44100, &sine(44100, -33.0, 5.0)), -33.0);
+    }
+
+    /// EBU Tech 3341 case 3: quiet passages more than 10 LU down are gated out.
+    #[test]
+    fn quiet_passages_are_gated() {
+        let mut samples = sine(48000, -36.0, 10.0);
+        samples.extend(sine(48000, -23.0, 30.0));
+        samples.extend(sine(48000, -36.0, 10.0));
+        assert_near(measure(48000, &samples), -23.0);
+
+        let mut samples = sine(48000, -20.0, 20.0);
+        samples.extend(vec![0.0; 48000 * 2 * 10]);
+        assert_near(measure(48000, &samples), -20.0);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        assert_eq!(measure(48000, &vec![0.0; 48000 * 2 * 3]), None);
+        assert_eq!(measure(48000, &sine(48000, -20.0, 0.3)), None);
+    }
+
+    #[tokio::test]
+    async fn stream_is_measured_across_odd_reads() {
+        let samples = sine(48000, -18.0, 3.0);
+        // A tiny pipe, so that reads end in the middle of samples and frames
+        let (mut tx, rx) = tokio::io::duplex(7);
+        tokio::spawn(async move {
+            for sample in samples {
+                tx.write_all(&sample.to_le_bytes()).await.unwrap();
+            }
+        });
+        assert_near(measure_stream(rx, 2, 48000).await.unwrap(), -18.0);
+    }
+
+    #[test]
+    fn boost_is_limited() {
+        assert_eq!(gain_db(Some(-16.0), -23.0, 12.0), -7.0);
+        assert_eq!(gain_db(Some(-40.0), -23.0, 12.0), 12.0);
+        assert_eq!(gain_db(None, -23.0, 12.0), 0.0);
+    }
+}
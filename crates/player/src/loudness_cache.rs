@@ -0,0 +1,101 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utils::state_file;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct Entry {
+    /// Size and modification time of the file when it was measured, to notice it being replaced
+    size: u64,
+    modified: u64,
+    /// Integrated loudness in LUFS, or `None` if the file has no audible audio
+    loudness: Option<f64>,
+}
+
+/// Measured loudness of media files, optionally persisted to a JSON file,
+/// since measuring means decoding the whole file.
+pub struct LoudnessCache {
+    path: Option<PathBuf>,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl LoudnessCache {
+    /// Loads the cache from `path`. A missing or unreadable file starts empty.
+    pub async fn load(path: Option<PathBuf>) -> Self {
+        let entries = match path.clone() {
+            Some(path) => tokio::task::spawn_blocking(move || {
+                state_file::load_or_default(&path, "loudness cache", |message| warn!("{message}"))
+            })
+            .await
+            .expect("loading the loudness cache panicked"),
+            None => BTreeMap::new(),
+        };
+        Self { path, entries }
+    }
+
+    /// The loudness of a file if it was measured and has not changed since:
+    /// `Some(None)` means it was measured and found silent.
+    pub async fn get(&self, file: &Path) -> Option<Option<f64>> {
+        let entry = self.entries.get(file)?;
+        let (size, modified) = fingerprint(file).await.ok()?;
+        (entry.size == size && entry.modified == modified).then_some(entry.loudness)
+    }
+
+    pub async fn insert(&mut self, file: &Path, loudness: Option<f64>) -> std::io::Result<()> {
+        let (size, modified) = fingerprint(file).await?;
+        let entry = Entry {
+            size,
+            modified,
+            loudness,
+        };
+        self.entries.insert(file.to_path_buf(), entry);
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        let entries = self.entries.clone();
+        tokio::task::spawn_blocking(move || state_file::save(&path, &entries))
+            .await
+            .expect("saving the loudness cache panicked")
+    }
+}
+
+async fn fingerprint(file: &Path) -> std::io::Result<(u64, u64)> {
+    let metadata = tokio::fs::metadata(file).await?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((metadata.len(), modified.as_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_survive_reload_until_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("loudness.json");
+        let loud = dir.path().join("loud.mkv");
+        let silent = dir.path().join("silent.mkv");
+        std::fs::write(&loud, b"pretend video").unwrap();
+        std::fs::write(&silent, b"pretend video").unwrap();
+
+        let mut cache = LoudnessCache::load(Some(cache_path.clone())).await;
+        assert_eq!(cache.get(&loud).await, None);
+        cache.insert(&loud, Some(-14.5)).await.unwrap();
+        cache.insert(&silent, None).await.unwrap();
+
+        let cache = LoudnessCache::load(Some(cache_path)).await;
+        assert_eq!(cache.get(&loud).await, Some(Some(-14.5)));
+        assert_eq!(cache.get(&silent).await, Some(None));
+
+        std::fs::write(&loud, b"a different, longer video").unwrap();
+        assert_eq!(cache.get(&loud).await, None);
+    }
+}
@@ -2,11 +2,15 @@
 #![feature(sync_nonpoison)]
 use std::{path::PathBuf, sync::Arc, time::Duration};
 
+use api_types::PlayerCommand;
 use clap::Parser;
 use tokio::{sync::Mutex, task::JoinSet};
-use tracing::info;
+use tracing::{info, warn};
 
 mod api;
+mod control;
+mod loudness;
+mod loudness_cache;
 mod playlist;
 mod watch_later;
 
@@ -19,6 +23,23 @@ struct Args {
     /// File to read the playlist from
     #[clap(short = 'l', long)]
     pub playlist: PathBuf,
+
+    /// Integrated loudness (LUFS, EBU R128) to bring every file to, e.g. -23.
+    /// Files play at their own loudness if this is not given.
+    #[clap(long, allow_hyphen_values = true)]
+    pub normalize_to: Option<f64>,
+
+    /// The most a quiet file is turned up by when normalizing, in dB
+    #[clap(long, default_value = "12")]
+    pub max_boost_db: f64,
+
+    /// File to cache the measured loudness of files in
+    #[clap(long)]
+    pub loudness_cache: Option<PathBuf>,
+
+    /// Socket to take commands on, like pause or next from the TV's remote
+    #[clap(long, default_value = api_types::PLAYER_CONTROL_SOCKET)]
+    pub control_socket: PathBuf,
 }
 
 #[tokio::main]
@@ -37,36 +58,132 @@ async fn main() {
 
     println!("first file: {}", first_file.display());
 
-    let mut player = api::MpvPlayer::new(&first_file.to_string_lossy().to_string().as_str())
+    let mut player = api::MpvPlayer::new(first_file.to_string_lossy().to_string().as_str())
         .await
         .expect("failed to init player");
 
     let mut watch_later = watch_later::load_from_file(&args.play_state).await.unwrap();
     if let Some(data) = &watch_later {
-        player.restore_state(&data).await.unwrap();
+        player.restore_state(data).await.unwrap();
     }
 
     let player = Arc::new(Mutex::new(player));
 
     let mut tasks = JoinSet::new();
 
+    if let Some(target) = args.normalize_to {
+        let cache = Arc::new(Mutex::new(
+            loudness_cache::LoudnessCache::load(args.loudness_cache.clone()).await,
+        ));
+
+        // Measuring decodes whole files, so it runs in the background; it is done after one pass,
+        // so it is not one of the tasks whose exit stops the player.
+        {
+            let cache = cache.clone();
+            let items = playlist.items.clone();
+            tokio::spawn(async move {
+                for item in items {
+                    if cache.lock().await.get(&item).await.is_some() {
+                        continue;
+                    }
+                    match loudness::measure_file(&item).await {
+                        Ok(loudness) => {
+                            info!("loudness of {}: {loudness:?} LUFS", item.display());
+                            if let Err(why) = cache.lock().await.insert(&item, loudness).await {
+                                warn!("failed to save loudness of {}: {why}", item.display());
+                            }
+                        }
+                        Err(why) => warn!("failed to measure {}: {why}", item.display()),
+                    }
+                }
+            });
+        }
+
+        let player = player.clone();
+        let max_boost = args.max_boost_db;
+        tasks.spawn(tokio::spawn(async move {
+            loop {
+                // Subscribe before looking at the path, so that a file that loads
+                // while the gain is being set still gets its own gain
+                let loaded = player
+                    .lock()
+                    .await
+                    .subscribe_event(|e| e.event == "file-loaded");
+                match player.lock().await.get_path().await {
+                    // Nothing is loaded yet
+                    Ok(path) if path.is_empty() => {}
+                    Ok(path) => {
+                        let loudness = cache.lock().await.get(path.as_ref()).await;
+                        let gain = match loudness {
+                            Some(loudness) => loudness::gain_db(loudness, target, max_boost),
+                            None => {
+                                info!("{path} has not been measured yet, playing it as is");
+                                0.0
+                            }
+                        };
+                        info!("setting gain of {gain:.2} dB for {path}");
+                        if let Err(why) = player.lock().await.set_audio_gain(gain).await {
+                            warn!("failed to set gain: {why}");
+                        }
+                    }
+                    Err(why) => warn!("failed to get the path of the current file: {why}"),
+                }
+                loaded.await.unwrap();
+            }
+        }));
+    }
+
     {
         let player = player.clone();
+        let playlist = playlist.clone();
+        let mut commands = control::listen(&args.control_socket)
+            .await
+            .expect("failed to create control socket");
         tasks.spawn(tokio::spawn(async move {
             let mut lid_status = lid_subscriber::AsyncLidSubscriber::new()
                 .await
                 .expect("failed to subscribe to lid status");
             let mut play_state = true;
+            // Paused by a command, until one says play or the lid is opened again
+            let mut held = false;
             loop {
                 let mut interval = tokio::time::interval(Duration::from_millis(7250));
                 interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
                 tokio::select! {
                     Some(state) = lid_status.next() => {
                         play_state = state.lid_open;
-                        player.lock().await.set_paused(!play_state).await.unwrap();
+                        if play_state {
+                            held = false;
+                        }
+                        player.lock().await.set_paused(!play_state || held).await.unwrap();
+                    }
+                    Some(command) = commands.recv() => {
+                        info!("received command {command:?}");
+                        match command {
+                            PlayerCommand::Play => held = false,
+                            PlayerCommand::Pause => held = true,
+                            PlayerCommand::TogglePause => held = !held,
+                            PlayerCommand::Next | PlayerCommand::Previous => {
+                                let current_file: PathBuf =
+                                    player.lock().await.get_path().await.unwrap().into();
+                                let file = if command == PlayerCommand::Next {
+                                    playlist.next_file(&current_file)
+                                } else {
+                                    playlist.previous_file(&current_file)
+                                };
+                                info!("loading file: {file:?}");
+                                player
+                                    .lock()
+                                    .await
+                                    .loadfile(file.to_string_lossy().to_string().as_str())
+                                    .await
+                                    .unwrap();
+                            }
+                        }
+                        player.lock().await.set_paused(!play_state || held).await.unwrap();
                     }
                     _ = interval.tick() => {
-                        player.lock().await.set_paused(!play_state).await.unwrap();
+                        player.lock().await.set_paused(!play_state || held).await.unwrap();
                     }
                 }
             }
@@ -139,5 +256,5 @@ async fn main() {
         }
     }
 
-    let _ = player.lock().await.send_quit();
+    let _ = player.lock().await.send_quit().await;
 }
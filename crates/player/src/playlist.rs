@@ -40,4 +40,22 @@ impl Playlist {
         // By default, return the first item.
         self.items[0].clone()
     }
+
+    pub fn previous_file(&self, current_file: &PathBuf) -> PathBuf {
+        // Same as next_file, walking the playlist backwards
+        let position = self
+            .items
+            .iter()
+            .position(|item| item == current_file)
+            .or_else(|| {
+                self.items
+                    .iter()
+                    .position(|item| item.file_name() == current_file.file_name())
+            });
+        match position {
+            Some(idx) if idx > 0 => self.items[idx - 1].clone(),
+            // The first item, or one that is not in the playlist: wrap around to the last one
+            _ => self.items[self.items.len() - 1].clone(),
+        }
+    }
 }
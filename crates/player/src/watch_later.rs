@@ -9,6 +9,7 @@ pub struct WatchLaterState {
 }
 
 impl WatchLaterState {
+    #[allow(dead_code)]
     pub fn new(path: PathBuf) -> Self {
         Self { path, time: 0.0 }
     }
@@ -0,0 +1,158 @@
+use std::{collections::BTreeSet, path::Path, path::PathBuf};
+
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::policy::Policy;
+
+/// The plugs to manage, from a TOML file with a `[[plug]]` table for each:
+///
+/// ```toml
+/// [[plug]]
+/// name = "soundbar"
+/// switch = { backend = "shelly", url = "http://192.168.1.41" }
+/// policy = { follow = "lid", on_delay_ms = 30000, off_during = ["23:00-07:00"], min_on_watts = 3.0 }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "plug")]
+    pub plugs: Vec<PlugConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PlugConfig {
+    /// Name for logs and the status socket
+    pub name: String,
+    pub switch: SwitchConfig,
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+/// How a plug is switched; see the backends' command line options for what the fields mean.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SwitchConfig {
+    Ha {
+        url: Url,
+        entity: String,
+        token_file: PathBuf,
+        power_entity: Option<String>,
+        energy_entity: Option<String>,
+    },
+    Tasmota {
+        url: Url,
+        relay: Option<u8>,
+        password_file: Option<PathBuf>,
+    },
+    Shelly {
+        url: Url,
+        #[serde(default)]
+        switch_id: u32,
+    },
+    Mqtt {
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        topic: String,
+        #[serde(default = "default_payload_on")]
+        payload_on: String,
+        #[serde(default = "default_payload_off")]
+        payload_off: String,
+        #[serde(default)]
+        retain: bool,
+        username: Option<String>,
+        password_file: Option<PathBuf>,
+        state_topic: Option<String>,
+    },
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_payload_on() -> String {
+    "ON".to_string()
+}
+
+fn default_payload_off() -> String {
+    "OFF".to_string()
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|why| format!("cannot read {}: {why}", path.display()))?;
+        Self::parse(&text).map_err(|why| format!("invalid config {}: {why}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|why| why.to_string())?;
+        if config.plugs.is_empty() {
+            return Err("no plugs configured".to_string());
+        }
+        let mut names = BTreeSet::new();
+        for plug in &config.plugs {
+            if !names.insert(&plug.name) {
+                return Err(format!("two plugs are called {:?}", plug.name));
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Follow;
+
+    #[test]
+    fn parses_plugs_with_policies() {
+        let config = Config::parse(
+            r#"
+            [[plug]]
+            name = "tv"
+            switch = { backend = "ha", url = "http://ha.local:8123", entity = "switch.tv", token_file = "/etc/ha-token" }
+            policy = { off_delay_ms = 30000, min_on_watts = 15 }
+
+            [[plug]]
+            name = "reading lamp"
+            switch = { backend = "mqtt", host = "broker.local", topic = "cmnd/lamp/POWER" }
+            policy = { follow = "inverse_lid", off_during = ["23:00-07:00"] }
+            "#,
+        )
+        .unwrap();
+
+        let [tv, lamp] = &config.plugs[..] else {
+            panic!("expected two plugs");
+        };
+        assert_eq!(tv.policy.follow, Follow::Lid);
+        assert_eq!(tv.policy.off_delay_ms, 30000);
+        assert_eq!(tv.policy.min_on_watts(), 15.0);
+        assert!(matches!(&tv.switch, SwitchConfig::Ha { entity, .. } if entity == "switch.tv"));
+        assert_eq!(lamp.policy.follow, Follow::InverseLid);
+        assert_eq!(lamp.policy.off_during.len(), 1);
+        assert!(matches!(
+            &lamp.switch,
+            SwitchConfig::Mqtt { port: 1883, payload_on, .. } if payload_on == "ON"
+        ));
+    }
+
+    #[test]
+    fn rejects_mistakes() {
+        // Unknown backend, unknown field, duplicate names, nothing at all
+        assert!(Config::parse("[[plug]]\nname = \"tv\"\nswitch = { backend = \"x10\" }").is_err());
+        assert!(
+            Config::parse(
+                "[[plug]]\nname = \"tv\"\nswitch = { backend = \"shelly\", url = \"http://s\", relay = 1 }"
+            )
+            .is_err()
+        );
+        let shelly =
+            "[[plug]]\nname = \"tv\"\nswitch = { backend = \"shelly\", url = \"http://s\" }\n";
+        assert!(Config::parse(shelly).is_ok());
+        assert!(Config::parse(&format!("{shelly}{shelly}")).is_err());
+        assert!(Config::parse("plug = []").is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+use api_types::{LidState, PendingChange};
+
+use crate::{
+    energy::EnergyLog,
+    policy::Policy,
+    reconcile::{self, RetryPolicy},
+    status::PlugReporter,
+    switch::PowerSwitch,
+};
+
+/// What a plug controller acts on.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Lid(LidState),
+    /// The plug reported its state by itself
+    Reported(bool),
+}
+
+/// A plug, and when it should be on.
+pub struct Plug {
+    pub name: String,
+    pub switch: Box<dyn PowerSwitch>,
+    pub policy: Policy,
+}
+
+/// How plugs with a power meter are read.
+#[derive(Clone)]
+pub struct Metering {
+    /// Time between two readings. A plug that was just turned on is given this long
+    /// to start drawing power before it is flagged.
+    pub interval: Duration,
+    pub energy: Arc<Mutex<EnergyLog>>,
+}
+
+fn local_time() -> chrono::NaiveTime {
+    chrono::Local::now().time()
+}
+
+/// Keeps the plug in the state its policy asks for, until the events stop.
+/// A change happens once the plug wanted it for the policy's delay, and is called off
+/// if the plug stops wanting it in the meantime. When someone switches the plug by hand,
+/// it is left that way until the policy asks for something new.
+/// Plugs with a power meter are also read every so often, to keep track of the energy they use
+/// and to notice when one is on with nothing running on it.
+pub fn run(
+    plug: &mut Plug,
+    events: &Receiver<Event>,
+    retry: RetryPolicy,
+    metering: &Metering,
+    status: &PlugReporter,
+) {
+    let name = plug.name.clone();
+    let mut lid_open = None;
+    // The state the plug is being switched to, once there is one
+    let mut wanted = None;
+    // A change waiting out its delay
+    let mut pending: Option<(bool, Instant)> = None;
+    let mut failures = 0;
+    let mut retry_at: Option<Instant> = None;
+    // The state the plug was last seen in, either switched by us or reported by itself
+    let mut seen_on = None;
+    let mut switched_at = Instant::now();
+    // Until it turns out the plug has no meter
+    let mut read_at = Some(Instant::now());
+    let mut last_reading: Option<Instant> = None;
+    let mut no_load = false;
+    // What was used today before a restart
+    let today = chrono::Local::now().date_naive();
+    let used_today = metering
+        .energy
+        .lock()
+        .expect("failed to lock energy log")
+        .total(&name, today);
+    status.update(|status| status.energy_today_kwh = used_today);
+    loop {
+        // Wait for the lid, or until it is time to try again, to make a pending change,
+        // for the policy's time of day ranges to begin or end, or to read the meter
+        let boundary = lid_open
+            .and(plug.policy.until_next_boundary(local_time()))
+            .map(|until| Instant::now() + until);
+        let wake_at = [retry_at, pending.map(|(_, at)| at), boundary, read_at]
+            .into_iter()
+            .flatten()
+            .min();
+        let event = match wake_at {
+            None => match events.recv() {
+                Ok(state) => Some(state),
+                Err(_) => return,
+            },
+            Some(wake_at) => {
+                match events.recv_timeout(wake_at.saturating_duration_since(Instant::now())) {
+                    Ok(state) => Some(state),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        };
+        if read_at.is_some_and(|at| at <= Instant::now()) {
+            let now = Instant::now();
+            read_at = Some(now + metering.interval);
+            match plug.switch.meter() {
+                Ok(Some(reading)) => {
+                    let elapsed = last_reading.map(|at| now - at);
+                    last_reading = Some(now);
+                    let mut energy = metering.energy.lock().expect("failed to lock energy log");
+                    let today =
+                        energy.record(&name, chrono::Local::now().date_naive(), reading, elapsed);
+                    if let Err(why) = energy.save() {
+                        eprintln!("{name}: failed to save energy log: {why}");
+                    }
+                    drop(energy);
+
+                    // A plug that was just turned on is given time to start drawing power,
+                    // and one that is not on, wanted or not, cannot be judged
+                    let flagged = wanted == Some(true)
+                        && seen_on == Some(true)
+                        && now - switched_at >= metering.interval
+                        && reading.watts < plug.policy.min_on_watts();
+                    if flagged != no_load {
+                        no_load = flagged;
+                        if no_load {
+                            eprintln!(
+                                "{name}: on but drawing only {}W, the TV is not actually on",
+                                reading.watts
+                            );
+                        } else if wanted == Some(true) {
+                            println!("{name}: drawing {}W now", reading.watts);
+                        }
+                    }
+                    status.update(|status| {
+                        status.power_watts = Some(reading.watts);
+                        status.energy_today_kwh = Some(today);
+                        status.no_load = no_load;
+                    });
+                }
+                Ok(None) => read_at = None,
+                Err(why) => {
+                    eprintln!(
+                        "{name}: failed to read meter of {}: {why}",
+                        plug.switch.name()
+                    );
+                }
+            }
+        }
+        match event {
+            Some(Event::Lid(state)) => {
+                println!("{name}: received new state: {state:?}");
+                lid_open = Some(state.lid_open);
+            }
+            Some(Event::Reported(on)) => {
+                if seen_on != Some(on) {
+                    seen_on = Some(on);
+                    switched_at = Instant::now();
+                }
+                if wanted == Some(on) && retry_at.take().is_some() {
+                    println!("{name}: turned {} after all", on_off(on));
+                    failures = 0;
+                } else if wanted.is_some_and(|wanted| wanted != on) && retry_at.is_none() {
+                    println!("{name}: switched {} by hand, leaving it", on_off(on));
+                }
+                status.update(|status| {
+                    status.reported_on = Some(on);
+                    status.in_sync = wanted == Some(on);
+                    if status.in_sync {
+                        status.failures = 0;
+                        status.persistent_failure = false;
+                        status.last_error = None;
+                    }
+                });
+                continue;
+            }
+            None => {}
+        }
+        let Some(lid_open) = lid_open else {
+            continue;
+        };
+
+        let now = Instant::now();
+        let desired = plug.policy.wants_on(lid_open, local_time());
+        if let Some((on, _)) = pending
+            && on != desired
+        {
+            println!("{name}: not turning {} after all", on_off(on));
+            pending = None;
+            status.update(|status| status.pending = None);
+        }
+        if wanted != Some(desired) && pending.is_none() {
+            let delay = plug.policy.delay(desired);
+            if !delay.is_zero() {
+                println!("{name}: turning {} in {delay:?}", on_off(desired));
+                status.update(|status| {
+                    status.pending = Some(PendingChange {
+                        on: desired,
+                        at: api_types::now() + delay,
+                    });
+                });
+            }
+            pending = Some((desired, now + delay));
+        }
+
+        let mut switch_now = retry_at.is_some_and(|at| at <= now);
+        if let Some((on, at)) = pending
+            && at <= now
+        {
+            pending = None;
+            wanted = Some(on);
+            failures = 0;
+            switch_now = true;
+            status.update(|status| {
+                status.wanted_on = Some(on);
+                status.in_sync = false;
+                status.pending = None;
+                status.failures = 0;
+                status.persistent_failure = false;
+            });
+        }
+        let (Some(on), true) = (wanted, switch_now) else {
+            continue;
+        };
+
+        match reconcile::apply(plug.switch.as_mut(), on) {
+            Ok(()) => {
+                if failures > 0 {
+                    println!("{name}: switched {} after {failures} failures", on_off(on));
+                }
+                failures = 0;
+                retry_at = None;
+                if seen_on != Some(on) {
+                    seen_on = Some(on);
+                    switched_at = Instant::now();
+                }
+                status.update(|status| {
+                    status.in_sync = true;
+                    status.failures = 0;
+                    status.persistent_failure = false;
+                    status.last_error = None;
+                });
+            }
+            Err(why) => {
+                failures += 1;
+                let backoff = retry.backoff(failures);
+                eprintln!(
+                    "{name}: failed to switch {} (attempt {failures}): {why}; retrying in {backoff:?}",
+                    plug.switch.name(),
+                );
+                if failures == retry.attempts {
+                    eprintln!("{name}: keeps failing, reporting it");
+                }
+                retry_at = Some(Instant::now() + backoff);
+                status.update(|status| {
+                    status.in_sync = false;
+                    status.failures = failures;
+                    status.persistent_failure = retry.is_persistent(failures);
+                    status.last_error = Some(why.to_string());
+                });
+            }
+        }
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use api_types::PlugStatus;
+
+    use super::*;
+    use crate::{policy::Follow, status::StatusPublisher, switch::Reading};
+
+    /// A plug that records what it is told, with a power meter if it is given a reading.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        commands: Arc<Mutex<Vec<bool>>>,
+        watts: Option<f64>,
+    }
+
+    impl PowerSwitch for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+            self.commands.lock().unwrap().push(on);
+            Ok(())
+        }
+
+        fn power(&mut self) -> std::io::Result<Option<bool>> {
+            Ok(self.commands.lock().unwrap().last().copied())
+        }
+
+        fn meter(&mut self) -> std::io::Result<Option<Reading>> {
+            Ok(self.watts.map(|watts| Reading {
+                watts,
+                total_kwh: None,
+            }))
+        }
+    }
+
+    fn lid(open: bool) -> Event {
+        Event::Lid(LidState {
+            lid_open: open,
+            changed_at: api_types::now(),
+        })
+    }
+
+    /// Runs a plug with the given policy on lid states sent 50ms apart,
+    /// and returns the commands it got by `end_ms` after the first state.
+    fn commands(policy: Policy, states: &[bool], end_ms: u64) -> Vec<bool> {
+        let events: Vec<_> = states.iter().map(|&open| lid(open)).collect();
+        commands_for(policy, &events, end_ms)
+    }
+
+    /// Like `commands`, with any events.
+    fn commands_for(policy: Policy, events: &[Event], end_ms: u64) -> Vec<bool> {
+        let recorder = Recorder::default();
+        drive(recorder.clone(), policy, events, end_ms);
+        recorder.commands.lock().unwrap().clone()
+    }
+
+    /// Runs a plug on `events` sent 50ms apart, with its meter read every 20ms,
+    /// and returns its status after `end_ms`.
+    fn drive(recorder: Recorder, policy: Policy, events: &[Event], end_ms: u64) -> PlugStatus {
+        let (tx, rx) = mpsc::channel();
+        let status = StatusPublisher::new(["test".to_string()]);
+        let controller = {
+            let mut plug = Plug {
+                name: "test".to_string(),
+                switch: Box::new(recorder),
+                policy,
+            };
+            let retry = RetryPolicy {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            };
+            let metering = Metering {
+                interval: Duration::from_millis(20),
+                energy: Arc::new(Mutex::new(EnergyLog::load(None))),
+            };
+            let reporter = status.reporter(0);
+            std::thread::spawn(move || run(&mut plug, &rx, retry, &metering, &reporter))
+        };
+        let started = Instant::now();
+        for event in events {
+            tx.send(event.clone()).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        std::thread::sleep(Duration::from_millis(end_ms).saturating_sub(started.elapsed()));
+        drop(tx);
+        controller.join().unwrap();
+        status.reporter(0).status()
+    }
+
+    /// Long next to the 50ms between events, so that the tests hold up on a busy machine:
+    /// what happens before the delay is checked for well before it ends, and what happens
+    /// after it is checked for well after.
+    const DELAY_MS: u64 = 1000;
+
+    fn off_delay() -> Policy {
+        Policy {
+            off_delay_ms: DELAY_MS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn turns_on_at_once_and_off_after_delay() {
+        // Still on shortly after closing
+        assert_eq!(commands(off_delay(), &[true, false], 400), [true]);
+        assert_eq!(
+            commands(off_delay(), &[true, false], DELAY_MS + 600),
+            [true, false]
+        );
+    }
+
+    #[test]
+    fn reopening_cancels_off() {
+        assert_eq!(
+            commands(off_delay(), &[true, false, true], DELAY_MS + 600),
+            [true]
+        );
+    }
+
+    #[test]
+    fn inverse_with_on_delay() {
+        let policy = Policy {
+            follow: Follow::InverseLid,
+            on_delay_ms: DELAY_MS,
+            ..Default::default()
+        };
+        assert_eq!(commands(policy.clone(), &[true, false], 400), [false]);
+        assert_eq!(
+            commands(policy, &[true, false], DELAY_MS + 600),
+            [false, true]
+        );
+    }
+
+    #[test]
+    fn off_during_keeps_plug_off() {
+        // A range covering the whole day but a minute, away from the current time
+        let now = local_time();
+        let policy = Policy {
+            off_during: vec![crate::policy::TimeRange {
+                start: now - chrono::Duration::minutes(1),
+                end: now - chrono::Duration::minutes(2),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(commands(policy, &[true], 400), [false]);
+    }
+
+    #[test]
+    fn manual_change_is_left_alone() {
+        let events = [lid(true), Event::Reported(false)];
+        assert_eq!(commands_for(Policy::default(), &events, 500), [true]);
+        // Until the lid asks for something else
+        let events = [lid(true), Event::Reported(false), lid(false), lid(true)];
+        assert_eq!(
+            commands_for(Policy::default(), &events, 600),
+            [true, false, true]
+        );
+    }
+
+    #[test]
+    fn flags_plug_that_is_on_without_load() {
+        let standby = Recorder {
+            watts: Some(0.5),
+            ..Default::default()
+        };
+        let status = drive(standby, Policy::default(), &[lid(true)], 500);
+        assert!(status.no_load);
+        assert_eq!(status.power_watts, Some(0.5));
+        assert!(status.energy_today_kwh.is_some());
+
+        let watching = Recorder {
+            watts: Some(80.0),
+            ..Default::default()
+        };
+        assert!(!drive(watching, Policy::default(), &[lid(true)], 500).no_load);
+        // Off, as it should be
+        let off = Recorder {
+            watts: Some(0.0),
+            ..Default::default()
+        };
+        assert!(!drive(off, Policy::default(), &[lid(false)], 500).no_load);
+    }
+}
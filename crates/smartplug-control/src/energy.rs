@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utils::state_file;
+
+use crate::switch::Reading;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct PlugEnergy {
+    /// The meter's running total at the last reading, to take the next one from
+    last_total_kwh: Option<f64>,
+    /// Energy used by local day
+    daily_kwh: BTreeMap<NaiveDate, f64>,
+}
+
+/// Energy used by each plug per day, optionally persisted to a JSON file
+/// so that the totals survive restarts.
+pub struct EnergyLog {
+    path: Option<PathBuf>,
+    plugs: BTreeMap<String, PlugEnergy>,
+}
+
+impl EnergyLog {
+    /// Loads the log from `path`. A missing or unreadable file starts empty.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let plugs = match &path {
+            Some(path) => {
+                state_file::load_or_default(path, "energy log", |message| eprintln!("{message}"))
+            }
+            None => BTreeMap::new(),
+        };
+        Self { path, plugs }
+    }
+
+    /// Adds what the plug used since its last reading to `day`, and returns that day's total.
+    /// Meters that keep a running total are followed by it; for the others, the power is
+    /// assumed to have been drawn for the `elapsed` time since the last reading.
+    pub fn record(
+        &mut self,
+        plug: &str,
+        day: NaiveDate,
+        reading: Reading,
+        elapsed: Option<Duration>,
+    ) -> f64 {
+        let energy = self.plugs.entry(plug.to_string()).or_default();
+        let used = match (reading.total_kwh, energy.last_total_kwh) {
+            // The meter was reset (or replaced) in the meantime, and counted from zero since
+            (Some(total), Some(last)) if total < last => total,
+            (Some(total), Some(last)) => total - last,
+            // Nothing to count from yet
+            (Some(_), None) => 0.0,
+            (None, _) => {
+                reading.watts.max(0.0) * elapsed.unwrap_or_default().as_secs_f64() / 3_600_000.0
+            }
+        };
+        energy.last_total_kwh = reading.total_kwh;
+        let today = energy.daily_kwh.entry(day).or_default();
+        *today += used;
+        *today
+    }
+
+    /// Energy the plug used on `day`, if it was seen that day.
+    pub fn total(&self, plug: &str, day: NaiveDate) -> Option<f64> {
+        self.plugs.get(plug)?.daily_kwh.get(&day).copied()
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => state_file::save(path, &self.plugs),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(watts: f64, total_kwh: Option<f64>) -> Reading {
+        Reading { watts, total_kwh }
+    }
+
+    #[test]
+    fn follows_meter_totals_across_days_and_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("energy.json");
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 12).unwrap();
+        let tuesday = monday.succ_opt().unwrap();
+
+        let mut log = EnergyLog::load(Some(path.clone()));
+        assert_eq!(
+            log.record("tv", monday, reading(80.0, Some(10.0)), None),
+            0.0
+        );
+        log.record("tv", monday, reading(80.0, Some(10.5)), None);
+        log.save().unwrap();
+
+        let mut log = EnergyLog::load(Some(path));
+        assert_eq!(log.total("tv", monday), Some(0.5));
+        assert_eq!(
+            log.record("tv", tuesday, reading(80.0, Some(11.0)), None),
+            0.5
+        );
+        // Reset to zero, and counted up to 0.25 since
+        assert_eq!(
+            log.record("tv", tuesday, reading(80.0, Some(0.25)), None),
+            0.75
+        );
+        assert_eq!(log.total("tv", monday), Some(0.5));
+        assert_eq!(log.total("lamp", monday), None);
+    }
+
+    #[test]
+    fn integrates_power_without_a_total() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 12).unwrap();
+        let mut log = EnergyLog::load(None);
+        let half_hour = Some(Duration::from_secs(30 * 60));
+        assert_eq!(log.record("tv", day, reading(100.0, None), None), 0.0);
+        assert_eq!(log.record("tv", day, reading(100.0, None), half_hour), 0.05);
+        log.save().unwrap();
+    }
+}
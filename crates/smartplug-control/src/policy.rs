@@ -0,0 +1,161 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::NaiveTime;
+use serde::Deserialize;
+use utils::time_of_day;
+
+/// A TV draws tens of watts when on and a watt or less in standby.
+const DEFAULT_MIN_ON_WATTS: f64 = 5.0;
+
+/// What the lid means for a plug.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Follow {
+    /// On while the lid is open
+    #[default]
+    Lid,
+    /// On while the lid is closed
+    InverseLid,
+}
+
+/// A daily stretch of local time, like 23:00-07:00, which may wrap around midnight.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected HH:MM-HH:MM, got {s:?}"))?;
+        Ok(Self {
+            start: time_of_day::parse(start)?,
+            end: time_of_day::parse(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// When a plug should be on.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub follow: Follow,
+    /// How long the plug should have wanted to be on before it is turned on, in milliseconds
+    #[serde(default)]
+    pub on_delay_ms: u64,
+    /// How long the plug should have wanted to be off before it is turned off, in milliseconds
+    #[serde(default)]
+    pub off_delay_ms: u64,
+    /// Local times of day when the plug is off whatever the lid does
+    #[serde(default)]
+    pub off_during: Vec<TimeRange>,
+    /// Below this power, a plug that is on is flagged as having nothing running on it,
+    /// for plugs with a power meter
+    pub min_on_watts: Option<f64>,
+}
+
+impl Policy {
+    pub fn wants_on(&self, lid_open: bool, time: NaiveTime) -> bool {
+        let by_lid = match self.follow {
+            Follow::Lid => lid_open,
+            Follow::InverseLid => !lid_open,
+        };
+        by_lid && !self.off_during.iter().any(|range| range.contains(time))
+    }
+
+    pub fn min_on_watts(&self) -> f64 {
+        self.min_on_watts.unwrap_or(DEFAULT_MIN_ON_WATTS)
+    }
+
+    /// How long to wait before switching the plug on or off.
+    pub fn delay(&self, on: bool) -> Duration {
+        Duration::from_millis(if on {
+            self.on_delay_ms
+        } else {
+            self.off_delay_ms
+        })
+    }
+
+    /// How long until an `off_during` range starts or ends, when the plug may need switching
+    /// without the lid changing.
+    pub fn until_next_boundary(&self, time: NaiveTime) -> Option<Duration> {
+        self.off_during
+            .iter()
+            .flat_map(|range| [range.start, range.end])
+            .map(|boundary| time_of_day::until(time, boundary))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn ranges_wrap_around_midnight() {
+        let night: TimeRange = "23:00-07:00".parse().unwrap();
+        assert!(night.contains(at("23:00:00")));
+        assert!(night.contains(at("03:00:00")));
+        assert!(!night.contains(at("07:00:00")));
+        assert!(!night.contains(at("12:00:00")));
+
+        let lunch: TimeRange = "12:00 - 13:30".parse().unwrap();
+        assert!(lunch.contains(at("12:30:00")));
+        assert!(!lunch.contains(at("13:30:00")));
+
+        assert!("23:00".parse::<TimeRange>().is_err());
+        assert!("23:00-25:00".parse::<TimeRange>().is_err());
+    }
+
+    #[test]
+    fn off_during_overrides_lid() {
+        let policy = Policy {
+            follow: Follow::InverseLid,
+            off_during: vec!["23:00-07:00".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(policy.wants_on(false, at("20:00:00")));
+        assert!(!policy.wants_on(true, at("20:00:00")));
+        assert!(!policy.wants_on(false, at("23:30:00")));
+    }
+
+    #[test]
+    fn wakes_up_at_range_boundaries() {
+        let policy = Policy {
+            off_during: vec!["23:00-07:00".parse().unwrap()],
+            ..Default::default()
+        };
+        let secs = |time| policy.until_next_boundary(at(time)).unwrap().as_secs();
+        assert_eq!(secs("22:00:00"), 60 * 60);
+        assert_eq!(secs("23:00:00"), 8 * 60 * 60);
+        assert_eq!(Policy::default().until_next_boundary(at("22:00:00")), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use crate::switch::PowerSwitch;
+
+/// How failed commands are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Attempts before the failure counts as persistent. Retrying carries on after that,
+    /// at the longest backoff, until the plug is in the wanted state.
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt, after `failures` failed ones in a row; doubles every time.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff)
+    }
+
+    pub fn is_persistent(&self, failures: u32) -> bool {
+        failures >= self.attempts
+    }
+}
+
+/// Switches the plug, then reads its state back to confirm it changed.
+pub fn apply(switch: &mut dyn PowerSwitch, on: bool) -> std::io::Result<()> {
+    switch.set_power(on)?;
+    match switch.power()? {
+        Some(state) if state != on => Err(std::io::Error::other(format!(
+            "{} is still {} after switching it",
+            switch.name(),
+            if state { "on" } else { "off" }
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plug that ignores the first few commands.
+    struct Stubborn {
+        ignore: u32,
+        on: bool,
+    }
+
+    impl PowerSwitch for Stubborn {
+        fn name(&self) -> &str {
+            "stubborn"
+        }
+
+        fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+            match self.ignore.checked_sub(1) {
+                Some(left) => self.ignore = left,
+                None => self.on = on,
+            }
+            Ok(())
+        }
+
+        fn power(&mut self) -> std::io::Result<Option<bool>> {
+            Ok(Some(self.on))
+        }
+    }
+
+    #[test]
+    fn read_back_catches_ignored_commands() {
+        let mut plug = Stubborn {
+            ignore: 1,
+            on: false,
+        };
+        assert!(apply(&mut plug, true).is_err());
+        apply(&mut plug, true).unwrap();
+        assert!(plug.on);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+        let backoffs: Vec<_> = (1..=5).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5]);
+        assert!(!policy.is_persistent(2));
+        assert!(policy.is_persistent(3));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{os::unix::fs::PermissionsExt, path::Path};
+
+/// Reads a secret (like an access token) from a file, refusing files that other users
+/// could read or change, and trimming the trailing newline editors leave behind.
+pub fn read_secret(path: &Path) -> Result<String, String> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|why| format!("cannot read secret file {}: {why}", path.display()))?;
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "secret file {} is accessible by other users (mode {mode:o}), refusing to use it; \
+             run `chmod 600` on it",
+            path.display()
+        ));
+    }
+    let secret = std::fs::read_to_string(path)
+        .map_err(|why| format!("cannot read secret file {}: {why}", path.display()))?;
+    let secret = secret.trim();
+    if secret.is_empty() {
+        return Err(format!("secret file {} is empty", path.display()));
+    }
+    Ok(secret.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, contents: &str, mode: u32) -> std::path::PathBuf {
+        let path = dir.join("token");
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_private_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), "abc.def\n", 0o600);
+        assert_eq!(read_secret(&path).unwrap(), "abc.def");
+    }
+
+    #[test]
+    fn refuses_readable_by_others() {
+        let dir = tempfile::tempdir().unwrap();
+        for mode in [0o644, 0o640, 0o604, 0o620] {
+            let path = write(dir.path(), "abc.def", mode);
+            let error = read_secret(&path).unwrap_err();
+            assert!(error.contains("accessible by other users"), "{error}");
+        }
+    }
+
+    #[test]
+    fn refuses_missing_or_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_secret(&dir.path().join("missing")).is_err());
+        let path = write(dir.path(), " \n", 0o600);
+        assert!(read_secret(&path).unwrap_err().contains("empty"));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    io::Write,
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use api_types::PlugStatus;
+
+/// The status of every plug, and how many times each changed,
+/// so that subscribers can tell which ones they have not seen yet.
+type Shared = Arc<(Mutex<Vec<(u64, PlugStatus)>>, Condvar)>;
+
+/// Publishes the plug statuses on a Unix socket, like lid-publisher does the lid state:
+/// every subscriber gets a JSON line with the current status of each plug,
+/// then another line whenever one changes.
+#[derive(Clone)]
+pub struct StatusPublisher {
+    shared: Shared,
+}
+
+/// Updates the status of one plug.
+pub struct PlugReporter {
+    publisher: StatusPublisher,
+    index: usize,
+}
+
+impl StatusPublisher {
+    pub fn new(plugs: impl IntoIterator<Item = String>) -> Self {
+        let statuses = plugs
+            .into_iter()
+            .map(|plug| {
+                let status = PlugStatus {
+                    plug,
+                    wanted_on: None,
+                    in_sync: false,
+                    reported_on: None,
+                    pending: None,
+                    failures: 0,
+                    persistent_failure: false,
+                    last_error: None,
+                    power_watts: None,
+                    energy_today_kwh: None,
+                    no_load: false,
+                    changed_at: api_types::now(),
+                };
+                (0, status)
+            })
+            .collect();
+        Self {
+            shared: Arc::new((Mutex::new(statuses), Condvar::new())),
+        }
+    }
+
+    /// The reporter for the `index`th plug given to `new`.
+    pub fn reporter(&self, index: usize) -> PlugReporter {
+        PlugReporter {
+            publisher: self.clone(),
+            index,
+        }
+    }
+
+    /// Starts accepting subscribers on `path`, in the background.
+    pub fn listen(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("someone else is listening on {}", path.display()),
+            ));
+        }
+        // Stale file or doesn't exist; safe to remove
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+
+        let accepting = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    eprintln!("failed to accept status connection: {stream:?}");
+                    return;
+                };
+                let shared = accepting.shared.clone();
+                std::thread::spawn(move || {
+                    if let Err(why) = serve(stream, shared)
+                        && why.kind() != std::io::ErrorKind::BrokenPipe
+                    {
+                        eprintln!("error in status connection: {why}");
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+}
+
+impl PlugReporter {
+    /// Changes the status and tells every subscriber, if anything changed.
+    pub fn update(&self, change: impl FnOnce(&mut PlugStatus)) {
+        let shared = &self.publisher.shared;
+        let mut guard = shared.0.lock().expect("failed to lock status");
+        let (generation, status) = &mut guard[self.index];
+        let mut new = status.clone();
+        change(&mut new);
+        if new != *status {
+            new.changed_at = api_types::now();
+            *status = new;
+            *generation += 1;
+            shared.1.notify_all();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn status(&self) -> PlugStatus {
+        let guard = self
+            .publisher
+            .shared
+            .0
+            .lock()
+            .expect("failed to lock status");
+        guard[self.index].1.clone()
+    }
+}
+
+fn serve(mut conn: UnixStream, shared: Shared) -> std::io::Result<()> {
+    // Generations sent so far, none at first
+    let mut seen: Vec<Option<u64>> = Vec::new();
+    loop {
+        let lines = {
+            let guard = shared
+                .1
+                .wait_while(
+                    shared.0.lock().expect("failed to lock status"),
+                    |statuses| {
+                        statuses
+                            .iter()
+                            .zip(seen.iter().chain(std::iter::repeat(&None)))
+                            .all(|((generation, _), seen)| Some(*generation) == *seen)
+                    },
+                )
+                .expect("failed to wait on condvar");
+            seen.resize(guard.len(), None);
+            let mut lines = Vec::new();
+            for ((generation, status), seen) in guard.iter().zip(&mut seen) {
+                if Some(*generation) != *seen {
+                    *seen = Some(*generation);
+                    lines.push(serde_json::to_string(status).expect("failed to serialize status"));
+                }
+            }
+            lines
+        };
+        for line in lines {
+            conn.write_all(line.as_bytes())?;
+            conn.write_all(b"\n")?;
+        }
+        conn.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+
+    #[test]
+    fn subscribers_get_current_statuses_and_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/plug.sock");
+        let publisher = StatusPublisher::new(["tv".to_string(), "lamp".to_string()]);
+        publisher.listen(&path).unwrap();
+        assert!(publisher.listen(&path).is_err());
+
+        let mut lines = BufReader::new(UnixStream::connect(&path).unwrap()).lines();
+        let mut next =
+            || serde_json::from_str::<PlugStatus>(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(next().plug, "tv");
+        assert_eq!(next().plug, "lamp");
+
+        let lamp = publisher.reporter(1);
+        lamp.update(|status| status.wanted_on = Some(true));
+        // Not a change, so not sent
+        lamp.update(|status| status.wanted_on = Some(true));
+        lamp.update(|status| status.in_sync = true);
+        let status = next();
+        assert_eq!(status.plug, "lamp");
+        // Either both changes at once, or one after the other
+        let status = if status.in_sync { status } else { next() };
+        assert_eq!(status.wanted_on, Some(true));
+        assert!(status.in_sync);
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+pub mod ha;
+pub mod mqtt;
+pub mod shelly;
+pub mod tasmota;
+
+/// Something that switches the power of a device on and off.
+pub trait PowerSwitch: Send {
+    /// Name of the switch, for logging.
+    fn name(&self) -> &str;
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()>;
+
+    /// Reads back whether the power is on, or `None` if the switch cannot tell.
+    fn power(&mut self) -> std::io::Result<Option<bool>>;
+
+    /// Reads the plug's power meter, or `None` if it has none.
+    fn meter(&mut self) -> std::io::Result<Option<Reading>> {
+        Ok(None)
+    }
+
+    /// Calls `on_change` whenever the plug reports its state by itself, e.g. after someone
+    /// switched it by hand. Switches that cannot tell never call it.
+    fn watch(&mut self, _on_change: Box<dyn Fn(bool) + Send>) {}
+}
+
+/// What a plug's power meter shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub watts: f64,
+    /// The meter's running energy total, if it keeps one
+    pub total_kwh: Option<f64>,
+}
+
+/// How the plug is switched.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Call a service through Home Assistant's WebSocket API
+    Ha,
+    /// Send a command to a Tasmota device over HTTP
+    Tasmota,
+    /// Call a Shelly (Gen2 or later) device's RPC API
+    Shelly,
+    /// Publish a message to an MQTT broker
+    Mqtt,
+}
+
+fn http_client(timeout: Duration) -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("failed to create HTTP client")
+}
+
+/// Leaves the URL out of the message: it may carry a password in its query.
+fn http_error(why: reqwest::Error) -> std::io::Error {
+    std::io::Error::other(why.without_url())
+}
+
+fn invalid_response(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
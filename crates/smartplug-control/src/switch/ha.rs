@@ -0,0 +1,481 @@
+use std::time::Duration;
+
+use reqwest::{Url, blocking::Client};
+use serde_json::{Value, json};
+use socket::HaSocket;
+
+use super::{PowerSwitch, Reading, http_client, http_error, invalid_response};
+
+mod socket;
+
+/// An entity switched through Home Assistant's WebSocket API, which also tells
+/// when the entity is switched some other way.
+pub struct HomeAssistant {
+    socket: HaSocket,
+    entity: String,
+    /// Sensors of the plug's power meter, in W or kW and in Wh or kWh
+    power_entity: Option<String>,
+    energy_entity: Option<String>,
+    timeout: Duration,
+}
+
+impl HomeAssistant {
+    /// `base` is Home Assistant's URL, `entity` an entity ID like `switch.tv`.
+    /// The plug's power is read from `power_entity`, like `sensor.tv_power`,
+    /// and its energy total from `energy_entity`, if it has a meter.
+    pub fn new(
+        base: Url,
+        token: String,
+        entity: String,
+        power_entity: Option<String>,
+        energy_entity: Option<String>,
+        timeout: Duration,
+    ) -> std::io::Result<Self> {
+        let base = directory(base);
+        let entities = [Some(&entity), power_entity.as_ref(), energy_entity.as_ref()]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        Ok(Self {
+            socket: HaSocket::open(&base, token, entities)?,
+            entity,
+            power_entity,
+            energy_entity,
+            timeout,
+        })
+    }
+
+    /// The latest state Home Assistant reported for one of the plug's entities.
+    fn state(&self, entity: &str) -> std::io::Result<Value> {
+        self.socket.state(entity, self.timeout).ok_or_else(|| {
+            invalid_response(format!("Home Assistant reported no state for {entity}"))
+        })
+    }
+}
+
+/// Adds a trailing slash to Home Assistant's URL, if it has none, so that joining paths to it
+/// keeps its last segment: `https://host/ha` is the directory `https://host/ha/`.
+fn directory(mut base: Url) -> Url {
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    base
+}
+
+/// The value of a sensor, converted to the first of `units` by their factors.
+fn sensor_value(state: &Value, units: &[(&str, f64)]) -> std::io::Result<f64> {
+    let entity = &state["entity_id"];
+    let value: f64 = state["state"]
+        .as_str()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid_response(format!("{entity} is in state {}", state["state"])))?;
+    let unit = state["attributes"]["unit_of_measurement"].as_str();
+    let factor = units
+        .iter()
+        .find(|(name, _)| Some(*name) == unit)
+        .map(|(_, factor)| factor)
+        .ok_or_else(|| invalid_response(format!("{entity} is measured in {unit:?}")))?;
+    Ok(value * factor)
+}
+
+impl PowerSwitch for HomeAssistant {
+    fn name(&self) -> &str {
+        &self.entity
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        // switch.tv is turned on by switch.turn_on, light.lamp by light.turn_on
+        let domain = match self.entity.split_once('.') {
+            Some((domain, _)) => domain,
+            None => "homeassistant",
+        };
+        let service = if on { "turn_on" } else { "turn_off" };
+        self.socket.call(
+            json!({
+                "type": "call_service",
+                "domain": domain,
+                "service": service,
+                "target": {"entity_id": self.entity},
+            }),
+            self.timeout,
+        )?;
+        Ok(())
+    }
+
+    fn power(&mut self) -> std::io::Result<Option<bool>> {
+        let state = self.state(&self.entity)?;
+        match state["state"].as_str() {
+            Some("on") => Ok(Some(true)),
+            Some("off") => Ok(Some(false)),
+            // Most likely "unavailable", when Home Assistant lost the plug
+            _ => Err(invalid_response(format!(
+                "{} is in state {}",
+                self.entity, state["state"]
+            ))),
+        }
+    }
+
+    fn meter(&mut self) -> std::io::Result<Option<Reading>> {
+        let Some(power_entity) = &self.power_entity else {
+            return Ok(None);
+        };
+        let watts = sensor_value(&self.state(power_entity)?, &[("W", 1.0), ("kW", 1000.0)])?;
+        let total_kwh = match &self.energy_entity {
+            Some(entity) => Some(sensor_value(
+                &self.state(entity)?,
+                &[("kWh", 1.0), ("Wh", 0.001), ("MWh", 1000.0)],
+            )?),
+            None => None,
+        };
+        Ok(Some(Reading { watts, total_kwh }))
+    }
+
+    fn watch(&mut self, on_change: Box<dyn Fn(bool) + Send>) {
+        self.socket
+            .watch(self.entity.clone(), move |state| match state {
+                "on" => on_change(true),
+                "off" => on_change(false),
+                _ => {}
+            });
+    }
+}
+
+/// Shows whether the TV is on in Home Assistant, as a binary sensor.
+/// Entities that no integration provides can only be set through the REST API.
+pub struct TvSensor {
+    client: Client,
+    url: Url,
+    token: String,
+}
+
+impl TvSensor {
+    /// `entity` is the sensor's entity ID, like `binary_sensor.tv`.
+    pub fn new(
+        base: &Url,
+        token: String,
+        entity: &str,
+        timeout: Duration,
+    ) -> std::io::Result<Self> {
+        let url = directory(base.clone())
+            .join(&format!("api/states/{entity}"))
+            .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidInput, why))?;
+        Ok(Self {
+            client: http_client(timeout),
+            url,
+            token,
+        })
+    }
+
+    pub fn publish(&self, on: bool) -> std::io::Result<()> {
+        self.client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .json(&json!({
+                "state": if on { "on" } else { "off" },
+                "attributes": {"friendly_name": "TV", "device_class": "power"},
+            }))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(http_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::mpsc::{self, Receiver, Sender},
+    };
+
+    use serde_json::Map;
+    use tungstenite::{
+        Message,
+        handshake::server::{Callback, ErrorResponse, Request, Response},
+    };
+
+    use super::*;
+
+    /// Accepts a WebSocket handshake only for the path.
+    struct ExpectPath(&'static str);
+
+    impl Callback for ExpectPath {
+        fn on_request(
+            self,
+            request: &Request,
+            response: Response,
+        ) -> Result<Response, ErrorResponse> {
+            assert_eq!(request.uri().path(), self.0);
+            Ok(response)
+        }
+    }
+
+    /// A Home Assistant that knows one entity and its meter, takes one connection and reports
+    /// the entities subscribed to and the services called on it.
+    /// Whatever is sent on `switch_by_hand` becomes the entity's new state.
+    struct FakeHa {
+        url: Url,
+        subscriptions: Receiver<Value>,
+        calls: Receiver<Value>,
+        switch_by_hand: Sender<&'static str>,
+    }
+
+    fn fake_ha(entity: &'static str, state: &'static str) -> FakeHa {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // Under a path, as behind a reverse proxy
+        let url = Url::parse(&format!("http://{}/ha", listener.local_addr().unwrap())).unwrap();
+        let (subscriptions_tx, subscriptions) = mpsc::channel();
+        let (calls_tx, calls) = mpsc::channel();
+        let (switch_by_hand, by_hand) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket =
+                tungstenite::accept_hdr(stream, ExpectPath("/ha/api/websocket")).unwrap();
+            let send = |socket: &mut tungstenite::WebSocket<_>, message: Value| {
+                socket.send(Message::text(message.to_string())).unwrap();
+            };
+            let read =
+                |socket: &mut tungstenite::WebSocket<std::net::TcpStream>| match socket.read() {
+                    Ok(Message::Text(text)) => Some(serde_json::from_str::<Value>(&text).unwrap()),
+                    _ => None,
+                };
+
+            send(&mut socket, json!({"type": "auth_required"}));
+            let auth = read(&mut socket).unwrap();
+            if auth["access_token"] != "secret" {
+                send(
+                    &mut socket,
+                    json!({"type": "auth_invalid", "message": "Invalid access token"}),
+                );
+                return;
+            }
+            send(&mut socket, json!({"type": "auth_ok"}));
+            socket
+                .get_mut()
+                .set_read_timeout(Some(Duration::from_millis(20)))
+                .unwrap();
+
+            let mut state = state;
+            let mut subscription = None;
+            // Compressed states, as subscribe_entities sends them
+            let states = |state: &str| {
+                let states = [
+                    (entity, json!({"s": state, "a": {}})),
+                    ("light.other", json!({"s": "on", "a": {}})),
+                    (
+                        "sensor.tv_power",
+                        json!({"s": "0.12", "a": {"unit_of_measurement": "kW"}}),
+                    ),
+                    (
+                        "sensor.tv_energy",
+                        json!({"s": "1520", "a": {"unit_of_measurement": "Wh"}}),
+                    ),
+                    (
+                        "sensor.lamp_power",
+                        json!({"s": "unavailable", "a": {"unit_of_measurement": "W"}}),
+                    ),
+                ];
+                states.map(|(entity, state)| (entity.to_string(), state))
+            };
+            let changed = |id, state| {
+                let change = (entity.to_string(), json!({"+": {"s": state, "lc": 1.0}}));
+                json!({"id": id, "type": "event", "event": {"c": Map::from_iter([change])}})
+            };
+            loop {
+                if let Ok(new_state) = by_hand.try_recv() {
+                    state = new_state;
+                    send(&mut socket, changed(subscription.clone().unwrap(), state));
+                }
+                let message = match socket.read() {
+                    Ok(Message::Text(text)) => serde_json::from_str::<Value>(&text).unwrap(),
+                    Ok(_) => continue,
+                    Err(tungstenite::Error::Io(_)) => continue,
+                    Err(_) => return,
+                };
+                let id = message["id"].clone();
+                match message["type"].as_str().unwrap() {
+                    "subscribe_entities" => {
+                        subscription = Some(id.clone());
+                        let wanted = &message["entity_ids"];
+                        subscriptions_tx.send(wanted.clone()).unwrap();
+                        send(
+                            &mut socket,
+                            json!({"id": id, "type": "result", "success": true, "result": null}),
+                        );
+                        let known = states(state).into_iter().filter(|(entity, _)| {
+                            wanted.as_array().unwrap().contains(&json!(entity))
+                        });
+                        send(
+                            &mut socket,
+                            json!({"id": id, "type": "event", "event": {"a": Map::from_iter(known)}}),
+                        );
+                    }
+                    "call_service" => {
+                        state = if message["service"] == "turn_on" {
+                            "on"
+                        } else {
+                            "off"
+                        };
+                        calls_tx.send(message).unwrap();
+                        send(&mut socket, changed(subscription.clone().unwrap(), state));
+                        send(
+                            &mut socket,
+                            json!({"id": id, "type": "result", "success": true, "result": {}}),
+                        );
+                    }
+                    _ => send(
+                        &mut socket,
+                        json!({"id": id, "type": "result", "success": false, "error": {"code": "unknown_command", "message": "Unknown command."}}),
+                    ),
+                }
+            }
+        });
+        FakeHa {
+            url,
+            subscriptions,
+            calls,
+            switch_by_hand,
+        }
+    }
+
+    fn switch(
+        ha: &FakeHa,
+        power_entity: Option<&str>,
+        energy_entity: Option<&str>,
+    ) -> HomeAssistant {
+        HomeAssistant::new(
+            ha.url.clone(),
+            "secret".to_string(),
+            "switch.tv".to_string(),
+            power_entity.map(str::to_string),
+            energy_entity.map(str::to_string),
+            Duration::from_secs(2),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn calls_service_and_reads_state_back() {
+        let ha = fake_ha("switch.tv", "off");
+        let mut switch = switch(&ha, None, None);
+        assert_eq!(switch.power().unwrap(), Some(false));
+        assert_eq!(ha.subscriptions.recv().unwrap(), json!(["switch.tv"]));
+        switch.set_power(true).unwrap();
+        assert_eq!(switch.power().unwrap(), Some(true));
+
+        let call = ha.calls.recv().unwrap();
+        assert_eq!(call["domain"], "switch");
+        assert_eq!(call["service"], "turn_on");
+        assert_eq!(call["target"]["entity_id"], "switch.tv");
+    }
+
+    #[test]
+    fn reports_changes_made_elsewhere() {
+        let ha = fake_ha("switch.tv", "off");
+        let mut switch = switch(&ha, None, None);
+        let (tx, changes) = mpsc::channel();
+        switch.watch(Box::new(move |on| tx.send(on).unwrap()));
+        // Once the subscription is in place
+        assert_eq!(switch.power().unwrap(), Some(false));
+
+        ha.switch_by_hand.send("on").unwrap();
+        // The state at connection time may come first
+        let change = changes.recv_timeout(Duration::from_secs(2)).unwrap();
+        let change = if change {
+            change
+        } else {
+            changes.recv_timeout(Duration::from_secs(2)).unwrap()
+        };
+        assert!(change);
+    }
+
+    #[test]
+    fn reads_meter_sensors() {
+        let ha = fake_ha("switch.tv", "on");
+        assert_eq!(switch(&ha, None, None).meter().unwrap(), None);
+
+        let ha = fake_ha("switch.tv", "on");
+        let mut metered = switch(&ha, Some("sensor.tv_power"), Some("sensor.tv_energy"));
+        assert_eq!(
+            metered.meter().unwrap(),
+            Some(Reading {
+                watts: 120.0,
+                total_kwh: Some(1.52)
+            })
+        );
+        // Only the plug's own entities are followed
+        assert_eq!(
+            ha.subscriptions.recv().unwrap(),
+            json!(["switch.tv", "sensor.tv_power", "sensor.tv_energy"])
+        );
+
+        let ha = fake_ha("switch.tv", "on");
+        assert!(
+            switch(&ha, Some("sensor.lamp_power"), None)
+                .meter()
+                .is_err()
+        );
+        let ha = fake_ha("switch.tv", "on");
+        assert!(switch(&ha, Some("sensor.missing"), None).meter().is_err());
+    }
+
+    #[test]
+    fn wrong_token_fails_commands() {
+        let ha = fake_ha("switch.tv", "off");
+        let mut switch = HomeAssistant::new(
+            ha.url.clone(),
+            "wrong".to_string(),
+            "switch.tv".to_string(),
+            None,
+            None,
+            Duration::from_millis(300),
+        )
+        .unwrap();
+        assert!(switch.set_power(true).is_err());
+        assert!(ha.calls.try_recv().is_err());
+    }
+
+    #[test]
+    fn unresponsive_server_times_out() {
+        // Accepts connections, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let mut switch = HomeAssistant::new(
+            url,
+            "secret".to_string(),
+            "switch.tv".to_string(),
+            None,
+            None,
+            Duration::from_millis(200),
+        )
+        .unwrap();
+        let started = std::time::Instant::now();
+        assert!(switch.set_power(true).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn publishes_tv_sensor() {
+        let mut server = mockito::Server::new();
+        let publish = server
+            .mock("POST", "/ha/api/states/binary_sensor.tv")
+            .match_header("authorization", "Bearer secret")
+            .match_body(mockito::Matcher::PartialJson(json!({"state": "on"})))
+            .with_status(201)
+            .create();
+
+        // Home Assistant behind a reverse proxy, under a path
+        let url = Url::parse(&format!("{}/ha", server.url())).unwrap();
+        let sensor = TvSensor::new(
+            &url,
+            "secret".to_string(),
+            "binary_sensor.tv",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        sensor.publish(true).unwrap();
+        publish.assert();
+    }
+}
@@ -0,0 +1,397 @@
+//! A client for Home Assistant's WebSocket API that stays connected in the background,
+//! so that state changes made elsewhere (by hand, or by automations) are seen as they happen.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{self, Receiver, Sender, TryRecvError},
+    },
+    time::{Duration, Instant},
+};
+
+use reqwest::Url;
+use serde_json::{Value, json};
+use tungstenite::{HandshakeError, Message, WebSocket, stream::MaybeTlsStream};
+
+/// How long a read waits for Home Assistant before checking for commands to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long connecting and logging in may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type Watcher = Box<dyn Fn(&str) + Send>;
+type Watchers = Arc<Mutex<Vec<(String, Watcher)>>>;
+
+/// The latest state of each subscribed entity, as `get_states` would return it,
+/// signalled whenever it changes.
+#[derive(Default)]
+struct States {
+    states: Mutex<HashMap<String, Value>>,
+    changed: Condvar,
+}
+
+struct Command {
+    message: Value,
+    /// The caller stops waiting then, so the command is dropped if it was not sent by then
+    deadline: Instant,
+    reply: Sender<std::io::Result<Value>>,
+}
+
+pub struct HaSocket {
+    commands: Sender<Command>,
+    watchers: Watchers,
+    states: Arc<States>,
+}
+
+impl HaSocket {
+    /// Starts connecting to the Home Assistant at `base` (its http or https URL),
+    /// reconnecting whenever the connection is lost, and following the state of `entities`.
+    pub fn open(base: &Url, token: String, entities: Vec<String>) -> std::io::Result<Self> {
+        let url = websocket_url(base)?;
+        let (commands, queue) = mpsc::channel();
+        let watchers = Watchers::default();
+        let states = Arc::<States>::default();
+        let (notify, update) = (watchers.clone(), states.clone());
+        std::thread::spawn(move || {
+            keep_connected(&url, &token, &entities, &queue, &notify, &update)
+        });
+        Ok(Self {
+            commands,
+            watchers,
+            states,
+        })
+    }
+
+    /// The latest state of a subscribed entity, waiting up to `timeout` for it
+    /// while connecting. `None` if Home Assistant did not report it by then.
+    pub fn state(&self, entity: &str, timeout: Duration) -> Option<Value> {
+        let states = self.states.states.lock().expect("failed to lock states");
+        let (states, _) = self
+            .states
+            .changed
+            .wait_timeout_while(states, timeout, |states| !states.contains_key(entity))
+            .expect("failed to lock states");
+        states.get(entity).cloned()
+    }
+
+    /// Sends a command (without its ID) and waits for its result.
+    pub fn call(&self, message: Value, timeout: Duration) -> std::io::Result<Value> {
+        let (reply, result) = mpsc::channel();
+        let command = Command {
+            message,
+            deadline: Instant::now() + timeout,
+            reply,
+        };
+        self.commands
+            .send(command)
+            .map_err(|_| std::io::Error::other("Home Assistant connection is gone"))?;
+        result.recv_timeout(timeout).unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "no answer from Home Assistant in time",
+            ))
+        })
+    }
+
+    /// Calls `on_change` with the state of `entity` whenever it changes,
+    /// and every time the connection is (re)established.
+    pub fn watch(&self, entity: String, on_change: impl Fn(&str) + Send + 'static) {
+        self.watchers
+            .lock()
+            .expect("failed to lock watchers")
+            .push((entity, Box::new(on_change)));
+    }
+}
+
+fn websocket_url(base: &Url) -> std::io::Result<Url> {
+    let mut url = base
+        .join("api/websocket")
+        .map_err(|why| std::io::Error::new(ErrorKind::InvalidInput, why))?;
+    let scheme = match url.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        other => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported scheme {other} for Home Assistant"),
+            ));
+        }
+    };
+    url.set_scheme(scheme)
+        .expect("ws and wss are valid schemes");
+    Ok(url)
+}
+
+fn websocket_error(why: tungstenite::Error) -> std::io::Error {
+    match why {
+        tungstenite::Error::Io(why) => why,
+        why => std::io::Error::other(why),
+    }
+}
+
+fn keep_connected(
+    url: &Url,
+    token: &str,
+    entities: &[String],
+    commands: &Receiver<Command>,
+    watchers: &Watchers,
+    states: &States,
+) {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let connected_at = Instant::now();
+        let result = Session::connect(url, token).and_then(|mut session| {
+            println!("connected to Home Assistant at {url}");
+            session.run(entities, commands, watchers, states)
+        });
+        // States from before a reconnection may be stale by now
+        states.states.lock().expect("failed to lock states").clear();
+        match result {
+            // Nobody is left to send commands
+            Ok(()) => return,
+            Err(why) => eprintln!("connection to Home Assistant at {url} failed: {why}"),
+        }
+        // A connection that held for a while was not failing, so start over with short delays
+        if connected_at.elapsed() > MAX_RECONNECT_DELAY {
+            delay = Duration::from_secs(1);
+        }
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+struct Session {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+    /// Callers waiting for the results of their commands, by command ID
+    pending: HashMap<u64, Sender<std::io::Result<Value>>>,
+}
+
+impl Session {
+    fn connect(url: &Url, token: &str) -> std::io::Result<Self> {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or_default();
+        let address = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("cannot resolve {host}"))
+        })?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let (socket, _) =
+            tungstenite::client_tls(url.as_str(), stream).map_err(|why| match why {
+                HandshakeError::Failure(why) => websocket_error(why),
+                HandshakeError::Interrupted(_) => std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    "Home Assistant did not complete the handshake",
+                ),
+            })?;
+        let mut session = Self {
+            socket,
+            next_id: 1,
+            pending: HashMap::new(),
+        };
+        // Home Assistant asks for the token before anything else
+        let hello = session.read_blocking()?;
+        if hello["type"] != "auth_required" {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("expected auth_required, got {hello}"),
+            ));
+        }
+        session.send(&json!({"type": "auth", "access_token": token}))?;
+        let reply = session.read_blocking()?;
+        match reply["type"].as_str() {
+            Some("auth_ok") => {}
+            Some("auth_invalid") => {
+                return Err(std::io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("access token rejected: {}", reply["message"]),
+                ));
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected answer to auth: {reply}"),
+                ));
+            }
+        }
+
+        let stream = match session.socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream,
+            MaybeTlsStream::Rustls(stream) => stream.get_mut(),
+            _ => unreachable!("only rustls is enabled"),
+        };
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(session)
+    }
+
+    fn send(&mut self, message: &Value) -> std::io::Result<()> {
+        self.socket
+            .send(Message::text(message.to_string()))
+            .map_err(websocket_error)
+    }
+
+    /// Sends a command with the next ID, and returns that ID.
+    fn send_command(&mut self, mut message: Value) -> std::io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        message["id"] = id.into();
+        self.send(&message)?;
+        Ok(id)
+    }
+
+    /// Reads the next JSON message, or `None` if there was none within the poll interval.
+    fn read(&mut self) -> std::io::Result<Option<Value>> {
+        loop {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => {
+                    return serde_json::from_str(text.as_str())
+                        .map(Some)
+                        .map_err(|why| std::io::Error::new(ErrorKind::InvalidData, why));
+                }
+                Ok(Message::Close(_)) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "Home Assistant closed the connection",
+                    ));
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(why))
+                    if matches!(why.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    // Also sends the pongs to pings read meanwhile
+                    match self.socket.flush() {
+                        Err(tungstenite::Error::Io(why)) if why.kind() == ErrorKind::WouldBlock => {
+                        }
+                        result => result.map_err(websocket_error)?,
+                    }
+                    return Ok(None);
+                }
+                Err(why) => return Err(websocket_error(why)),
+            }
+        }
+    }
+
+    /// Reads the next JSON message while logging in, when reads time out after `CONNECT_TIMEOUT`.
+    fn read_blocking(&mut self) -> std::io::Result<Value> {
+        self.read()?.ok_or_else(|| {
+            std::io::Error::new(ErrorKind::TimedOut, "Home Assistant did not answer")
+        })
+    }
+
+    /// Passes commands on and results back until the connection fails,
+    /// or returns `Ok` once the `HaSocket` is dropped.
+    fn run(
+        &mut self,
+        entities: &[String],
+        commands: &Receiver<Command>,
+        watchers: &Watchers,
+        states: &States,
+    ) -> std::io::Result<()> {
+        // The first event has the current states, which catches up on what changed while disconnected
+        let subscription =
+            self.send_command(json!({"type": "subscribe_entities", "entity_ids": entities}))?;
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(command) if command.deadline > Instant::now() => {
+                        let id = self.send_command(command.message)?;
+                        self.pending.insert(id, command.reply);
+                    }
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+
+            let Some(mut message) = self.read()? else {
+                continue;
+            };
+            let id = message["id"].as_u64();
+            match message["type"].as_str() {
+                Some("result") if id == Some(subscription) && message["success"] != true => {
+                    return Err(std::io::Error::other(format!(
+                        "cannot subscribe to state changes: {}",
+                        message["error"]
+                    )));
+                }
+                Some("result") => {
+                    let Some(reply) = id.and_then(|id| self.pending.remove(&id)) else {
+                        continue;
+                    };
+                    let result = if message["success"] == true {
+                        Ok(message["result"].take())
+                    } else {
+                        Err(std::io::Error::other(format!(
+                            "Home Assistant refused: {}",
+                            message["error"]["message"]
+                        )))
+                    };
+                    let _ = reply.send(result);
+                }
+                Some("event") if id == Some(subscription) => {
+                    update(states, watchers, &message["event"]);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Applies a `subscribe_entities` event: whole states of entities under `a`
+/// (`s` being the state and `a` the attributes), changes to them under `c`
+/// and entities that are gone under `r`. Watchers hear of every new state.
+fn update(states: &States, watchers: &Watchers, event: &Value) {
+    let mut changed = Vec::new();
+    {
+        let mut states = states.states.lock().expect("failed to lock states");
+        for (entity, state) in event["a"].as_object().into_iter().flatten() {
+            states.insert(
+                entity.clone(),
+                json!({"entity_id": entity, "state": state["s"], "attributes": state["a"]}),
+            );
+            changed.push((entity, &state["s"]));
+        }
+        for (entity, diff) in event["c"].as_object().into_iter().flatten() {
+            let Some(state) = states.get_mut(entity) else {
+                continue;
+            };
+            let added = &diff["+"];
+            if !added["s"].is_null() {
+                state["state"] = added["s"].clone();
+                changed.push((entity, &added["s"]));
+            }
+            for (name, value) in added["a"].as_object().into_iter().flatten() {
+                state["attributes"][name] = value.clone();
+            }
+            let removed = diff["-"]["a"].as_array().into_iter().flatten();
+            if let Some(attributes) = state["attributes"].as_object_mut() {
+                for name in removed.filter_map(Value::as_str) {
+                    attributes.remove(name);
+                }
+            }
+        }
+        for entity in event["r"].as_array().into_iter().flatten() {
+            if let Some(entity) = entity.as_str() {
+                states.remove(entity);
+            }
+        }
+    }
+    states.changed.notify_all();
+    for (entity, state) in changed {
+        notify(watchers, entity, state);
+    }
+}
+
+fn notify(watchers: &Watchers, entity: &str, state: &Value) {
+    let Some(state) = state.as_str() else {
+        return;
+    };
+    for (watched, on_change) in watchers.lock().expect("failed to lock watchers").iter() {
+        if watched == entity {
+            on_change(state);
+        }
+    }
+}
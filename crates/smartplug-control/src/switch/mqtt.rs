@@ -0,0 +1,269 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+
+use super::PowerSwitch;
+
+/// Numbers the switches in this process, so that each has its own client ID.
+/// A broker drops the older session when a second client connects with the same ID.
+static NEXT_SWITCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Where and what to publish.
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+    pub topic: String,
+    pub payload_on: String,
+    pub payload_off: String,
+    pub retain: bool,
+    /// Topic the plug reports its state on with the same payloads, e.g. stat/tv-plug/POWER
+    pub state_topic: Option<String>,
+    pub timeout: Duration,
+}
+
+/// A plug switched by publishing a message to an MQTT broker, e.g. to a Tasmota or Zigbee2MQTT
+/// command topic. Messages are published with QoS 1 and count as sent once the broker acks them.
+pub struct MqttSwitch {
+    options: MqttOptions,
+    name: String,
+    settings: MqttSettings,
+    /// State the plug reported after the last command
+    reported: Option<bool>,
+}
+
+impl MqttSwitch {
+    pub fn new(settings: MqttSettings) -> Self {
+        let client_id = format!(
+            "smartplug-control-{}-{}",
+            std::process::id(),
+            NEXT_SWITCH.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut options = MqttOptions::new(client_id, &settings.host, settings.port);
+        if let Some((username, password)) = &settings.credentials {
+            options.set_credentials(username, password);
+        }
+        Self {
+            options,
+            name: format!(
+                "mqtt:{}:{}/{}",
+                settings.host, settings.port, settings.topic
+            ),
+            settings,
+            reported: None,
+        }
+    }
+
+    fn parse_state(&self, payload: &[u8]) -> Option<bool> {
+        if payload == self.settings.payload_on.as_bytes() {
+            Some(true)
+        } else if payload == self.settings.payload_off.as_bytes() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl PowerSwitch for MqttSwitch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        let deadline = Instant::now() + self.settings.timeout;
+        // The lid rarely changes, so connect for every message rather than keeping a session alive
+        let (client, mut connection) = Client::new(self.options.clone(), 10);
+        let state_topic = self.settings.state_topic.clone();
+        if let Some(state_topic) = &state_topic {
+            client
+                .subscribe(state_topic, QoS::AtLeastOnce)
+                .map_err(std::io::Error::other)?;
+        }
+        let payload = if on {
+            &self.settings.payload_on
+        } else {
+            &self.settings.payload_off
+        };
+        client
+            .publish(
+                &self.settings.topic,
+                QoS::AtLeastOnce,
+                self.settings.retain,
+                payload.as_bytes(),
+            )
+            .map_err(std::io::Error::other)?;
+
+        self.reported = None;
+        let mut acked = false;
+        while !acked || (state_topic.is_some() && self.reported.is_none()) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let Ok(event) = connection.recv_timeout(left) else {
+                if acked {
+                    // The broker has the message; read-back will tell that the plug never answered
+                    break;
+                }
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("no ack from {} in time", self.name),
+                ));
+            };
+            match event.map_err(std::io::Error::other)? {
+                Event::Incoming(Packet::PubAck(_)) => acked = true,
+                // Retained messages are the state from before this command
+                Event::Incoming(Packet::Publish(publish))
+                    if Some(&publish.topic) == state_topic.as_ref() && !publish.retain =>
+                {
+                    self.reported = self.parse_state(&publish.payload);
+                }
+                _ => {}
+            }
+        }
+        let _ = client.disconnect();
+        Ok(())
+    }
+
+    fn power(&mut self) -> std::io::Result<Option<bool>> {
+        let Some(state_topic) = &self.settings.state_topic else {
+            return Ok(None);
+        };
+        // There is no asking for the state, so this is what came in after the last command
+        self.reported.map(Some).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no state reported on {state_topic}"),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    /// Reads an MQTT packet: its type and flags byte, and the rest of it.
+    fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).ok()?;
+        let header = byte[0];
+        let (mut length, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).ok()?;
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((header, body))
+    }
+
+    fn string(body: &[u8]) -> (String, &[u8]) {
+        let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let text = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+        (text, &body[2 + len..])
+    }
+
+    /// A QoS 0 PUBLISH of a state on stat/plug/POWER.
+    fn publish(retain: bool, payload: &str) -> Vec<u8> {
+        let topic = b"stat/plug/POWER";
+        let mut packet = vec![0x30 | retain as u8, (2 + topic.len() + payload.len()) as u8];
+        packet.extend((topic.len() as u16).to_be_bytes());
+        packet.extend(topic);
+        packet.extend(payload.as_bytes());
+        packet
+    }
+
+    /// A broker that accepts one connection and reports the messages published on it.
+    /// When given a state, it answers every command with it on stat/plug/POWER.
+    fn broker(state: Option<&'static str>) -> (u16, mpsc::Receiver<(u8, String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Some((header, body)) = read_packet(&mut stream) {
+                match header >> 4 {
+                    // CONNECT
+                    1 => stream.write_all(&[0x20, 2, 0, 0]).unwrap(),
+                    // SUBSCRIBE, granted QoS 1; a retained, stale state comes right after
+                    8 => {
+                        stream.write_all(&[0x90, 3, body[0], body[1], 1]).unwrap();
+                        stream.write_all(&publish(true, "OFF")).unwrap();
+                    }
+                    // PUBLISH
+                    3 => {
+                        let (topic, rest) = string(&body);
+                        tx.send((header & 0x0f, topic, rest[2..].to_vec())).unwrap();
+                        stream.write_all(&[0x40, 2, rest[0], rest[1]]).unwrap();
+                        if let Some(state) = state {
+                            stream.write_all(&publish(false, state)).unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    fn settings(port: u16, state_topic: Option<&str>) -> MqttSettings {
+        MqttSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            credentials: None,
+            topic: "cmnd/plug/POWER".to_string(),
+            payload_on: "ON".to_string(),
+            payload_off: "OFF".to_string(),
+            retain: true,
+            state_topic: state_topic.map(str::to_string),
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    #[test]
+    fn publishes_payload_and_waits_for_ack() {
+        let (port, published) = broker(None);
+        let mut switch = MqttSwitch::new(settings(port, None));
+        switch.set_power(false).unwrap();
+        assert_eq!(switch.power().unwrap(), None);
+
+        let (flags, topic, payload) = published.recv().unwrap();
+        assert_eq!(topic, "cmnd/plug/POWER");
+        assert_eq!(payload, b"OFF");
+        // QoS 1, retained
+        assert_eq!(flags, 0b0011);
+    }
+
+    #[test]
+    fn every_switch_has_its_own_client_id() {
+        let a = MqttSwitch::new(settings(1883, None));
+        let b = MqttSwitch::new(settings(1883, None));
+        assert_ne!(a.options.client_id(), b.options.client_id());
+    }
+
+    #[test]
+    fn reads_state_reported_after_command() {
+        let (port, _published) = broker(Some("ON"));
+        let mut switch = MqttSwitch::new(settings(port, Some("stat/plug/POWER")));
+        switch.set_power(true).unwrap();
+        assert_eq!(switch.power().unwrap(), Some(true));
+
+        // A plug that stays silent has no confirmed state
+        let (port, _published) = broker(None);
+        let mut switch = MqttSwitch::new(settings(port, Some("stat/plug/POWER")));
+        switch.set_power(true).unwrap();
+        assert!(switch.power().is_err());
+    }
+}
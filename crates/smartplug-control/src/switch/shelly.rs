@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use reqwest::{Url, blocking::Client};
+use serde_json::{Value, json};
+
+use super::{PowerSwitch, Reading, http_client, http_error, invalid_response};
+
+/// A switch of a Shelly Gen2 (or later) device, driven through its JSON-RPC API.
+/// Devices with authentication enabled are not supported.
+pub struct Shelly {
+    client: Client,
+    base: Url,
+    name: String,
+    /// Switch component ID, 0 on single-relay devices
+    switch_id: u32,
+}
+
+impl Shelly {
+    pub fn new(base: Url, switch_id: u32, timeout: Duration) -> Self {
+        Self {
+            client: http_client(timeout),
+            name: format!("shelly:{}#{switch_id}", base.host_str().unwrap_or_default()),
+            base,
+            switch_id,
+        }
+    }
+}
+
+impl PowerSwitch for Shelly {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        self.call("Switch.Set", json!({"id": self.switch_id, "on": on}))?;
+        Ok(())
+    }
+
+    fn power(&mut self) -> std::io::Result<Option<bool>> {
+        let status = self.call("Switch.GetStatus", json!({"id": self.switch_id}))?;
+        match status["output"].as_bool() {
+            Some(on) => Ok(Some(on)),
+            None => Err(invalid_response(format!(
+                "{} sent a status without output: {status}",
+                self.name
+            ))),
+        }
+    }
+
+    fn meter(&mut self) -> std::io::Result<Option<Reading>> {
+        let status = self.call("Switch.GetStatus", json!({"id": self.switch_id}))?;
+        // Only switches with metering report power, and the energy counter in Wh
+        Ok(status["apower"].as_f64().map(|watts| Reading {
+            watts,
+            total_kwh: status["aenergy"]["total"].as_f64().map(|wh| wh / 1000.0),
+        }))
+    }
+}
+
+impl Shelly {
+    /// Calls an RPC method and returns its result.
+    fn call(&self, method: &str, params: Value) -> std::io::Result<Value> {
+        let url = self
+            .base
+            .join("rpc")
+            .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidInput, why))?;
+        let mut reply: Value = self
+            .client
+            .post(url)
+            .json(&json!({"id": 1, "method": method, "params": params}))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .map_err(http_error)?;
+        // Errors come back with a 200 status and an error object instead of a result
+        if let Some(error) = reply.get("error") {
+            return Err(invalid_response(format!(
+                "{} refused {method}: {error}",
+                self.name
+            )));
+        }
+        Ok(reply["result"].take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_switch_set() {
+        let mut server = mockito::Server::new();
+        let set = server
+            .mock("POST", "/rpc")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "method": "Switch.Set",
+                "params": {"id": 1, "on": false},
+            })))
+            .with_body(r#"{"id":1,"src":"shellyplus2pm","result":{"was_on":true}}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        Shelly::new(url, 1, Duration::from_secs(5))
+            .set_power(false)
+            .unwrap();
+        set.assert();
+    }
+
+    #[test]
+    fn rpc_error_is_an_error() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("POST", "/rpc")
+            .with_body(
+                r#"{"id":1,"error":{"code":-105,"message":"Argument 'id', value 7 not found!"}}"#,
+            )
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        assert!(
+            Shelly::new(url, 7, Duration::from_secs(5))
+                .set_power(true)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reads_switch_status() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("POST", "/rpc")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "method": "Switch.GetStatus",
+                "params": {"id": 0},
+            })))
+            .with_body(r#"{"id":1,"src":"shellyplugs","result":{"id":0,"source":"HTTP","output":true,"apower":83.2}}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        let mut switch = Shelly::new(url, 0, Duration::from_secs(5));
+        assert_eq!(switch.power().unwrap(), Some(true));
+    }
+
+    #[test]
+    fn reads_power_meter() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("POST", "/rpc")
+            .with_body(r#"{"id":1,"src":"shellyplugs","result":{"id":0,"output":true,"apower":83.2,"aenergy":{"total":1520.5}}}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        let mut switch = Shelly::new(url, 0, Duration::from_secs(5));
+        assert_eq!(
+            switch.meter().unwrap(),
+            Some(Reading {
+                watts: 83.2,
+                total_kwh: Some(1.5205)
+            })
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use reqwest::{Url, blocking::Client};
+
+use super::{PowerSwitch, Reading, http_client, http_error, invalid_response};
+
+/// Tasmota always calls its web user this.
+const TASMOTA_USER: &str = "admin";
+
+/// A relay of a Tasmota device, switched through its HTTP command API (`/cm?cmnd=Power On`).
+pub struct Tasmota {
+    client: Client,
+    base: Url,
+    name: String,
+    /// Relay number on devices with more than one
+    relay: Option<u8>,
+    password: Option<String>,
+}
+
+impl Tasmota {
+    pub fn new(base: Url, relay: Option<u8>, password: Option<String>, timeout: Duration) -> Self {
+        let name = match relay {
+            Some(relay) => format!("tasmota:{}#{relay}", base.host_str().unwrap_or_default()),
+            None => format!("tasmota:{}", base.host_str().unwrap_or_default()),
+        };
+        Self {
+            client: http_client(timeout),
+            base,
+            name,
+            relay,
+            password,
+        }
+    }
+}
+
+impl PowerSwitch for Tasmota {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_power(&mut self, on: bool) -> std::io::Result<()> {
+        let state = if on { "ON" } else { "OFF" };
+        if self.command(Some(state))? == on {
+            Ok(())
+        } else {
+            Err(invalid_response(format!(
+                "{} did not turn {state}",
+                self.name
+            )))
+        }
+    }
+
+    fn power(&mut self) -> std::io::Result<Option<bool>> {
+        // Power without an argument only reports the state
+        self.command(None).map(Some)
+    }
+
+    fn meter(&mut self) -> std::io::Result<Option<Reading>> {
+        let status = self.send("Status 10")?;
+        let energy = &status["StatusSNS"]["ENERGY"];
+        // Devices with several channels report an array, one value per relay
+        let channel = |value: &serde_json::Value| match value.as_array() {
+            Some(values) => values
+                .get(self.relay.unwrap_or(1).saturating_sub(1) as usize)?
+                .as_f64(),
+            None => value.as_f64(),
+        };
+        // Devices without a meter have no ENERGY section
+        Ok(channel(&energy["Power"]).map(|watts| Reading {
+            watts,
+            total_kwh: channel(&energy["Total"]),
+        }))
+    }
+}
+
+impl Tasmota {
+    /// Sends a Power command, with `ON`, `OFF` or no argument, and returns the state it reports.
+    fn command(&self, argument: Option<&str>) -> std::io::Result<bool> {
+        let relay = self.relay.map(|r| r.to_string()).unwrap_or_default();
+        let command = match argument {
+            Some(argument) => format!("Power{relay} {argument}"),
+            None => format!("Power{relay}"),
+        };
+        let reply = self.send(&command)?;
+
+        // Replies with the state, as {"POWER":"ON"} or {"POWER2":"ON"}
+        let reported = reply
+            .get(format!("POWER{relay}"))
+            .or_else(|| reply.get("POWER"))
+            .and_then(|v| v.as_str());
+        match reported {
+            Some("ON") => Ok(true),
+            Some("OFF") => Ok(false),
+            _ => Err(invalid_response(format!(
+                "{} replied {reply} to {command}",
+                self.name
+            ))),
+        }
+    }
+
+    /// Sends any command and returns the reply.
+    fn send(&self, command: &str) -> std::io::Result<serde_json::Value> {
+        let mut url = self
+            .base
+            .join("cm")
+            .map_err(|why| std::io::Error::new(std::io::ErrorKind::InvalidInput, why))?;
+        url.query_pairs_mut().append_pair("cmnd", command);
+        if let Some(password) = &self.password {
+            url.query_pairs_mut()
+                .append_pair("user", TASMOTA_USER)
+                .append_pair("password", password);
+        }
+        self.client
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .map_err(http_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use super::*;
+
+    #[test]
+    fn sends_power_command() {
+        let mut server = mockito::Server::new();
+        let on = server
+            .mock("GET", "/cm")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("cmnd".into(), "Power2 ON".into()),
+                Matcher::UrlEncoded("user".into(), "admin".into()),
+                Matcher::UrlEncoded("password".into(), "hunter2".into()),
+            ]))
+            .with_body(r#"{"POWER2":"ON"}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        let mut switch = Tasmota::new(
+            url,
+            Some(2),
+            Some("hunter2".to_string()),
+            Duration::from_secs(5),
+        );
+        switch.set_power(true).unwrap();
+        on.assert();
+
+        // Errors get logged and published, so they must not give the password away
+        let url = Url::parse(&server.url()).unwrap().join("wrong/").unwrap();
+        let mut switch = Tasmota::new(
+            url,
+            Some(2),
+            Some("hunter2".to_string()),
+            Duration::from_secs(5),
+        );
+        let why = switch.set_power(true).unwrap_err().to_string();
+        assert!(!why.contains("hunter2"), "{why}");
+    }
+
+    #[test]
+    fn reads_back_power() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/cm")
+            .match_query(Matcher::UrlEncoded("cmnd".into(), "Power OFF".into()))
+            .with_body(r#"{"POWER":"ON"}"#)
+            .create();
+
+        server
+            .mock("GET", "/cm")
+            .match_query(Matcher::UrlEncoded("cmnd".into(), "Power".into()))
+            .with_body(r#"{"POWER":"ON"}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        let mut switch = Tasmota::new(url, None, None, Duration::from_secs(5));
+        assert!(switch.set_power(false).is_err());
+        assert_eq!(switch.power().unwrap(), Some(true));
+    }
+
+    #[test]
+    fn reads_energy_status() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/cm")
+            .match_query(Matcher::UrlEncoded("cmnd".into(), "Status 10".into()))
+            .with_body(r#"{"StatusSNS":{"Time":"2026-10-18T20:00:00","ENERGY":{"Total":[3.185,0.5],"Today":[0.2,0.0],"Power":[22,0],"Voltage":230}}}"#)
+            .create();
+
+        let url = Url::parse(&server.url()).unwrap();
+        let mut switch = Tasmota::new(url, Some(1), None, Duration::from_secs(5));
+        assert_eq!(
+            switch.meter().unwrap(),
+            Some(Reading {
+                watts: 22.0,
+                total_kwh: Some(3.185)
+            })
+        );
+    }
+}
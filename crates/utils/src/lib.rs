@@ -3,6 +3,9 @@ use std::fs;
 use std::io;
 use std::path::Path;
 
+pub mod state_file;
+pub mod time_of_day;
+
 /// Sets up the environment variables so that the current process can run wayland programs.
 pub fn setup_wayland_env() -> io::Result<()> {
     // Get the current user's UID
@@ -20,10 +23,10 @@ pub fn setup_wayland_env() -> io::Result<()> {
     if path.exists() && path.is_dir() {
         for entry in fs::read_dir(path)? {
             let entry = entry?;
-            if let Some(name) = entry.file_name().to_str() {
-                if name.starts_with("wayland-") {
-                    sockets.push(name.to_string());
-                }
+            if let Some(name) = entry.file_name().to_str()
+                && name.starts_with("wayland-")
+            {
+                sockets.push(name.to_string());
             }
         }
     }
@@ -53,12 +56,11 @@ pub fn setup_wayland_env() -> io::Result<()> {
     if Path::new(&x11_dir).exists() && Path::new(&x11_dir).is_dir() {
         for entry in fs::read_dir(&x11_dir)? {
             let entry = entry?;
-            if let Some(name) = entry.file_name().to_str() {
-                if name.starts_with("X") {
-                    if let Ok(num) = name[1..].parse::<u32>() {
-                        x11_sockets.push(num);
-                    }
-                }
+            if let Some(name) = entry.file_name().to_str()
+                && let Some(num) = name.strip_prefix('X')
+                && let Ok(num) = num.parse::<u32>()
+            {
+                x11_sockets.push(num);
             }
         }
     }
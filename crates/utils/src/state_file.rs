@@ -0,0 +1,88 @@
+//! State kept in a JSON file, so that it survives restarts.
+
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Reads the state saved at `path`. A missing file is the default, empty state.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(why) => return Err(why),
+    };
+    serde_json::from_str(&text).map_err(|why| io::Error::new(ErrorKind::InvalidData, why))
+}
+
+/// Like [`load`], but an unreadable file is reported through `log` and starts over too.
+/// `what` names the state in the message, like `energy log`.
+pub fn load_or_default<T: DeserializeOwned + Default>(
+    path: &Path,
+    what: &str,
+    log: impl FnOnce(String),
+) -> T {
+    load(path).unwrap_or_else(|why| {
+        log(format!(
+            "failed to load {what} from {}: {why}; starting over",
+            path.display()
+        ));
+        T::default()
+    })
+}
+
+/// Saves the state to `path`.
+pub fn save<T: Serialize>(path: &Path, state: &T) -> io::Result<()> {
+    let text = serde_json::to_string(state).expect("failed to serialize state");
+    // Write to a temporary file first, so a crash never leaves a truncated file behind.
+    // Named after the whole file name, so that state.json and state.cfg do not share one
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn state_files_with_the_same_stem_keep_their_own_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("energy.json");
+        let cfg = dir.path().join("energy.cfg");
+        // Both would write their temporary file here if it was named after the stem
+        std::fs::create_dir(dir.path().join("energy.tmp")).unwrap();
+        save(&json, &1).unwrap();
+        save(&cfg, &2).unwrap();
+        assert_eq!(load::<u32>(&json).unwrap(), 1);
+        assert_eq!(load::<u32>(&cfg).unwrap(), 2);
+    }
+
+    #[test]
+    fn saves_and_loads_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let empty: BTreeMap<String, u32> = load(&path).unwrap();
+        assert!(empty.is_empty());
+
+        let state = BTreeMap::from([("intel_backlight".to_string(), 420)]);
+        save(&path, &state).unwrap();
+        assert_eq!(load::<BTreeMap<String, u32>>(&path).unwrap(), state);
+        assert!(!dir.path().join("state.json.tmp").exists());
+
+        std::fs::write(&path, "{truncated").unwrap();
+        let error = load::<BTreeMap<String, u32>>(&path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let mut logged = None;
+        let levels: BTreeMap<String, u32> =
+            load_or_default(&path, "levels", |message| logged = Some(message));
+        assert!(levels.is_empty());
+        assert!(logged.unwrap().starts_with("failed to load levels from"));
+    }
+}
@@ -0,0 +1,78 @@
+//! Local times of day, as used by schedules that repeat every day.
+
+use std::time::Duration;
+
+use chrono::{NaiveTime, Timelike};
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Parses a `HH:MM` time, ignoring surrounding whitespace.
+pub fn parse(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|why| format!("invalid time {time:?}: {why}"))
+}
+
+/// Parses comma-separated `HH:MM=percent` points, sorted by time. There has to be at least one.
+pub fn parse_percentages(s: &str) -> Result<Vec<(NaiveTime, u8)>, String> {
+    let mut points = Vec::new();
+    for point in s.split(',') {
+        let (time, percent) = point
+            .split_once('=')
+            .ok_or_else(|| format!("expected HH:MM=percent, got {point:?}"))?;
+        let percent: u8 = percent
+            .trim()
+            .parse()
+            .map_err(|why| format!("invalid percentage {percent:?}: {why}"))?;
+        if percent > 100 {
+            return Err(format!("percentage {percent} is above 100"));
+        }
+        points.push((parse(time)?, percent));
+    }
+    points.sort_by_key(|(time, _)| *time);
+    Ok(points)
+}
+
+/// How long from `time` until the clock next reads `boundary`; a whole day if it does now.
+/// Rounded up, so that whoever sleeps this long does not wake up just before the boundary.
+pub fn until(time: NaiveTime, boundary: NaiveTime) -> Duration {
+    let now = time.num_seconds_from_midnight();
+    let start = boundary.num_seconds_from_midnight();
+    let seconds = match (start + SECONDS_PER_DAY - now) % SECONDS_PER_DAY {
+        0 => SECONDS_PER_DAY,
+        seconds => seconds,
+    };
+    Duration::from_secs(seconds as u64) - Duration::from_nanos(time.nanosecond() as u64)
+        + Duration::from_millis(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn parses_times_and_percentages() {
+        assert_eq!(parse(" 07:30 "), Ok(at("07:30:00.0")));
+        assert!(parse("25:00").is_err());
+        assert_eq!(
+            parse_percentages("22:00=35, 07:00=60"),
+            Ok(vec![(at("07:00:00.0"), 60), (at("22:00:00.0"), 35)])
+        );
+        assert!(parse_percentages("22:00").is_err());
+        assert!(parse_percentages("22:00=101").is_err());
+        assert!(parse_percentages("").is_err());
+    }
+
+    #[test]
+    fn waits_until_the_boundary_comes_around() {
+        let secs = |time, boundary| until(at(time), at(boundary)).as_secs_f64();
+        assert_eq!(secs("21:00:00.0", "22:00:00.0"), 60.0 * 60.0 + 0.001);
+        assert_eq!(secs("23:00:00.0", "07:00:00.0"), 8.0 * 60.0 * 60.0 + 0.001);
+        assert_eq!(secs("22:00:00.0", "22:00:00.0"), 24.0 * 60.0 * 60.0 + 0.001);
+        // Part of a second in is taken off
+        assert_eq!(secs("21:59:59.5", "22:00:00.0"), 0.501);
+    }
+}
@@ -1,35 +1,403 @@
-use std::time::Duration;
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
 
+use api_types::LidState;
 use clap::Parser;
+use mixer::{Backend, Mixer};
+use ramp::Ramp;
+use schedule::VolumeCaps;
+
+mod mixer;
+mod ramp;
+mod schedule;
+
+/// Time between two writes while ramping.
+const RAMP_STEP: Duration = Duration::from_millis(50);
+
+/// After setting the volume, it is checked this many more times and rewritten if it changed,
+/// because the sound server sometimes resets it right after a lid event.
+const SETTLE_CHECKS: u32 = 2;
+const SETTLE_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(clap::Parser)]
 struct Args {
     /// The volume to set when the lid is open
     #[clap(short, long, default_value = "100")]
     volume: u8,
+
+    /// Caps on that volume by local time, as comma-separated HH:MM=percent slots
+    /// that each last until the next one begins, e.g. 07:00=60,22:00=35
+    #[clap(long)]
+    volume_caps: Option<VolumeCaps>,
+
+    /// How long it takes to turn the volume up, in milliseconds
+    #[clap(long, default_value = "1000")]
+    ramp_up_ms: u64,
+
+    /// How long it takes to turn the volume down, in milliseconds
+    #[clap(long, default_value = "500")]
+    ramp_down_ms: u64,
+
+    /// Mute when the lid closes instead of setting the volume to 0%, so the level is kept
+    #[clap(long)]
+    mute: bool,
+
+    /// How to set the volume
+    #[clap(long, value_enum, default_value = "alsa")]
+    backend: Backend,
+
+    /// Sound card, by number or ID (as in /proc/asound/cards)
+    #[clap(long, default_value = "0")]
+    card: String,
+
+    /// Simple mixer control to set, like amixer's
+    #[clap(long, default_value = "Master")]
+    control: String,
+
+    /// PulseAudio server socket (defaults to the user's, in $XDG_RUNTIME_DIR)
+    #[clap(long, env = "PULSE_SERVER")]
+    server: Option<String>,
+
+    /// PulseAudio sink to set (as in `pactl list sinks short`) instead of the default sink
+    #[clap(long)]
+    sink: Option<String>,
+
+    /// CEC adapter to reach the audio system through
+    #[clap(long, default_value = "/dev/cec0")]
+    cec_device: PathBuf,
+}
+
+enum Event {
+    Lid(LidState),
+    /// The sink being controlled changed or reappeared, so the volume has to be set on it again.
+    Sink(std::io::Result<String>),
+    /// A new volume cap slot began.
+    Slot,
+}
+
+/// The state the mixer should end up in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    Volume(u8),
+    /// Muted, with the volume level left where it was before ramping down
+    Muted,
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Volume(volume) => write!(f, "{volume}%"),
+            Self::Muted => write!(f, "muted"),
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
+    let mut mixer: Box<dyn Mixer> = match args.backend {
+        Backend::Alsa => Box::new(
+            mixer::alsa::AlsaMixer::open(&args.card, &args.control)
+                .expect("failed to open ALSA mixer"),
+        ),
+        Backend::Pulse => Box::new(
+            mixer::pulse::PulseMixer::open(args.server.as_deref(), args.sink.clone())
+                .expect("failed to connect to sound server"),
+        ),
+        Backend::Cec => Box::new(
+            mixer::cec::CecMixer::open(&args.cec_device).expect("failed to open CEC adapter"),
+        ),
+    };
+    // A configured sink may not exist yet, e.g. HDMI while the TV is off
+    match mixer.volume() {
+        Ok(current) => println!("using mixer {} (currently at {current}%)", mixer.name()),
+        Err(why) => eprintln!("cannot read volume of {}: {why}", mixer.name()),
+    }
+
     let lid_stream =
         lid_subscriber::LidSubscriber::new().expect("failed to connect to lid status socket");
 
-    for event in lid_stream {
-        let new_volume = if event.lid_open { args.volume } else { 0 };
-        println!(
-            "New state at {}, setting volume to {}",
-            event.changed_at, new_volume
-        );
-        for i in 0..3 {
-            println!("write {}/3", i + 1);
-            let mut process = std::process::Command::new("amixer")
-                .arg("set")
-                .arg("Master")
-                .arg(format!("{new_volume}%"))
-                .spawn()
-                .expect("failed to run amixer");
-            process.wait().expect("failed to wait for amixer");
-            std::thread::sleep(Duration::from_millis(250));
+    let (tx, events) = mpsc::channel();
+    if args.backend == Backend::Pulse {
+        let connection = mixer::pulse::connect(args.server.as_deref())
+            .expect("failed to connect to sound server");
+        let watcher = mixer::pulse::SinkWatcher::new(connection, args.sink.clone())
+            .expect("failed to watch sinks");
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            let result = watcher.run(|sink| {
+                let _ = tx.send(Event::Sink(Ok(sink)));
+            });
+            if let Err(why) = result {
+                let _ = tx.send(Event::Sink(Err(why)));
+            }
+        });
+    }
+    std::thread::spawn(move || {
+        for event in lid_stream {
+            if tx.send(Event::Lid(event)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut target = None;
+    let mut lid_open = false;
+    let mut next_event = events.recv().ok();
+    while let Some(event) = next_event.take() {
+        match event {
+            Event::Lid(event) => {
+                lid_open = event.lid_open;
+                let new_target = if event.lid_open {
+                    Target::Volume(open_volume(&args))
+                } else if args.mute {
+                    Target::Muted
+                } else {
+                    Target::Volume(0)
+                };
+                println!(
+                    "New state at {}, setting volume to {}",
+                    event.changed_at, new_target
+                );
+                target = Some(new_target);
+            }
+            Event::Sink(Ok(sink)) => {
+                if let Some(target) = target {
+                    println!("now controlling sink {sink}, setting volume to {target} again");
+                }
+            }
+            Event::Sink(Err(why)) => {
+                eprintln!(
+                    "lost track of sinks, so sinks that appear from now on go unnoticed: {why}"
+                )
+            }
+            Event::Slot => {
+                let new_target = Target::Volume(open_volume(&args));
+                println!("volume cap changed, setting volume to {new_target}");
+                target = Some(new_target);
+            }
+        }
+        next_event = match target {
+            // The sink may be gone, e.g. HDMI while the TV is off; the target is kept
+            // and set again when the sink appears
+            Some(target) => {
+                run_ramp(mixer.as_mut(), &args, target, &events).unwrap_or_else(|why| {
+                    eprintln!(
+                        "failed to set volume of {} to {target}: {why}",
+                        mixer.name()
+                    );
+                    None
+                })
+            }
+            None => None,
+        }
+        .or_else(|| wait(&events, args.volume_caps.as_ref(), lid_open));
+    }
+}
+
+/// The volume to set when the lid is open, capped for the current time of day.
+fn open_volume(args: &Args) -> u8 {
+    match &args.volume_caps {
+        Some(caps) => caps.cap_at(chrono::Local::now().time()).min(args.volume),
+        None => args.volume,
+    }
+}
+
+/// Waits for the next event. While the lid is open, the start of a new volume cap slot is one too.
+fn wait(events: &Receiver<Event>, caps: Option<&VolumeCaps>, lid_open: bool) -> Option<Event> {
+    let Some(caps) = caps.filter(|_| lid_open) else {
+        return events.recv().ok();
+    };
+    match events.recv_timeout(caps.until_next_slot(chrono::Local::now().time())) {
+        Ok(event) => Some(event),
+        Err(RecvTimeoutError::Timeout) => Some(Event::Slot),
+        Err(RecvTimeoutError::Disconnected) => None,
+    }
+}
+
+/// Ramps the volume to the target, then keeps an eye on it for a moment and sets it again
+/// if something changed it. Returns early with the new event if one arrives in the meantime.
+fn run_ramp(
+    mixer: &mut dyn Mixer,
+    args: &Args,
+    target: Target,
+    events: &Receiver<Event>,
+) -> std::io::Result<Option<Event>> {
+    let level = mixer.volume()?;
+    // Only look at the switch if we use it: not every control has one
+    let muted = args.mute && mixer.muted()?;
+    let (from, to) = match target {
+        Target::Muted if muted => (level, level),
+        Target::Muted => (level, 0),
+        Target::Volume(volume) if muted => {
+            // Start from silence rather than jumping to the level that was kept while muted
+            if mixer.can_ramp() {
+                mixer.set_volume(0)?;
+            }
+            mixer.set_mute(false)?;
+            (0, volume)
+        }
+        Target::Volume(volume) => (level, volume),
+    };
+
+    if from != to && mixer.can_ramp() {
+        let duration = if to > from {
+            args.ramp_up_ms
+        } else {
+            args.ramp_down_ms
+        };
+        let ramp = Ramp {
+            from,
+            to,
+            duration: Duration::from_millis(duration),
+        };
+        for volume in ramp.steps(RAMP_STEP) {
+            mixer.set_volume(volume)?;
+            match events.recv_timeout(RAMP_STEP) {
+                Ok(event) => {
+                    println!("new event during ramp, stopping");
+                    return Ok(Some(event));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
         }
     }
+
+    finish(mixer, target, level)?;
+    for _ in 0..SETTLE_CHECKS {
+        match events.recv_timeout(SETTLE_INTERVAL) {
+            Ok(event) => return Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+        let reached = match target {
+            Target::Volume(volume) => mixer.volume()?.abs_diff(volume) <= 1,
+            Target::Muted => mixer.muted()?,
+        };
+        if !reached {
+            println!("volume changed behind our back, setting it to {target} again");
+            finish(mixer, target, level)?;
+        }
+    }
+    Ok(None)
+}
+
+/// Puts the mixer in the target state; `level` is the volume to leave behind when muting.
+fn finish(mixer: &mut dyn Mixer, target: Target, level: u8) -> std::io::Result<()> {
+    match target {
+        Target::Volume(volume) => mixer::set_and_verify(mixer, volume),
+        Target::Muted => {
+            mixer.set_mute(true)?;
+            mixer.set_volume(level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mixer::mock::MockMixer;
+
+    fn args(extra: &[&str]) -> Args {
+        let base = [
+            "volume-control",
+            "--ramp-up-ms",
+            "200",
+            "--ramp-down-ms",
+            "200",
+        ];
+        Args::parse_from(base.iter().chain(extra))
+    }
+
+    #[test]
+    fn ramps_up_and_settles() {
+        let mock = MockMixer::new(0);
+        let (_tx, events) = mpsc::channel();
+        let result = run_ramp(&mut mock.clone(), &args(&[]), Target::Volume(60), &events);
+
+        assert!(result.unwrap().is_none());
+        let writes = mock.writes();
+        assert_eq!(writes.last(), Some(&60));
+        assert!(writes.len() > 2);
+        assert!(writes.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    /// A mixer that is too slow to ramp.
+    struct Slow(MockMixer);
+
+    impl Mixer for Slow {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn volume(&mut self) -> std::io::Result<u8> {
+            self.0.volume()
+        }
+
+        fn set_volume(&mut self, percent: u8) -> std::io::Result<()> {
+            self.0.set_volume(percent)
+        }
+
+        fn muted(&mut self) -> std::io::Result<bool> {
+            self.0.muted()
+        }
+
+        fn set_mute(&mut self, muted: bool) -> std::io::Result<()> {
+            self.0.set_mute(muted)
+        }
+
+        fn can_ramp(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn slow_mixers_go_straight_to_target() {
+        let mock = MockMixer::new(0);
+        let (_tx, events) = mpsc::channel();
+        let result = run_ramp(
+            &mut Slow(mock.clone()),
+            &args(&[]),
+            Target::Volume(60),
+            &events,
+        );
+
+        assert!(result.unwrap().is_none());
+        assert_eq!(mock.writes(), [60]);
+    }
+
+    #[test]
+    fn new_event_cancels_ramp() {
+        let mock = MockMixer::new(80);
+        let (tx, events) = mpsc::channel();
+        tx.send(Event::Lid(LidState {
+            lid_open: true,
+            changed_at: api_types::now(),
+        }))
+        .unwrap();
+
+        let result = run_ramp(&mut mock.clone(), &args(&[]), Target::Volume(0), &events);
+        assert!(matches!(result, Ok(Some(Event::Lid(_)))));
+        let volume = mock.clone().volume().unwrap();
+        assert!(volume > 0 && volume < 80, "stopped at {volume}");
+    }
+
+    #[test]
+    fn mute_keeps_level() {
+        let mock = MockMixer::new(60);
+        let args = args(&["--mute"]);
+        let (_tx, events) = mpsc::channel();
+
+        run_ramp(&mut mock.clone(), &args, Target::Muted, &events).unwrap();
+        assert!(mock.clone().muted().unwrap());
+        assert_eq!(mock.clone().volume().unwrap(), 60);
+        assert!(mock.writes().contains(&0));
+
+        run_ramp(&mut mock.clone(), &args, Target::Volume(60), &events).unwrap();
+        assert!(!mock.clone().muted().unwrap());
+        assert_eq!(mock.clone().volume().unwrap(), 60);
+    }
 }
@@ -0,0 +1,116 @@
+pub mod alsa;
+pub mod cec;
+#[cfg(test)]
+pub mod mock;
+pub mod pulse;
+
+/// Something whose playback volume can be read and set.
+pub trait Mixer: Send {
+    /// Name of the mixer, for logging.
+    fn name(&self) -> &str;
+
+    /// Current volume, as a percentage of the control's range.
+    fn volume(&mut self) -> std::io::Result<u8>;
+
+    fn set_volume(&mut self, percent: u8) -> std::io::Result<()>;
+
+    fn muted(&mut self) -> std::io::Result<bool>;
+
+    /// Mutes or unmutes without touching the volume level.
+    fn set_mute(&mut self, muted: bool) -> std::io::Result<()>;
+
+    /// Whether setting the volume is quick enough to ramp it in small steps.
+    /// Mixers that are not go straight to the ramp's target.
+    fn can_ramp(&self) -> bool {
+        true
+    }
+}
+
+/// How the volume gets written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Talk to the ALSA control device (/dev/snd/controlC*) directly
+    Alsa,
+    /// Set a sink's volume through PulseAudio, or PipeWire's PulseAudio server
+    Pulse,
+    /// Pass volume changes on to the TV's audio system over HDMI-CEC
+    Cec,
+}
+
+/// Sets the volume and reads it back, failing if the mixer did not take it.
+/// Mixers round to their own steps, so a difference of one percent is accepted.
+pub fn set_and_verify(mixer: &mut dyn Mixer, percent: u8) -> std::io::Result<()> {
+    mixer.set_volume(percent)?;
+    let actual = mixer.volume()?;
+    if actual.abs_diff(percent) > 1 {
+        return Err(std::io::Error::other(format!(
+            "{} reports {actual}% after setting {percent}%",
+            mixer.name()
+        )));
+    }
+    Ok(())
+}
+
+/// Converts a percentage to a raw value in `min..=max`, the way amixer does.
+pub fn percent_to_raw(percent: u8, min: i64, max: i64) -> i64 {
+    let percent = percent.min(100) as i64;
+    min + ((max - min) * percent + 50) / 100
+}
+
+/// Converts a raw value in `min..=max` to a percentage.
+pub fn raw_to_percent(raw: i64, min: i64, max: i64) -> u8 {
+    if max <= min {
+        return 0;
+    }
+    let raw = raw.clamp(min, max);
+    (((raw - min) * 100 + (max - min) / 2) / (max - min)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockMixer, *};
+
+    /// A mixer that ignores writes, like a control held by another program.
+    struct StuckMixer;
+
+    impl Mixer for StuckMixer {
+        fn name(&self) -> &str {
+            "stuck"
+        }
+
+        fn volume(&mut self) -> std::io::Result<u8> {
+            Ok(20)
+        }
+
+        fn set_volume(&mut self, _percent: u8) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn muted(&mut self) -> std::io::Result<bool> {
+            Ok(false)
+        }
+
+        fn set_mute(&mut self, _muted: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn percent_conversion_round_trips() {
+        for percent in 0..=100 {
+            let raw = percent_to_raw(percent, 0, 87);
+            assert!(raw_to_percent(raw, 0, 87).abs_diff(percent) <= 1);
+        }
+        assert_eq!(percent_to_raw(60, -6400, 0), -2560);
+        assert_eq!(raw_to_percent(-2560, -6400, 0), 60);
+    }
+
+    #[test]
+    fn verify_catches_ignored_writes() {
+        let mut mock = MockMixer::new(0);
+        set_and_verify(&mut mock, 60).unwrap();
+        assert_eq!(mock.writes(), [60]);
+
+        assert!(set_and_verify(&mut StuckMixer, 60).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! A mixer backed by the ALSA control interface, using the kernel's ioctls
+//! on `/dev/snd/controlC*` directly instead of going through alsa-lib or amixer.
+//!
+//! The structs below mirror the kernel's uapi/sound/asound.h.
+
+use std::{
+    ffi::c_long,
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use super::{Mixer, percent_to_raw, raw_to_percent};
+
+const ELEM_NAME_MAXLEN: usize = 44;
+/// `SNDRV_CTL_ELEM_IFACE_MIXER`
+const IFACE_MIXER: i32 = 2;
+/// `SNDRV_CTL_ELEM_TYPE_BOOLEAN`
+const TYPE_BOOLEAN: i32 = 1;
+/// `SNDRV_CTL_ELEM_TYPE_INTEGER`
+const TYPE_INTEGER: i32 = 2;
+
+/// `struct snd_ctl_elem_id`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElemId {
+    numid: u32,
+    iface: i32,
+    device: u32,
+    subdevice: u32,
+    name: [u8; ELEM_NAME_MAXLEN],
+    index: u32,
+}
+
+/// The `value` union of `struct snd_ctl_elem_info`
+#[repr(C)]
+#[derive(Clone, Copy)]
+union ElemInfoValue {
+    /// min, max, step
+    integer: [c_long; 3],
+    integer64: [i64; 3],
+    reserved: [u8; 128],
+}
+
+/// `struct snd_ctl_elem_info`
+#[repr(C)]
+struct ElemInfo {
+    id: ElemId,
+    kind: i32,
+    access: u32,
+    count: u32,
+    owner: i32,
+    value: ElemInfoValue,
+    reserved: [u8; 64],
+}
+
+/// The `value` union of `struct snd_ctl_elem_value`
+#[repr(C)]
+#[derive(Clone, Copy)]
+union ElemValueData {
+    integer: [c_long; 128],
+    integer64: [i64; 64],
+    bytes: [u8; 512],
+}
+
+/// `struct snd_ctl_elem_value`
+#[repr(C)]
+struct ElemValue {
+    id: ElemId,
+    indirect: u32,
+    value: ElemValueData,
+    reserved: [u8; 128],
+}
+
+/// `struct snd_ctl_card_info`
+#[repr(C)]
+struct CardInfo {
+    card: i32,
+    pad: i32,
+    id: [u8; 16],
+    driver: [u8; 16],
+    name: [u8; 32],
+    longname: [u8; 80],
+    reserved: [u8; 16],
+    mixername: [u8; 80],
+    components: [u8; 128],
+}
+
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+    assert!(size_of::<ElemId>() == 64);
+    assert!(size_of::<ElemInfo>() == 272);
+    assert!(size_of::<ElemValue>() == 1224);
+    assert!(size_of::<CardInfo>() == 376);
+};
+
+nix::ioctl_read!(ctl_card_info, b'U', 0x01, CardInfo);
+nix::ioctl_readwrite!(ctl_elem_info, b'U', 0x11, ElemInfo);
+nix::ioctl_readwrite!(ctl_elem_read, b'U', 0x12, ElemValue);
+nix::ioctl_readwrite!(ctl_elem_write, b'U', 0x13, ElemValue);
+
+impl ElemId {
+    fn mixer(name: &str) -> std::io::Result<Self> {
+        let mut id = Self {
+            numid: 0,
+            iface: IFACE_MIXER,
+            device: 0,
+            subdevice: 0,
+            name: [0; ELEM_NAME_MAXLEN],
+            index: 0,
+        };
+        if name.len() >= ELEM_NAME_MAXLEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("control name {name:?} is too long"),
+            ));
+        }
+        id.name[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(id)
+    }
+}
+
+/// Location of the control device for a card given by number (`0`) or ID (`PCH`).
+pub fn control_device(card: &str) -> std::io::Result<PathBuf> {
+    let number = match card.parse::<u32>() {
+        Ok(number) => number,
+        Err(_) => {
+            // /proc/asound/<id> is a symlink to card<number>
+            let target = std::fs::read_link(Path::new("/proc/asound").join(card))?;
+            target
+                .to_string_lossy()
+                .strip_prefix("card")
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("cannot find the number of sound card {card:?}"),
+                    )
+                })?
+        }
+    };
+    Ok(PathBuf::from(format!("/dev/snd/controlC{number}")))
+}
+
+/// A simple mixer control (like amixer's `Master`), driven through its `<name> Playback Volume`
+/// element and, for muting, its `<name> Playback Switch` element if it has one.
+pub struct AlsaMixer {
+    name: String,
+    device: File,
+    volume: ElemId,
+    channels: u32,
+    min: i64,
+    max: i64,
+    /// The switch element and its number of channels
+    switch: Option<(ElemId, u32)>,
+}
+
+impl AlsaMixer {
+    /// Opens a simple control (e.g. `Master`) on a card (e.g. `0` or `PCH`).
+    pub fn open(card: &str, control: &str) -> std::io::Result<Self> {
+        let path = control_device(card)?;
+        let device = File::options().read(true).write(true).open(&path)?;
+
+        let mut card_info: CardInfo = unsafe { std::mem::zeroed() };
+        unsafe { ctl_card_info(device.as_raw_fd(), &mut card_info) }?;
+        let card_name = c_string(&card_info.name);
+
+        let volume = ElemId::mixer(&format!("{control} Playback Volume"))?;
+        let mut info: ElemInfo = unsafe { std::mem::zeroed() };
+        info.id = volume;
+        unsafe { ctl_elem_info(device.as_raw_fd(), &mut info) }.map_err(|why| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("card {card_name} has no control {control:?}: {why}"),
+            )
+        })?;
+        if info.kind != TYPE_INTEGER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("control {control:?} is not an integer volume"),
+            ));
+        }
+        let [min, max, _] = unsafe { info.value.integer };
+
+        let mut switch: ElemInfo = unsafe { std::mem::zeroed() };
+        switch.id = ElemId::mixer(&format!("{control} Playback Switch"))?;
+        let switch = match unsafe { ctl_elem_info(device.as_raw_fd(), &mut switch) } {
+            Ok(_) if switch.kind == TYPE_BOOLEAN => Some((switch.id, switch.count.min(128))),
+            _ => None,
+        };
+
+        Ok(Self {
+            name: format!("{card_name}/{control}"),
+            device,
+            // the kernel fills in the numeric ID, which makes later lookups cheaper
+            volume: info.id,
+            // an element holds at most 128 integer values
+            channels: info.count.min(128),
+            min: long(min),
+            max: long(max),
+            switch,
+        })
+    }
+
+    fn read(&self, id: ElemId) -> std::io::Result<ElemValue> {
+        let mut value: ElemValue = unsafe { std::mem::zeroed() };
+        value.id = id;
+        unsafe { ctl_elem_read(self.device.as_raw_fd(), &mut value) }?;
+        Ok(value)
+    }
+
+    fn write(&self, value: &mut ElemValue) -> std::io::Result<()> {
+        unsafe { ctl_elem_write(self.device.as_raw_fd(), value) }?;
+        Ok(())
+    }
+}
+
+impl Mixer for AlsaMixer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn volume(&mut self) -> std::io::Result<u8> {
+        let value = self.read(self.volume)?;
+        let channels = unsafe { &value.value.integer[..self.channels as usize] };
+        // Report the loudest channel, like amixer's summary does
+        let raw = channels.iter().copied().max().unwrap_or_default();
+        Ok(raw_to_percent(long(raw), self.min, self.max))
+    }
+
+    fn set_volume(&mut self, percent: u8) -> std::io::Result<()> {
+        let raw = percent_to_raw(percent, self.min, self.max) as c_long;
+        let mut value: ElemValue = unsafe { std::mem::zeroed() };
+        value.id = self.volume;
+        for channel in 0..self.channels as usize {
+            unsafe { value.value.integer[channel] = raw };
+        }
+        self.write(&mut value)
+    }
+
+    fn muted(&mut self) -> std::io::Result<bool> {
+        let Some((switch, channels)) = self.switch else {
+            return Ok(false);
+        };
+        let value = self.read(switch)?;
+        // The switch is on when sound plays; muted means every channel is off
+        let channels = unsafe { &value.value.integer[..channels as usize] };
+        Ok(channels.iter().all(|&on| on == 0))
+    }
+
+    fn set_mute(&mut self, muted: bool) -> std::io::Result<()> {
+        let (switch, channels) = self.switch.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} has no mute switch", self.name),
+            )
+        })?;
+        let mut value: ElemValue = unsafe { std::mem::zeroed() };
+        value.id = switch;
+        for channel in 0..channels as usize {
+            unsafe { value.value.integer[channel] = c_long::from(!muted) };
+        }
+        self.write(&mut value)
+    }
+}
+
+/// `c_long` is `i64` on 64-bit targets, but `i32` on 32-bit ones.
+#[allow(clippy::useless_conversion)]
+fn long(value: c_long) -> i64 {
+    i64::from(value)
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_mixer_elements() {
+        let id = ElemId::mixer("Master Playback Volume").unwrap();
+        assert_eq!(id.iface, IFACE_MIXER);
+        assert_eq!((id.numid, id.device, id.index), (0, 0, 0));
+        assert_eq!(c_string(&id.name), "Master Playback Volume");
+        // The name has to leave room for its NUL terminator
+        assert!(ElemId::mixer(&"x".repeat(ELEM_NAME_MAXLEN - 1)).is_ok());
+        assert!(ElemId::mixer(&"x".repeat(ELEM_NAME_MAXLEN)).is_err());
+    }
+
+    #[test]
+    fn finds_control_device_by_number() {
+        assert_eq!(
+            control_device("1").unwrap(),
+            PathBuf::from("/dev/snd/controlC1")
+        );
+        assert_eq!(c_string(b"HDA Intel PCH\0\0garbage"), "HDA Intel PCH");
+        assert_eq!(c_string(b"unterminated"), "unterminated");
+        assert_eq!(long(-1), -1);
+    }
+}
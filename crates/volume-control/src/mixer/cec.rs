@@ -0,0 +1,121 @@
+//! A mixer that passes volume changes on to the audio system (an AV receiver or soundbar)
+//! over HDMI-CEC, by pressing its volume keys until it reports the wanted level.
+
+use std::{path::Path, time::Duration};
+
+use cec::{
+    AudioStatus, CecDevice,
+    message::{AUDIO_SYSTEM, key, opcode},
+};
+
+use super::Mixer;
+
+/// How long to wait for the audio system to report its status.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// Audio systems step by one or a few percent per key press, so this goes all the way.
+const MAX_PRESSES: usize = 100;
+
+pub struct CecMixer {
+    device: CecDevice,
+    name: String,
+}
+
+impl CecMixer {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let device = CecDevice::open(path, cec::DEFAULT_OSD_NAME, false)?;
+        let name = format!("cec:{}", device.name());
+        Ok(Self { device, name })
+    }
+
+    fn status(&self) -> std::io::Result<AudioStatus> {
+        let reply = self.device.request(
+            AUDIO_SYSTEM,
+            opcode::GIVE_AUDIO_STATUS,
+            &[],
+            opcode::REPORT_AUDIO_STATUS,
+            REPLY_TIMEOUT,
+        )?;
+        let operand = reply.operands.first().copied().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "empty audio status")
+        })?;
+        Ok(AudioStatus::from_operand(operand))
+    }
+
+    fn press(&self, key: u8) -> std::io::Result<()> {
+        self.device
+            .send(AUDIO_SYSTEM, opcode::USER_CONTROL_PRESSED, &[key])?;
+        self.device
+            .send(AUDIO_SYSTEM, opcode::USER_CONTROL_RELEASED, &[])
+    }
+}
+
+/// The key that brings the volume closer to `target`, or `None` once it is close enough.
+fn volume_key(current: u8, target: u8) -> Option<u8> {
+    if current.abs_diff(target) <= 1 {
+        None
+    } else if current < target {
+        Some(key::VOLUME_UP)
+    } else {
+        Some(key::VOLUME_DOWN)
+    }
+}
+
+impl Mixer for CecMixer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn volume(&mut self) -> std::io::Result<u8> {
+        self.status()?
+            .volume
+            .ok_or_else(|| std::io::Error::other("the audio system does not report its volume"))
+    }
+
+    fn set_volume(&mut self, percent: u8) -> std::io::Result<()> {
+        let mut last = None;
+        for _ in 0..MAX_PRESSES {
+            let current = self.volume()?;
+            let Some(key) = volume_key(current, percent) else {
+                return Ok(());
+            };
+            // Stop if a key press does not change anything, e.g. at the audio system's limit
+            if last == Some(current) {
+                break;
+            }
+            last = Some(current);
+            self.press(key)?;
+        }
+        Ok(())
+    }
+
+    fn muted(&mut self) -> std::io::Result<bool> {
+        Ok(self.status()?.muted)
+    }
+
+    fn set_mute(&mut self, muted: bool) -> std::io::Result<()> {
+        // Unlike the mute key, these do not toggle
+        if muted {
+            self.press(key::MUTE_FUNCTION)
+        } else {
+            self.press(key::RESTORE_VOLUME_FUNCTION)
+        }
+    }
+
+    /// Every step asks for the status again, which can take up to a second each time
+    fn can_ramp(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_towards_target() {
+        assert_eq!(volume_key(20, 40), Some(key::VOLUME_UP));
+        assert_eq!(volume_key(40, 20), Some(key::VOLUME_DOWN));
+        assert_eq!(volume_key(39, 40), None);
+        assert_eq!(volume_key(40, 40), None);
+    }
+}
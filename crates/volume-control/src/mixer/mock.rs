@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+
+use super::Mixer;
+
+/// An in-memory mixer. Clones share the same state,
+/// so a test can keep one to inspect what the controller wrote.
+#[derive(Clone, Default)]
+pub struct MockMixer {
+    volume: Arc<Mutex<u8>>,
+    muted: Arc<Mutex<bool>>,
+    writes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockMixer {
+    pub fn new(volume: u8) -> Self {
+        Self {
+            volume: Arc::new(Mutex::new(volume)),
+            muted: Arc::default(),
+            writes: Arc::default(),
+        }
+    }
+
+    /// Every volume written so far, in order.
+    pub fn writes(&self) -> Vec<u8> {
+        self.writes.lock().expect("failed to lock writes").clone()
+    }
+}
+
+impl Mixer for MockMixer {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn volume(&mut self) -> std::io::Result<u8> {
+        Ok(*self.volume.lock().expect("failed to lock volume"))
+    }
+
+    fn set_volume(&mut self, percent: u8) -> std::io::Result<()> {
+        *self.volume.lock().expect("failed to lock volume") = percent;
+        self.writes
+            .lock()
+            .expect("failed to lock writes")
+            .push(percent);
+        Ok(())
+    }
+
+    fn muted(&mut self) -> std::io::Result<bool> {
+        Ok(*self.muted.lock().expect("failed to lock mute"))
+    }
+
+    fn set_mute(&mut self, muted: bool) -> std::io::Result<()> {
+        *self.muted.lock().expect("failed to lock mute") = muted;
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+//! A mixer that sets the volume of a PulseAudio (or pipewire-pulse) sink.
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use protocol::{Connection, TagReader};
+
+use super::Mixer;
+
+pub mod protocol;
+
+const CLIENT_NAME: &str = "volume-control";
+
+/// Finds the server socket: `server` (as in `$PULSE_SERVER`, `unix:` prefix optional),
+/// otherwise the per-user socket in `$XDG_RUNTIME_DIR`.
+pub fn socket_path(server: Option<&str>) -> std::io::Result<PathBuf> {
+    if let Some(server) = server {
+        return match server.strip_prefix("unix:") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None if server.starts_with('/') => Ok(PathBuf::from(server)),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("only unix sockets are supported, not {server:?}"),
+            )),
+        };
+    }
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "XDG_RUNTIME_DIR is not set, cannot find the sound server",
+        )
+    })?;
+    Ok(PathBuf::from(runtime_dir).join("pulse/native"))
+}
+
+/// The auth cookie, if the user has one. PipeWire does not need it.
+fn read_cookie() -> Option<Vec<u8>> {
+    let path = match std::env::var_os("PULSE_COOKIE") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config/pulse/cookie"),
+    };
+    std::fs::read(path).ok()
+}
+
+pub fn connect(server: Option<&str>) -> std::io::Result<Connection<UnixStream>> {
+    let path = socket_path(server)?;
+    let stream = UnixStream::connect(&path).map_err(|why| {
+        std::io::Error::new(
+            why.kind(),
+            format!(
+                "cannot connect to sound server at {}: {why}",
+                path.display()
+            ),
+        )
+    })?;
+    Connection::new(stream, read_cookie().as_deref(), CLIENT_NAME)
+}
+
+/// Name of the server's current default sink, if it has one.
+pub fn default_sink<T: Read + Write>(
+    connection: &mut Connection<T>,
+) -> std::io::Result<Option<String>> {
+    let mut reply = connection.request(protocol::COMMAND_GET_SERVER_INFO, |_| {})?;
+    // package name, package version, user name, host name
+    for _ in 0..4 {
+        reply.string()?;
+    }
+    reply.sample_spec()?;
+    reply.string()
+}
+
+struct SinkInfo {
+    index: u32,
+    name: String,
+    volumes: Vec<u32>,
+    muted: bool,
+}
+
+/// Looks a sink up by index, or by name if `index` is [`protocol::INVALID_INDEX`].
+fn sink_info<T: Read + Write>(
+    connection: &mut Connection<T>,
+    index: u32,
+    name: Option<&str>,
+) -> std::io::Result<SinkInfo> {
+    let mut reply = connection.request(protocol::COMMAND_GET_SINK_INFO, |w| {
+        w.u32(index).string(name);
+    })?;
+    read_sink_info(&mut reply)
+}
+
+fn read_sink_info(reply: &mut TagReader) -> std::io::Result<SinkInfo> {
+    let index = reply.u32()?;
+    let name = reply.string()?.unwrap_or_default();
+    let _description = reply.string()?;
+    reply.sample_spec()?;
+    reply.channel_map()?;
+    let _owner_module = reply.u32()?;
+    let volumes = reply.cvolume()?;
+    let muted = reply.bool()?;
+    Ok(SinkInfo {
+        index,
+        name,
+        volumes,
+        muted,
+    })
+}
+
+fn volume_to_percent(volume: u32) -> u8 {
+    let percent =
+        (volume as u64 * 100 + protocol::VOLUME_NORM as u64 / 2) / protocol::VOLUME_NORM as u64;
+    percent.min(u8::MAX as u64) as u8
+}
+
+fn percent_to_volume(percent: u8) -> u32 {
+    ((percent.min(100) as u64 * protocol::VOLUME_NORM as u64 + 50) / 100) as u32
+}
+
+/// Sets the volume of one sink, or of whichever sink is the default at the time.
+pub struct PulseMixer<T> {
+    name: String,
+    sink: Option<String>,
+    connection: Connection<T>,
+}
+
+impl PulseMixer<UnixStream> {
+    /// Connects to the server; `sink` is a sink name (as in `pactl list sinks short`),
+    /// or `None` to follow the default sink.
+    pub fn open(server: Option<&str>, sink: Option<String>) -> std::io::Result<Self> {
+        Ok(Self::new(connect(server)?, sink))
+    }
+}
+
+impl<T: Read + Write> PulseMixer<T> {
+    pub fn new(connection: Connection<T>, sink: Option<String>) -> Self {
+        Self {
+            name: sink.clone().unwrap_or_else(|| "default sink".to_string()),
+            sink,
+            connection,
+        }
+    }
+
+    fn target(&mut self) -> std::io::Result<SinkInfo> {
+        let name = match &self.sink {
+            Some(name) => name.clone(),
+            None => default_sink(&mut self.connection)?.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "sound server has no default sink",
+                )
+            })?,
+        };
+        let info = sink_info(&mut self.connection, protocol::INVALID_INDEX, Some(&name))
+            .map_err(|why| std::io::Error::new(why.kind(), format!("sink {name}: {why}")))?;
+        self.name = format!("pulse:{}", info.name);
+        Ok(info)
+    }
+}
+
+impl<T: Read + Write + Send> Mixer for PulseMixer<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn volume(&mut self) -> std::io::Result<u8> {
+        let info = self.target()?;
+        // Report the loudest channel, like the ALSA mixer does
+        let volume = info.volumes.iter().copied().max().unwrap_or_default();
+        Ok(volume_to_percent(volume))
+    }
+
+    fn set_volume(&mut self, percent: u8) -> std::io::Result<()> {
+        let info = self.target()?;
+        let volumes = vec![percent_to_volume(percent); info.volumes.len()];
+        self.connection
+            .request(protocol::COMMAND_SET_SINK_VOLUME, |w| {
+                w.u32(info.index).string(None).cvolume(&volumes);
+            })
+            .map(|_| ())
+    }
+
+    fn muted(&mut self) -> std::io::Result<bool> {
+        Ok(self.target()?.muted)
+    }
+
+    fn set_mute(&mut self, muted: bool) -> std::io::Result<()> {
+        let info = self.target()?;
+        self.connection
+            .request(protocol::COMMAND_SET_SINK_MUTE, |w| {
+                w.u32(info.index).string(None).bool(muted);
+            })
+            .map(|_| ())
+    }
+}
+
+/// Watches the server for the sink a [`PulseMixer`] controls becoming a different sink
+/// (the default changed) or appearing again (like an HDMI sink once the TV is on).
+pub struct SinkWatcher<T> {
+    connection: Connection<T>,
+    sink: Option<String>,
+    default: Option<String>,
+}
+
+impl<T: Read + Write> SinkWatcher<T> {
+    /// Subscribes to the events that matter for `sink`, which is `None` to follow the default.
+    pub fn new(mut connection: Connection<T>, sink: Option<String>) -> std::io::Result<Self> {
+        let mask = match sink {
+            Some(_) => protocol::SUBSCRIPTION_MASK_SINK,
+            None => protocol::SUBSCRIPTION_MASK_SERVER,
+        };
+        connection.request(protocol::COMMAND_SUBSCRIBE, |w| {
+            w.u32(mask);
+        })?;
+        let default = default_sink(&mut connection)?;
+        Ok(Self {
+            connection,
+            sink,
+            default,
+        })
+    }
+
+    /// Calls `on_change` with the sink's name every time it changes. Only returns on errors.
+    pub fn run(mut self, mut on_change: impl FnMut(String)) -> std::io::Result<()> {
+        loop {
+            let event = self.connection.next_event()?;
+            match &self.sink {
+                None if event.facility() == protocol::EVENT_SERVER => {
+                    let default = default_sink(&mut self.connection)?;
+                    if default != self.default {
+                        self.default = default.clone();
+                        if let Some(name) = default {
+                            on_change(name);
+                        }
+                    }
+                }
+                Some(sink)
+                    if event.facility() == protocol::EVENT_SINK
+                        && event.event_type() == protocol::EVENT_NEW =>
+                {
+                    let info = sink_info(&mut self.connection, event.index, None)?;
+                    if &info.name == sink {
+                        on_change(info.name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex, mpsc},
+        time::Duration,
+    };
+
+    use super::*;
+    use protocol::{TagWriter, read_packet, write_packet};
+
+    /// Subscription mask and the connection to send events on
+    type Subscriber = (u32, Arc<Mutex<UnixStream>>);
+
+    /// A sound server with a few sinks, serving clients on threads.
+    #[derive(Clone, Default)]
+    struct FakeServer {
+        state: Arc<Mutex<State>>,
+        subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        default_sink: Option<String>,
+        /// Name, channel volumes and mute; the index is the position
+        sinks: Vec<(String, Vec<u32>, bool)>,
+    }
+
+    impl FakeServer {
+        fn new(sinks: &[&str], default_sink: &str) -> Self {
+            let server = Self::default();
+            let mut state = server.state.lock().unwrap();
+            state.default_sink = Some(default_sink.to_string());
+            state.sinks = sinks
+                .iter()
+                .map(|name| (name.to_string(), vec![protocol::VOLUME_NORM / 2; 2], false))
+                .collect();
+            drop(state);
+            server
+        }
+
+        fn connect(&self) -> Connection<UnixStream> {
+            let (client, server) = UnixStream::pair().unwrap();
+            let this = self.clone();
+            std::thread::spawn(move || this.serve(server));
+            Connection::new(client, None, "test").unwrap()
+        }
+
+        fn volumes(&self, sink: &str) -> Vec<u32> {
+            let state = self.state.lock().unwrap();
+            state.sinks.iter().find(|s| s.0 == sink).unwrap().1.clone()
+        }
+
+        fn set_default(&self, sink: &str) {
+            self.state.lock().unwrap().default_sink = Some(sink.to_string());
+            self.notify(protocol::EVENT_SERVER | protocol::EVENT_CHANGE, u32::MAX);
+        }
+
+        fn add_sink(&self, sink: &str) {
+            let mut state = self.state.lock().unwrap();
+            state.sinks.push((sink.to_string(), vec![0; 2], false));
+            let index = state.sinks.len() as u32 - 1;
+            drop(state);
+            self.notify(protocol::EVENT_SINK | protocol::EVENT_NEW, index);
+        }
+
+        fn notify(&self, kind: u32, index: u32) {
+            for (mask, writer) in self.subscribers.lock().unwrap().iter() {
+                let facility = 1 << (kind & protocol::EVENT_FACILITY_MASK);
+                if mask & facility != 0 {
+                    let mut packet = TagWriter::default();
+                    packet
+                        .u32(protocol::COMMAND_SUBSCRIBE_EVENT)
+                        .u32(u32::MAX)
+                        .u32(kind)
+                        .u32(index);
+                    write_packet(&mut *writer.lock().unwrap(), &packet.into_bytes()).unwrap();
+                }
+            }
+        }
+
+        fn serve(&self, mut stream: UnixStream) {
+            let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+            while let Ok(packet) = read_packet(&mut stream) {
+                let mut request = TagReader::new(packet);
+                let command = request.u32().unwrap();
+                let tag = request.u32().unwrap();
+                let mut reply = TagWriter::default();
+                reply.u32(protocol::COMMAND_REPLY).u32(tag);
+                let mut state = self.state.lock().unwrap();
+                match command {
+                    protocol::COMMAND_AUTH => {
+                        reply.u32(protocol::PROTOCOL_VERSION);
+                    }
+                    protocol::COMMAND_SET_CLIENT_NAME => {
+                        reply.u32(0);
+                    }
+                    protocol::COMMAND_GET_SERVER_INFO => {
+                        reply
+                            .string(Some("pulseaudio"))
+                            .string(Some("16.1"))
+                            .string(Some("user"))
+                            .string(Some("host"))
+                            .sample_spec(3, 2, 48000)
+                            .string(state.default_sink.as_deref())
+                            .string(None);
+                    }
+                    protocol::COMMAND_GET_SINK_INFO
+                    | protocol::COMMAND_SET_SINK_VOLUME
+                    | protocol::COMMAND_SET_SINK_MUTE => {
+                        let index = request.u32().unwrap();
+                        let name = request.string().unwrap();
+                        let found = if index == protocol::INVALID_INDEX {
+                            let name = name.unwrap();
+                            state.sinks.iter().position(|(sink, ..)| *sink == name)
+                        } else {
+                            Some(index as usize).filter(|&i| i < state.sinks.len())
+                        };
+                        let Some(found) = found else {
+                            reply = TagWriter::default();
+                            reply.u32(protocol::COMMAND_ERROR).u32(tag).u32(5);
+                            write_packet(&mut *writer.lock().unwrap(), &reply.into_bytes())
+                                .unwrap();
+                            continue;
+                        };
+                        let (sink, volumes, muted) = &mut state.sinks[found];
+                        if command == protocol::COMMAND_SET_SINK_VOLUME {
+                            let new = request.cvolume().unwrap();
+                            assert_eq!(new.len(), volumes.len());
+                            *volumes = new;
+                        } else if command == protocol::COMMAND_SET_SINK_MUTE {
+                            *muted = request.bool().unwrap();
+                        } else {
+                            reply
+                                .u32(found as u32)
+                                .string(Some(sink))
+                                .string(Some("A sink"))
+                                .sample_spec(3, 2, 48000)
+                                .channel_map(&[1, 2])
+                                .u32(0)
+                                .cvolume(volumes)
+                                .bool(*muted);
+                        }
+                    }
+                    protocol::COMMAND_SUBSCRIBE => {
+                        let mask = request.u32().unwrap();
+                        self.subscribers
+                            .lock()
+                            .unwrap()
+                            .push((mask, writer.clone()));
+                    }
+                    other => panic!("unexpected command {other}"),
+                }
+                drop(state);
+                write_packet(&mut *writer.lock().unwrap(), &reply.into_bytes()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn sets_volume_of_default_sink() {
+        let server = FakeServer::new(&["speakers", "hdmi"], "speakers");
+        let mut mixer = PulseMixer::new(server.connect(), None);
+        assert_eq!(mixer.volume().unwrap(), 50);
+        assert_eq!(mixer.name(), "pulse:speakers");
+
+        super::super::set_and_verify(&mut mixer, 60).unwrap();
+        assert_eq!(server.volumes("speakers"), [percent_to_volume(60); 2]);
+        assert_eq!(server.volumes("hdmi"), [protocol::VOLUME_NORM / 2; 2]);
+
+        // The next call goes to the new default
+        server.set_default("hdmi");
+        mixer.set_volume(0).unwrap();
+        assert_eq!(server.volumes("hdmi"), [0; 2]);
+        assert_eq!(mixer.name(), "pulse:hdmi");
+    }
+
+    #[test]
+    fn sets_volume_of_configured_sink() {
+        let server = FakeServer::new(&["speakers", "hdmi"], "speakers");
+        let mut mixer = PulseMixer::new(server.connect(), Some("hdmi".to_string()));
+        mixer.set_volume(100).unwrap();
+        assert_eq!(server.volumes("hdmi"), [protocol::VOLUME_NORM; 2]);
+        assert_eq!(server.volumes("speakers"), [protocol::VOLUME_NORM / 2; 2]);
+
+        mixer.set_mute(true).unwrap();
+        assert!(mixer.muted().unwrap());
+        assert_eq!(server.volumes("hdmi"), [protocol::VOLUME_NORM; 2]);
+
+        let mut missing = PulseMixer::new(server.connect(), Some("usb".to_string()));
+        let error = missing.volume().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    fn watch(server: &FakeServer, sink: Option<&str>) -> mpsc::Receiver<String> {
+        let watcher = SinkWatcher::new(server.connect(), sink.map(str::to_string)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || watcher.run(|name| tx.send(name).unwrap()));
+        rx
+    }
+
+    #[test]
+    fn watcher_follows_default_sink() {
+        let server = FakeServer::new(&["speakers", "hdmi"], "speakers");
+        let changes = watch(&server, None);
+
+        server.set_default("speakers");
+        server.set_default("hdmi");
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "hdmi"
+        );
+        assert!(changes.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn watcher_notices_configured_sink_appearing() {
+        let server = FakeServer::new(&["speakers"], "speakers");
+        let changes = watch(&server, Some("hdmi"));
+
+        server.add_sink("usb");
+        server.add_sink("hdmi");
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "hdmi"
+        );
+        assert!(changes.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+//! Just enough of PulseAudio's native protocol to read and set sink volumes.
+//! PipeWire's pipewire-pulse speaks the same protocol on the same socket.
+//!
+//! Every packet starts with a 20-byte descriptor (length, channel and three fields only used by
+//! audio data), followed by a "tagstruct": values that are each prefixed with a one-byte type tag.
+//! Commands are numbered as in pulsecore/native-common.h.
+//! Both directions are implemented, so that tests can play the server;
+//! what only the server needs is built for tests alone.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+
+/// We do not negotiate shared memory, so the version is sent without the SHM/memfd flag bits.
+pub const PROTOCOL_VERSION: u32 = 32;
+const VERSION_MASK: u32 = 0x0000_ffff;
+const DESCRIPTOR_SIZE: usize = 20;
+/// Channel of control packets (as opposed to audio data)
+const CONTROL_CHANNEL: u32 = u32::MAX;
+/// No sane control packet comes close to this.
+const MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+const COOKIE_SIZE: usize = 256;
+
+pub const COMMAND_ERROR: u32 = 0;
+pub const COMMAND_REPLY: u32 = 2;
+pub const COMMAND_AUTH: u32 = 8;
+pub const COMMAND_SET_CLIENT_NAME: u32 = 9;
+pub const COMMAND_GET_SERVER_INFO: u32 = 20;
+pub const COMMAND_GET_SINK_INFO: u32 = 21;
+pub const COMMAND_SUBSCRIBE: u32 = 35;
+pub const COMMAND_SET_SINK_VOLUME: u32 = 36;
+pub const COMMAND_SET_SINK_MUTE: u32 = 39;
+pub const COMMAND_SUBSCRIBE_EVENT: u32 = 66;
+
+/// Index meaning "look it up by name instead".
+pub const INVALID_INDEX: u32 = u32::MAX;
+/// 100% volume
+pub const VOLUME_NORM: u32 = 0x10000;
+
+pub const SUBSCRIPTION_MASK_SINK: u32 = 0x0001;
+pub const SUBSCRIPTION_MASK_SERVER: u32 = 0x0080;
+pub const EVENT_FACILITY_MASK: u32 = 0x000f;
+pub const EVENT_SINK: u32 = 0x0000;
+pub const EVENT_SERVER: u32 = 0x0007;
+pub const EVENT_TYPE_MASK: u32 = 0x0030;
+pub const EVENT_NEW: u32 = 0x0000;
+#[cfg(test)]
+pub const EVENT_CHANGE: u32 = 0x0010;
+
+/// Error codes from pulse/def.h that are worth telling apart.
+const ERROR_ACCESS: u32 = 1;
+const ERROR_NOENTITY: u32 = 5;
+
+const TAG_STRING: u8 = b't';
+const TAG_STRING_NULL: u8 = b'N';
+const TAG_U32: u8 = b'L';
+const TAG_SAMPLE_SPEC: u8 = b'a';
+const TAG_ARBITRARY: u8 = b'x';
+const TAG_BOOLEAN_TRUE: u8 = b'1';
+const TAG_BOOLEAN_FALSE: u8 = b'0';
+const TAG_CHANNEL_MAP: u8 = b'm';
+const TAG_CVOLUME: u8 = b'v';
+const TAG_PROPLIST: u8 = b'P';
+
+/// Builds the payload of a packet.
+#[derive(Default)]
+pub struct TagWriter(Vec<u8>);
+
+impl TagWriter {
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.push(TAG_U32);
+        self.0.extend(value.to_be_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: Option<&str>) -> &mut Self {
+        match value {
+            Some(value) => {
+                self.0.push(TAG_STRING);
+                self.0.extend(value.as_bytes());
+                self.0.push(0);
+            }
+            None => self.0.push(TAG_STRING_NULL),
+        }
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.0.push(if value {
+            TAG_BOOLEAN_TRUE
+        } else {
+            TAG_BOOLEAN_FALSE
+        });
+        self
+    }
+
+    pub fn arbitrary(&mut self, value: &[u8]) -> &mut Self {
+        self.0.push(TAG_ARBITRARY);
+        self.0.extend((value.len() as u32).to_be_bytes());
+        self.0.extend(value);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn sample_spec(&mut self, format: u8, channels: u8, rate: u32) -> &mut Self {
+        self.0.extend([TAG_SAMPLE_SPEC, format, channels]);
+        self.0.extend(rate.to_be_bytes());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn channel_map(&mut self, positions: &[u8]) -> &mut Self {
+        self.0.extend([TAG_CHANNEL_MAP, positions.len() as u8]);
+        self.0.extend(positions);
+        self
+    }
+
+    pub fn cvolume(&mut self, volumes: &[u32]) -> &mut Self {
+        self.0.extend([TAG_CVOLUME, volumes.len() as u8]);
+        for volume in volumes {
+            self.0.extend(volume.to_be_bytes());
+        }
+        self
+    }
+
+    /// A property list: each value is a NUL-terminated string stored as arbitrary data.
+    pub fn proplist(&mut self, properties: &[(&str, &str)]) -> &mut Self {
+        self.0.push(TAG_PROPLIST);
+        for (key, value) in properties {
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            self.string(Some(key))
+                .u32(data.len() as u32)
+                .arbitrary(&data);
+        }
+        self.string(None)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Reads the payload of a packet, in the order the values were written.
+pub struct TagReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl TagReader {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(invalid("packet ends early".to_string()));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn raw_u32(&mut self) -> std::io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().expect("took 4 bytes")))
+    }
+
+    fn tag(&mut self, expected: u8) -> std::io::Result<()> {
+        let tag = self.byte()?;
+        if tag != expected {
+            return Err(invalid(format!(
+                "expected tag {:?}, got {:?}",
+                expected as char, tag as char
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn u32(&mut self) -> std::io::Result<u32> {
+        self.tag(TAG_U32)?;
+        self.raw_u32()
+    }
+
+    pub fn string(&mut self) -> std::io::Result<Option<String>> {
+        match self.byte()? {
+            TAG_STRING_NULL => Ok(None),
+            TAG_STRING => {
+                let rest = &self.data[self.pos..];
+                let end = rest
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or_else(|| invalid("unterminated string".to_string()))?;
+                let value = String::from_utf8_lossy(&rest[..end]).into_owned();
+                self.pos += end + 1;
+                Ok(Some(value))
+            }
+            tag => Err(invalid(format!("expected a string, got {:?}", tag as char))),
+        }
+    }
+
+    pub fn bool(&mut self) -> std::io::Result<bool> {
+        match self.byte()? {
+            TAG_BOOLEAN_TRUE => Ok(true),
+            TAG_BOOLEAN_FALSE => Ok(false),
+            tag => Err(invalid(format!(
+                "expected a boolean, got {:?}",
+                tag as char
+            ))),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn arbitrary(&mut self) -> std::io::Result<Vec<u8>> {
+        self.tag(TAG_ARBITRARY)?;
+        let len = self.raw_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Returns (format, channels, rate).
+    pub fn sample_spec(&mut self) -> std::io::Result<(u8, u8, u32)> {
+        self.tag(TAG_SAMPLE_SPEC)?;
+        let format = self.byte()?;
+        let channels = self.byte()?;
+        Ok((format, channels, self.raw_u32()?))
+    }
+
+    pub fn channel_map(&mut self) -> std::io::Result<Vec<u8>> {
+        self.tag(TAG_CHANNEL_MAP)?;
+        let channels = self.byte()? as usize;
+        Ok(self.take(channels)?.to_vec())
+    }
+
+    pub fn cvolume(&mut self) -> std::io::Result<Vec<u32>> {
+        self.tag(TAG_CVOLUME)?;
+        let channels = self.byte()?;
+        (0..channels).map(|_| self.raw_u32()).collect()
+    }
+
+    #[cfg(test)]
+    pub fn proplist(&mut self) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        self.tag(TAG_PROPLIST)?;
+        let mut properties = Vec::new();
+        while let Some(key) = self.string()? {
+            let len = self.u32()?;
+            let value = self.arbitrary()?;
+            if value.len() != len as usize {
+                return Err(invalid(format!("property {key} has the wrong length")));
+            }
+            properties.push((key, value));
+        }
+        Ok(properties)
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+pub fn write_packet(stream: &mut impl Write, payload: &[u8]) -> std::io::Result<()> {
+    let mut packet = Vec::with_capacity(DESCRIPTOR_SIZE + payload.len());
+    packet.extend((payload.len() as u32).to_be_bytes());
+    packet.extend(CONTROL_CHANNEL.to_be_bytes());
+    // offset (high and low) and flags are only used for audio data
+    packet.extend([0; 12]);
+    packet.extend(payload);
+    stream.write_all(&packet)
+}
+
+pub fn read_packet(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut descriptor = [0u8; DESCRIPTOR_SIZE];
+    stream.read_exact(&mut descriptor)?;
+    let len = u32::from_be_bytes(descriptor[..4].try_into().expect("4 bytes")) as usize;
+    let channel = u32::from_be_bytes(descriptor[4..8].try_into().expect("4 bytes"));
+    if len > MAX_PACKET_SIZE {
+        return Err(invalid(format!("packet of {len} bytes is too large")));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    if channel != CONTROL_CHANNEL {
+        return Err(invalid(format!(
+            "unexpected audio data on channel {channel}"
+        )));
+    }
+    Ok(payload)
+}
+
+/// A subscription event: what happened (facility and type bits) and the index of the object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub kind: u32,
+    pub index: u32,
+}
+
+impl Event {
+    pub fn facility(self) -> u32 {
+        self.kind & EVENT_FACILITY_MASK
+    }
+
+    pub fn event_type(self) -> u32 {
+        self.kind & EVENT_TYPE_MASK
+    }
+}
+
+/// An authenticated connection to the sound server.
+pub struct Connection<T> {
+    stream: T,
+    next_tag: u32,
+    /// Subscription events that arrived while waiting for a reply
+    events: VecDeque<Event>,
+}
+
+impl<T: Read + Write> Connection<T> {
+    /// Authenticates and introduces the client by name.
+    /// PipeWire ignores the cookie, and PulseAudio accepts any for clients of the same user.
+    pub fn new(stream: T, cookie: Option<&[u8]>, client_name: &str) -> std::io::Result<Self> {
+        let mut connection = Self {
+            stream,
+            next_tag: 0,
+            events: VecDeque::new(),
+        };
+        let cookie = cookie.unwrap_or(&[0; COOKIE_SIZE]);
+        let mut reply = connection.request(COMMAND_AUTH, |w| {
+            w.u32(PROTOCOL_VERSION).arbitrary(cookie);
+        })?;
+        let server_version = reply.u32()? & VERSION_MASK;
+        if server_version < 13 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("sound server speaks protocol version {server_version}, need at least 13"),
+            ));
+        }
+        connection.request(COMMAND_SET_CLIENT_NAME, |w| {
+            w.proplist(&[("application.name", client_name)]);
+        })?;
+        Ok(connection)
+    }
+
+    /// Sends a command and waits for its reply, which is returned without the command and tag.
+    pub fn request(
+        &mut self,
+        command: u32,
+        arguments: impl FnOnce(&mut TagWriter),
+    ) -> std::io::Result<TagReader> {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        let mut writer = TagWriter::default();
+        writer.u32(command).u32(tag);
+        arguments(&mut writer);
+        write_packet(&mut self.stream, &writer.into_bytes())?;
+
+        loop {
+            let mut reader = TagReader::new(read_packet(&mut self.stream)?);
+            let reply_command = reader.u32()?;
+            let reply_tag = reader.u32()?;
+            match reply_command {
+                COMMAND_SUBSCRIBE_EVENT => self.events.push_back(read_event(&mut reader)?),
+                COMMAND_REPLY if reply_tag == tag => return Ok(reader),
+                COMMAND_ERROR if reply_tag == tag => return Err(server_error(reader.u32()?)),
+                // Anything else (like stream notifications) is not for us
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits for the next subscription event.
+    pub fn next_event(&mut self) -> std::io::Result<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let mut reader = TagReader::new(read_packet(&mut self.stream)?);
+            if reader.u32()? == COMMAND_SUBSCRIBE_EVENT {
+                reader.u32()?;
+                return read_event(&mut reader);
+            }
+        }
+    }
+}
+
+fn read_event(reader: &mut TagReader) -> std::io::Result<Event> {
+    Ok(Event {
+        kind: reader.u32()?,
+        index: reader.u32()?,
+    })
+}
+
+fn server_error(code: u32) -> std::io::Error {
+    let kind = match code {
+        ERROR_ACCESS => std::io::ErrorKind::PermissionDenied,
+        ERROR_NOENTITY => std::io::ErrorKind::NotFound,
+        _ => std::io::ErrorKind::Other,
+    };
+    std::io::Error::new(kind, format!("sound server returned error {code}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagstruct_round_trips() {
+        let mut writer = TagWriter::default();
+        writer
+            .u32(7)
+            .string(Some("hdmi"))
+            .string(None)
+            .bool(true)
+            .sample_spec(3, 2, 48000)
+            .channel_map(&[1, 2])
+            .cvolume(&[VOLUME_NORM, 0])
+            .proplist(&[("application.name", "volume-control")]);
+
+        let mut reader = TagReader::new(writer.into_bytes());
+        assert_eq!(reader.u32().unwrap(), 7);
+        assert_eq!(reader.string().unwrap().as_deref(), Some("hdmi"));
+        assert_eq!(reader.string().unwrap(), None);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.sample_spec().unwrap(), (3, 2, 48000));
+        assert_eq!(reader.channel_map().unwrap(), [1, 2]);
+        assert_eq!(reader.cvolume().unwrap(), [VOLUME_NORM, 0]);
+        assert_eq!(
+            reader.proplist().unwrap(),
+            [("application.name".to_string(), b"volume-control\0".to_vec())]
+        );
+        assert!(reader.u32().is_err());
+    }
+
+    #[test]
+    fn wrong_tag_is_an_error() {
+        let mut writer = TagWriter::default();
+        writer.string(Some("not a number"));
+        assert!(TagReader::new(writer.into_bytes()).u32().is_err());
+    }
+}
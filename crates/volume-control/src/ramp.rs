@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+/// A gradual change of volume, in percent.
+#[derive(Clone, Copy, Debug)]
+pub struct Ramp {
+    pub from: u8,
+    pub to: u8,
+    pub duration: Duration,
+}
+
+impl Ramp {
+    /// Volume at the given progress (`0.0..=1.0`) of the ramp.
+    pub fn volume_at(&self, progress: f64) -> u8 {
+        let progress = progress.clamp(0.0, 1.0);
+        let from = self.from as f64;
+        let to = self.to as f64;
+        (from + (to - from) * progress).round() as u8
+    }
+
+    /// The volumes to write, one per `step`, ending exactly at `to`.
+    pub fn steps(&self, step: Duration) -> impl Iterator<Item = u8> {
+        let count = if step.is_zero() {
+            1
+        } else {
+            (self.duration.as_secs_f64() / step.as_secs_f64())
+                .ceil()
+                .max(1.0) as u32
+        };
+        let ramp = *self;
+        (1..=count).map(move |i| ramp.volume_at(i as f64 / count as f64))
+    }
+}
@@ -0,0 +1,89 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::NaiveTime;
+use utils::time_of_day;
+
+/// Upper limits on the volume by local time of day. Each slot starts at its time
+/// and lasts until the next one begins, wrapping around midnight.
+#[derive(Clone, Debug)]
+pub struct VolumeCaps {
+    slots: Vec<(NaiveTime, u8)>,
+}
+
+impl VolumeCaps {
+    /// Index of the slot that is in effect at `time`.
+    fn slot_at(&self, time: NaiveTime) -> usize {
+        // The last slot starting at or before now, or yesterday's last one
+        self.slots
+            .iter()
+            .rposition(|(start, _)| *start <= time)
+            .unwrap_or(self.slots.len() - 1)
+    }
+
+    pub fn cap_at(&self, time: NaiveTime) -> u8 {
+        self.slots[self.slot_at(time)].1
+    }
+
+    /// How long until the next slot begins.
+    pub fn until_next_slot(&self, time: NaiveTime) -> Duration {
+        // With a single slot, that is the same one a day later
+        let next = (self.slot_at(time) + 1) % self.slots.len();
+        time_of_day::until(time, self.slots[next].0)
+    }
+}
+
+impl FromStr for VolumeCaps {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let slots = time_of_day::parse_percentages(s)?;
+        if slots.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err("two slots start at the same time".to_string());
+        }
+        Ok(Self { slots })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn caps_by_slot() {
+        let caps: VolumeCaps = "22:00=35, 07:00=60".parse().unwrap();
+        assert_eq!(caps.cap_at(at("12:00:00")), 60);
+        assert_eq!(caps.cap_at(at("07:00:00")), 60);
+        assert_eq!(caps.cap_at(at("21:59:59")), 60);
+        assert_eq!(caps.cap_at(at("22:00:00")), 35);
+        // Wraps around midnight
+        assert_eq!(caps.cap_at(at("03:00:00")), 35);
+    }
+
+    #[test]
+    fn time_until_next_slot() {
+        let caps: VolumeCaps = "07:00=60,22:00=35".parse().unwrap();
+        let secs = |time| caps.until_next_slot(at(time)).as_secs();
+        assert_eq!(secs("21:00:00"), 60 * 60);
+        assert_eq!(secs("22:00:00"), 9 * 60 * 60);
+        assert_eq!(secs("23:00:00"), 8 * 60 * 60);
+
+        let single: VolumeCaps = "00:00=50".parse().unwrap();
+        assert_eq!(
+            single.until_next_slot(at("12:00:00")).as_secs(),
+            12 * 60 * 60
+        );
+    }
+
+    #[test]
+    fn rejects_bad_schedules() {
+        assert!("".parse::<VolumeCaps>().is_err());
+        assert!("22:00".parse::<VolumeCaps>().is_err());
+        assert!("25:00=10".parse::<VolumeCaps>().is_err());
+        assert!("22:00=101".parse::<VolumeCaps>().is_err());
+        assert!("22:00=10,22:00=20".parse::<VolumeCaps>().is_err());
+    }
+}
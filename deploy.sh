@@ -20,4 +20,6 @@ for bin in $BINARIES; do
     rsync -avz --progress "target/release/$bin" "$HOST:$DEPLOY_PATH"
 done
 
+rsync -avz --progress overseer.toml "$HOST:$DEPLOY_PATH"
+
 echo "Deployment complete!"
\ No newline at end of file